@@ -0,0 +1,229 @@
+use std::collections::BTreeSet;
+use std::ffi::{c_char, c_void, CStr};
+
+type CMIOObjectID = u32;
+type OSStatus = i32;
+type CFStringRef = *const c_void;
+
+/// Identifies a property of a CoreMediaIO object.
+#[repr(C)]
+struct CMIOObjectPropertyAddress {
+    selector: u32,
+    scope: u32,
+    element: u32,
+}
+
+#[link(name = "CoreMediaIO", kind = "framework")]
+extern "C" {
+    fn CMIOObjectGetPropertyDataSize(
+        object_id: CMIOObjectID,
+        address: *const CMIOObjectPropertyAddress,
+        qualifier_data_size: u32,
+        qualifier_data: *const c_void,
+        data_size: *mut u32,
+    ) -> OSStatus;
+
+    fn CMIOObjectGetPropertyData(
+        object_id: CMIOObjectID,
+        address: *const CMIOObjectPropertyAddress,
+        qualifier_data_size: u32,
+        qualifier_data: *const c_void,
+        data_size: u32,
+        data_used: *mut u32,
+        data: *mut c_void,
+    ) -> OSStatus;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFStringGetCString(
+        string: CFStringRef,
+        buffer: *mut c_char,
+        buffer_size: isize,
+        encoding: u32,
+    ) -> u8;
+
+    fn CFRelease(object: *const c_void);
+}
+
+/// `kCFStringEncodingUTF8`
+const CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
+const fn four_char_code(code: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*code)
+}
+
+/// `kCMIOObjectSystemObject`, which owns the list of devices
+const SYSTEM_OBJECT: CMIOObjectID = 1;
+/// `kCMIOHardwarePropertyDevices`
+const HARDWARE_PROPERTY_DEVICES: u32 = four_char_code(b"dev#");
+/// `kCMIODevicePropertyDeviceIsRunningSomewhere`
+const DEVICE_PROPERTY_IS_RUNNING_SOMEWHERE: u32 = four_char_code(b"gone");
+/// `kCMIOObjectPropertyName`
+const OBJECT_PROPERTY_NAME: u32 = four_char_code(b"lnam");
+/// `kCMIODevicePropertyDeviceUID`
+const DEVICE_PROPERTY_UID: u32 = four_char_code(b"uid ");
+/// `kCMIOObjectPropertyScopeGlobal`
+const SCOPE_GLOBAL: u32 = four_char_code(b"glob");
+/// `kCMIOObjectPropertyElementMain`
+const ELEMENT_MAIN: u32 = 0;
+
+fn global_property(selector: u32) -> CMIOObjectPropertyAddress {
+    CMIOObjectPropertyAddress {
+        selector,
+        scope: SCOPE_GLOBAL,
+        element: ELEMENT_MAIN,
+    }
+}
+
+fn check(status: OSStatus, action: &str) -> std::io::Result<()> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "CoreMediaIO failed to {} (error {})",
+            action, status
+        )))
+    }
+}
+
+/// Lists the IDs of the video devices CoreMediaIO knows about.
+fn get_devices() -> std::io::Result<Vec<CMIOObjectID>> {
+    let address = global_property(HARDWARE_PROPERTY_DEVICES);
+    let mut data_size = 0;
+    // SAFETY: `address` and `data_size` are valid for the duration of the call
+    check(
+        unsafe {
+            CMIOObjectGetPropertyDataSize(
+                SYSTEM_OBJECT,
+                &address,
+                0,
+                std::ptr::null(),
+                &mut data_size,
+            )
+        },
+        "list video devices",
+    )?;
+
+    let mut devices: Vec<CMIOObjectID> =
+        vec![0; data_size as usize / std::mem::size_of::<CMIOObjectID>()];
+    let mut data_used = 0;
+    // SAFETY: `devices` has room for `data_size` bytes, and CoreMediaIO writes no more than that
+    check(
+        unsafe {
+            CMIOObjectGetPropertyData(
+                SYSTEM_OBJECT,
+                &address,
+                0,
+                std::ptr::null(),
+                data_size,
+                &mut data_used,
+                devices.as_mut_ptr().cast(),
+            )
+        },
+        "list video devices",
+    )?;
+
+    // Devices may have been removed since we asked how many there were
+    devices.truncate(data_used as usize / std::mem::size_of::<CMIOObjectID>());
+    Ok(devices)
+}
+
+/// Whether any process is using `device`.
+fn is_running_somewhere(device: CMIOObjectID) -> std::io::Result<bool> {
+    let address = global_property(DEVICE_PROPERTY_IS_RUNNING_SOMEWHERE);
+    let mut running: u32 = 0;
+    let mut data_used = 0;
+    // SAFETY: `running` is a `UInt32`, which is what CoreMediaIO writes for this property
+    check(
+        unsafe {
+            CMIOObjectGetPropertyData(
+                device,
+                &address,
+                0,
+                std::ptr::null(),
+                std::mem::size_of::<u32>() as u32,
+                &mut data_used,
+                (&mut running as *mut u32).cast(),
+            )
+        },
+        "check whether a video device is running",
+    )?;
+
+    Ok(running != 0)
+}
+
+/// Reads a string property of `device`, e.g. its name.
+fn get_string_property(device: CMIOObjectID, selector: u32) -> Option<String> {
+    let address = global_property(selector);
+    let mut string: CFStringRef = std::ptr::null();
+    let mut data_used = 0;
+    // SAFETY: `string` is a `CFStringRef`, which is what CoreMediaIO writes for string properties
+    let status = unsafe {
+        CMIOObjectGetPropertyData(
+            device,
+            &address,
+            0,
+            std::ptr::null(),
+            std::mem::size_of::<CFStringRef>() as u32,
+            &mut data_used,
+            (&mut string as *mut CFStringRef).cast(),
+        )
+    };
+    if status != 0 || string.is_null() {
+        return None;
+    }
+
+    let mut buffer = [0 as c_char; 1024];
+    // SAFETY: `buffer` is valid for its length, and we own `string`, so must release it
+    let converted = unsafe {
+        let converted = CFStringGetCString(
+            string,
+            buffer.as_mut_ptr(),
+            buffer.len() as isize,
+            CF_STRING_ENCODING_UTF8,
+        );
+        CFRelease(string);
+        converted
+    };
+    if converted == 0 {
+        return None;
+    }
+
+    // SAFETY: `CFStringGetCString` succeeded, so `buffer` holds a null-terminated string
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_str()
+        .ok()
+        .map(str::to_string)
+}
+
+/// A video device known to CoreMediaIO.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Camera {
+    /// The name of the device, e.g. `FaceTime HD Camera`
+    pub name: String,
+    /// The unique ID of the device, which stays the same when it is plugged back in
+    pub unique_id: String,
+}
+
+impl Camera {
+    /// Whether `name` is this camera's name or unique ID, ignoring case.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.unique_id.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Returns the video devices that are currently in use by any process. Devices that are removed
+/// while we're checking them are skipped.
+pub fn get_running_cameras() -> std::io::Result<BTreeSet<Camera>> {
+    Ok(get_devices()?
+        .into_iter()
+        .filter(|device| is_running_somewhere(*device).unwrap_or(false))
+        .map(|device| Camera {
+            name: get_string_property(device, OBJECT_PROPERTY_NAME)
+                .unwrap_or_else(|| format!("CoreMediaIO device {}", device)),
+            unique_id: get_string_property(device, DEVICE_PROPERTY_UID)
+                .unwrap_or_else(|| device.to_string()),
+        })
+        .collect())
+}
@@ -0,0 +1,744 @@
+use crate::desired_state::Trigger;
+use crate::duration::{format_duration, parse_duration};
+use crate::event_source::{EventSourceHealth, EventSourceStatus};
+use crate::logging::{log_error, log_info};
+use crate::overrides::{parse_setting, Override};
+use crate::queue::QueueStatus;
+use crate::reload::reload_profile;
+use crate::worker::DeviceWorker;
+#[cfg(unix)]
+use crate::CliError;
+use crate::{
+    adjust_selected_devices, format_device_status, get_connected_devices_info,
+    set_selected_devices_on_and_log, set_selected_devices_on_and_log_if_decided,
+    turn_off_selected_devices_and_log, turn_on_selected_devices_and_log, Adjustment, DeviceContext,
+    DeviceFilter, DeviceStatus, ForcedState, TriggerSource,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// Commands that can be sent to a running listener over its control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ControlCommand {
+    /// Turn the Litra device on
+    On,
+    /// Turn the Litra device off
+    Off,
+    /// Stop toggling the Litra device when webcam events are detected
+    Pause,
+    /// Start toggling the Litra device when webcam events are detected again
+    Resume,
+    /// Stop toggling the Litra device when webcam events are detected for a while, e.g. `15m`,
+    /// then start again by itself
+    Snooze,
+    /// Turn the Litra device on, and keep it on regardless of webcam events for a while
+    ForceOn,
+    /// Turn the Litra device off, and keep it off regardless of webcam events for a while
+    ForceOff,
+    /// Show whether automatic toggling is paused, and the state of the Litra device
+    Status,
+    /// Explain what the listener did about the most recent webcam event, and why
+    ExplainLast,
+    /// Make the Litra device brighter by `--brightness-step`
+    BrightnessUp,
+    /// Make the Litra device dimmer by `--brightness-step`
+    BrightnessDown,
+    /// Make the Litra device's color temperature cooler by `--temperature-step`
+    TemperatureUp,
+    /// Make the Litra device's color temperature warmer by `--temperature-step`
+    TemperatureDown,
+    /// Change a setting until the listener restarts, e.g. `set fade 500ms`, or put it back with
+    /// `default`
+    Set,
+    /// Stop reading from or writing to the Litra device with `maintenance on`, e.g. while
+    /// updating its firmware, and bring it up to date with `maintenance off`
+    Maintenance,
+    /// Reload the profiles file, applying changes to the listener's `--profile` which don't need
+    /// a restart
+    Reload,
+    /// Handle a simulated webcam turning on, sent by `simulate camera-on`
+    #[value(hide = true)]
+    SimulateCameraOn,
+    /// Handle a simulated webcam turning off, sent by `simulate camera-off`
+    #[value(hide = true)]
+    SimulateCameraOff,
+}
+
+/// Webcam events that can be simulated with `simulate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SimulatedEvent {
+    /// The webcam turning on
+    CameraOn,
+    /// The webcam turning off
+    CameraOff,
+}
+
+impl SimulatedEvent {
+    /// The command which asks the listener to handle this event.
+    pub fn control_command(self) -> ControlCommand {
+        match self {
+            SimulatedEvent::CameraOn => ControlCommand::SimulateCameraOn,
+            SimulatedEvent::CameraOff => ControlCommand::SimulateCameraOff,
+        }
+    }
+}
+
+/// The camera that simulated webcam events are reported as coming from, in logs and hooks.
+const SIMULATED_CAMERA: &str = "simulated camera";
+
+/// A state forced with `force-on` or `force-off`, and how long is left until it expires.
+#[derive(Debug, Serialize)]
+pub struct ForcedStatus {
+    on: bool,
+    seconds_remaining: u64,
+}
+
+/// Whether automatic toggling is paused or overridden, the health of the event source, the
+/// queues of work waiting to be done and the state of the connected Litra devices.
+#[derive(Debug, Serialize)]
+pub struct ListenerStatus {
+    maintenance: bool,
+    paused: bool,
+    /// How long is left until automatic toggling resumes, if it was paused with `snooze`
+    snoozed_seconds_remaining: Option<u64>,
+    forced: Option<ForcedStatus>,
+    /// How long webcam events are ignored for after a device was turned on or off by hand, with
+    /// `--manual-override-grace-period`
+    manual_override_seconds_remaining: Option<u64>,
+    /// Settings changed with `ctl set`, which only last until the listener restarts
+    overrides: Vec<Override>,
+    event_source: EventSourceStatus,
+    queues: Vec<QueueStatus>,
+    devices: Vec<DeviceStatus>,
+}
+
+/// The longest request we'll read from a client. Requests are a command name and an optional
+/// duration or setting, so anything longer than this is a misbehaving client.
+#[cfg(unix)]
+const MAX_REQUEST_LENGTH: u64 = 1024;
+
+/// How long we'll wait for a client to send its request before giving up on it, so a client that
+/// connects and then does nothing can't block other clients.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the default path of the control socket, inside `$XDG_RUNTIME_DIR` if it is set, or
+/// the temporary directory otherwise. Each `--profile` has its own socket.
+#[cfg(unix)]
+pub fn default_control_socket_path(profile: Option<&str>) -> PathBuf {
+    let filename = match profile {
+        Some(profile) => format!("litra-autotoggle-{}.sock", profile),
+        None => "litra-autotoggle.sock".to_string(),
+    };
+
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(filename)
+}
+
+/// Binds the control socket at `path`, replacing a stale socket left behind by a previous run.
+#[cfg(unix)]
+pub fn bind_control_socket(path: &Path) -> Result<UnixListener, CliError> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(CliError::IoError(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!(
+                    "Another listener is already using the control socket at {}",
+                    path.display()
+                ),
+            )));
+        }
+
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Accepts connections on the control socket and handles their commands. Clients are handled
+/// one at a time, and each is limited to a single short request, so a misbehaving client can't
+/// flood the listener with commands.
+#[cfg(unix)]
+pub async fn serve_control_socket(
+    listener: UnixListener,
+    worker: DeviceWorker,
+    event_source: EventSourceHealth,
+    device_filter: DeviceFilter,
+    require_device: bool,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                log_error!("Failed to accept control socket connection: {}", error);
+                continue;
+            }
+        };
+
+        let (reader, mut writer) = stream.into_split();
+        let mut request = String::new();
+        let read_result = tokio::time::timeout(
+            REQUEST_TIMEOUT,
+            BufReader::new(reader.take(MAX_REQUEST_LENGTH)).read_line(&mut request),
+        )
+        .await;
+
+        let response = match read_result {
+            Ok(Ok(_)) => {
+                // Requests are a command name, optionally followed by a duration, or for `set`,
+                // a setting and a value
+                let mut parts = request.split_whitespace();
+                let command = ControlCommand::from_str(parts.next().unwrap_or_default(), true)
+                    .map_err(|_| format!("Unknown command `{}`", request.trim()));
+
+                match command {
+                    Ok(ControlCommand::Set) => {
+                        log_info!("Received `{}` command on control socket", request.trim());
+                        handle_set_command(&worker, parts.next(), parts.next()).await
+                    }
+                    Ok(ControlCommand::Maintenance) => {
+                        log_info!("Received `{}` command on control socket", request.trim());
+                        handle_maintenance_command(
+                            &worker,
+                            parts.next(),
+                            &device_filter,
+                            require_device,
+                        )
+                        .await
+                    }
+                    Ok(command) => match parts.next().map(parse_duration).transpose() {
+                        Ok(duration) => {
+                            log_info!("Received `{}` command on control socket", request.trim());
+                            handle_control_command(
+                                command,
+                                duration,
+                                &worker,
+                                &event_source,
+                                &device_filter,
+                                require_device,
+                                TriggerSource::Manual,
+                            )
+                            .await
+                        }
+                        Err(message) => Err(message),
+                    },
+                    Err(message) => Err(message),
+                }
+            }
+            Ok(Err(error)) => Err(format!("Failed to read command: {}", error)),
+            Err(_) => Err("Timed out waiting for command".to_string()),
+        };
+
+        let response = match response {
+            Ok(message) => format!("ok\n{}", message),
+            Err(message) => format!("error: {}\n", message),
+        };
+
+        if let Err(error) = writer.write_all(response.as_bytes()).await {
+            log_error!("Failed to write control socket response: {}", error);
+        }
+    }
+}
+
+/// Changes `setting` to `value` until the listener restarts, for `ctl set` and `POST /set`,
+/// returning a message to send back to the client.
+pub async fn handle_set_command(
+    worker: &DeviceWorker,
+    setting: Option<&str>,
+    value: Option<&str>,
+) -> Result<String, String> {
+    let (Some(setting), Some(value)) = (setting, value) else {
+        return Err("A setting and a value are required, e.g. `set fade 500ms`".to_string());
+    };
+    let setting = parse_setting(setting)?;
+    let value = value.to_string();
+
+    let message = worker
+        .run(move |context| context.overrides.set(&mut context.options, setting, &value))
+        .await?;
+    log_info!("{}", message);
+
+    Ok(format!("{}\n", message))
+}
+
+/// Turns maintenance mode on or off for `ctl maintenance`, returning a message to send back to
+/// the client. While it is on, webcam events are still detected and logged, but the devices are
+/// left alone, so firmware updates aren't interrupted by our own HID traffic. When it is turned
+/// off, the devices are brought up to date.
+#[cfg(unix)]
+async fn handle_maintenance_command(
+    worker: &DeviceWorker,
+    value: Option<&str>,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+) -> Result<String, String> {
+    let maintenance = match value {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return Err("`on` or `off` is required, e.g. `maintenance on`".to_string()),
+    };
+
+    let device_filter = device_filter.clone();
+    worker
+        .run(move |context| {
+            if context.maintenance == maintenance {
+                return Ok(format!(
+                    "Maintenance mode is already {}\n",
+                    if maintenance { "on" } else { "off" }
+                ));
+            }
+
+            context.maintenance = maintenance;
+            if maintenance {
+                // Close any devices we're holding open, so the firmware updater can have them
+                context.persistent_device_handles.clear();
+                log_info!("Turned on maintenance mode, so Litra devices will be left alone");
+                return Ok("Maintenance mode is on. Litra devices will be left alone until you run `ctl maintenance off`.\n".to_string());
+            }
+
+            log_info!("Turned off maintenance mode, returning to automatic toggling");
+            let trigger = Trigger::MaintenanceEnded {
+                webcam_in_use: context.webcam_in_use,
+            };
+            set_selected_devices_on_and_log_if_decided(
+                context,
+                &device_filter,
+                require_device,
+                None,
+                trigger,
+            )
+            .map(|()| "Maintenance mode is off\n".to_string())
+            .map_err(|error| error.to_string())
+        })
+        .await
+}
+
+/// Handles `command` from a control client, returning a message to send back to the client.
+/// `duration` is how long to force the device on or off for, and is required for `force-on` and
+/// `force-off`. `trigger_source` says where the command came from, for toggle logs.
+pub async fn handle_control_command(
+    command: ControlCommand,
+    duration: Option<Duration>,
+    worker: &DeviceWorker,
+    event_source: &EventSourceHealth,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    trigger_source: TriggerSource,
+) -> Result<String, String> {
+    let device_filter = device_filter.clone();
+
+    match command {
+        // Only the control socket can send a setting and a value
+        ControlCommand::Set => {
+            Err("A setting and a value are required, e.g. `set fade 500ms`".to_string())
+        }
+        ControlCommand::Maintenance => {
+            Err("`on` or `off` is required, e.g. `maintenance on`".to_string())
+        }
+        ControlCommand::Reload => worker.run(reload_profile).await,
+        ControlCommand::On | ControlCommand::Off => worker
+            .run(move |context| {
+                set_selected_devices_on_and_log(
+                    context,
+                    &device_filter,
+                    require_device,
+                    trigger_source,
+                    None,
+                    command == ControlCommand::On,
+                )
+            })
+            .await
+            .map(|_| String::new())
+            .map_err(|error| error.to_string()),
+        ControlCommand::BrightnessUp
+        | ControlCommand::BrightnessDown
+        | ControlCommand::TemperatureUp
+        | ControlCommand::TemperatureDown => {
+            let adjustment = match command {
+                ControlCommand::BrightnessUp => Adjustment::Brightness { up: true },
+                ControlCommand::BrightnessDown => Adjustment::Brightness { up: false },
+                ControlCommand::TemperatureUp => Adjustment::Temperature { up: true },
+                _ => Adjustment::Temperature { up: false },
+            };
+
+            worker
+                .run(move |context| {
+                    adjust_selected_devices(context, &device_filter, require_device, adjustment)
+                })
+                .await
+                .map(|_| String::new())
+                .map_err(|error| error.to_string())
+        }
+        ControlCommand::Pause => {
+            worker
+                .run(|context| {
+                    context.paused = true;
+                    context.snoozed_until = None;
+                })
+                .await;
+            log_info!("Paused automatic toggling");
+            Ok(String::new())
+        }
+        ControlCommand::Resume => {
+            worker
+                .run(|context| {
+                    context.paused = false;
+                    context.snoozed_until = None;
+                })
+                .await;
+            log_info!("Resumed automatic toggling");
+            Ok(String::new())
+        }
+        ControlCommand::Snooze => {
+            let duration = duration.ok_or_else(|| {
+                "A duration is required to snooze automatic toggling, e.g. `15m`".to_string()
+            })?;
+            let until = Instant::now() + duration;
+
+            worker
+                .run(move |context| {
+                    context.paused = true;
+                    context.snoozed_until = Some(until);
+                })
+                .await;
+            log_info!(
+                "Paused automatic toggling for {}",
+                format_duration(duration)
+            );
+
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                worker
+                    .run(move |context| end_snooze(context, until, &device_filter, require_device))
+                    .await;
+            });
+
+            Ok(String::new())
+        }
+        ControlCommand::ForceOn | ControlCommand::ForceOff => {
+            let duration = duration.ok_or_else(|| {
+                "A duration is required to force the Litra device on or off, e.g. `45m`".to_string()
+            })?;
+            let forced = ForcedState {
+                on: command == ControlCommand::ForceOn,
+                until: Instant::now() + duration,
+            };
+
+            let device_filter_clone = device_filter.clone();
+            let result = worker
+                .run(move |context| {
+                    log_info!(
+                        "Forcing Litra device {} for {}",
+                        if forced.on { "on" } else { "off" },
+                        format_duration(duration)
+                    );
+                    context.forced = Some(forced);
+                    set_selected_devices_on_and_log(
+                        context,
+                        &device_filter_clone,
+                        require_device,
+                        trigger_source,
+                        None,
+                        forced.on,
+                    )
+                })
+                .await;
+
+            // Start the timer even if we failed to toggle the device, so the forced state can't
+            // get stuck
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                worker
+                    .run(move |context| {
+                        expire_forced_state(context, forced, &device_filter, require_device)
+                    })
+                    .await;
+            });
+
+            result
+                .map(|_| String::new())
+                .map_err(|error| error.to_string())
+        }
+        ControlCommand::SimulateCameraOn | ControlCommand::SimulateCameraOff => {
+            let on = command == ControlCommand::SimulateCameraOn;
+            log_info!(
+                "Simulating the webcam turning {}",
+                if on { "on" } else { "off" }
+            );
+
+            // Handled like a detected webcam event, so the response explains what was done
+            worker
+                .run(move |context| {
+                    let result = if on {
+                        turn_on_selected_devices_and_log(
+                            context,
+                            &device_filter,
+                            require_device,
+                            Some(SIMULATED_CAMERA),
+                            None,
+                        )
+                    } else {
+                        turn_off_selected_devices_and_log(
+                            context,
+                            &device_filter,
+                            require_device,
+                            Some(SIMULATED_CAMERA),
+                            None,
+                        )
+                    };
+
+                    result.map(|()| {
+                        context
+                            .last_explanation
+                            .as_ref()
+                            .map(|explanation| explanation.describe())
+                            .unwrap_or_default()
+                    })
+                })
+                .await
+                .map_err(|error| error.to_string())
+        }
+        ControlCommand::ExplainLast => Ok(worker
+            .run(|context| {
+                context
+                    .last_explanation
+                    .as_ref()
+                    .map(|explanation| explanation.describe())
+            })
+            .await
+            .unwrap_or_else(|| "No webcam events have been detected yet\n".to_string())),
+        ControlCommand::Status => {
+            let status = get_listener_status(worker, event_source, &device_filter).await?;
+
+            let mut message = format!(
+                "Automatic toggling: {}\nEvent source: {}\nQueues: {}\n",
+                match &status.forced {
+                    _ if status.maintenance =>
+                        "maintenance mode (devices are left alone)".to_string(),
+                    Some(forced) => format!(
+                        "forced {} for another {}s",
+                        if forced.on { "on" } else { "off" },
+                        forced.seconds_remaining
+                    ),
+                    None if status.paused => match status.snoozed_seconds_remaining {
+                        Some(seconds_remaining) => {
+                            format!("paused for another {}s", seconds_remaining)
+                        }
+                        None => "paused".to_string(),
+                    },
+                    None => match status.manual_override_seconds_remaining {
+                        Some(seconds_remaining) => format!(
+                            "left alone for another {}s, as the device was turned on or off by hand",
+                            seconds_remaining
+                        ),
+                        None => "active".to_string(),
+                    },
+                },
+                status.event_source.describe(),
+                status
+                    .queues
+                    .iter()
+                    .map(QueueStatus::describe)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            if !status.overrides.is_empty() {
+                message.push_str(&format!(
+                    "Temporary overrides (until the listener restarts): {}\n",
+                    status
+                        .overrides
+                        .iter()
+                        .map(Override::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            for device in status.devices {
+                message.push_str(&format_device_status(&device));
+                message.push('\n');
+            }
+
+            Ok(message)
+        }
+    }
+}
+
+/// Resumes automatic toggling once a snooze started with `snooze` is over, and turns the device
+/// on or off to match the webcam, since webcam events during the snooze were ignored. Does nothing
+/// if toggling has since been resumed, paused indefinitely or snoozed again.
+fn end_snooze(
+    context: &mut DeviceContext,
+    until: Instant,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+) {
+    if context.snoozed_until != Some(until) {
+        return;
+    }
+
+    context.snoozed_until = None;
+    context.paused = false;
+    log_info!("Snooze is over, so resumed automatic toggling");
+
+    let trigger = Trigger::SnoozeEnded {
+        webcam_in_use: context.webcam_in_use,
+    };
+    if let Err(error) = set_selected_devices_on_and_log_if_decided(
+        context,
+        device_filter,
+        require_device,
+        None,
+        trigger,
+    ) {
+        log_error!(
+            "Failed to update Litra device after snooze ended: {}",
+            error
+        );
+    }
+}
+
+/// Clears `forced` once it has expired, and turns the device on or off to match the webcam, unless
+/// automatic toggling is paused. Does nothing if `forced` has since been replaced by another
+/// forced state.
+fn expire_forced_state(
+    context: &mut DeviceContext,
+    forced: ForcedState,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+) {
+    if context.forced != Some(forced) {
+        return;
+    }
+
+    context.forced = None;
+    log_info!("Forced state expired, returning to automatic toggling");
+
+    let trigger = Trigger::ForcedStateExpired {
+        webcam_in_use: context.webcam_in_use,
+    };
+    if let Err(error) = set_selected_devices_on_and_log_if_decided(
+        context,
+        device_filter,
+        require_device,
+        None,
+        trigger,
+    ) {
+        log_error!(
+            "Failed to update Litra device after forced state expired: {}",
+            error
+        );
+    }
+}
+
+/// Returns whether automatic toggling is paused or overridden, the health of the event source, the
+/// queues of work waiting to be done and the state of the connected Litra devices.
+pub async fn get_listener_status(
+    worker: &DeviceWorker,
+    event_source: &EventSourceHealth,
+    device_filter: &DeviceFilter,
+) -> Result<ListenerStatus, String> {
+    let device_filter = device_filter.clone();
+    let event_source = event_source.status();
+    let device_queue = worker.queue_status();
+
+    worker
+        .run(move |context| {
+            // Devices aren't read while maintenance mode is on
+            if !context.maintenance {
+                context
+                    .context
+                    .refresh_connected_devices()
+                    .map_err(|error| error.to_string())?;
+            }
+
+            Ok(ListenerStatus {
+                maintenance: context.maintenance,
+                paused: context.paused,
+                snoozed_seconds_remaining: context
+                    .snoozed_until
+                    .map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+                forced: context.forced.map(|forced| ForcedStatus {
+                    on: forced.on,
+                    seconds_remaining: forced
+                        .until
+                        .saturating_duration_since(Instant::now())
+                        .as_secs(),
+                }),
+                manual_override_seconds_remaining: context.manual_override_seconds_remaining(),
+                overrides: context.overrides.active().to_vec(),
+                event_source,
+                queues: vec![device_queue, context.camera_hooks.queue_status()],
+                devices: if context.maintenance {
+                    Vec::new()
+                } else {
+                    get_connected_devices_info(&context.context, &device_filter)
+                        .into_iter()
+                        .map(DeviceStatus::from)
+                        .collect()
+                },
+            })
+        })
+        .await
+}
+
+/// Sends `command` (with `arguments`, e.g. a duration, if given) to the listener at `path`,
+/// printing its response. Returns an error if the listener couldn't be reached or failed to
+/// handle the command.
+#[cfg(unix)]
+pub fn send_control_command(
+    path: &Path,
+    command: ControlCommand,
+    arguments: &[String],
+) -> Result<(), CliError> {
+    let mut stream = UnixStream::connect(path).map_err(|error| {
+        CliError::IoError(std::io::Error::new(
+            error.kind(),
+            format!(
+                "Failed to connect to the control socket at {} - is litra-autotoggle running with `--control-socket`? ({})",
+                path.display(),
+                error
+            ),
+        ))
+    })?;
+
+    let command_name = command
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    let request = format!(
+        "{}\n",
+        std::iter::once(command_name.as_str())
+            .chain(arguments.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    stream.write_all(request.as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    match response.strip_prefix("ok\n") {
+        Some(message) => {
+            print!("{}", message);
+            Ok(())
+        }
+        None => Err(CliError::ControlError(
+            response.trim().trim_start_matches("error: ").to_string(),
+        )),
+    }
+}
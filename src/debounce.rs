@@ -0,0 +1,141 @@
+use crate::event_source::CameraEvent;
+use std::time::{Duration, Instant};
+
+/// Holds back webcam events for `--on-delay` or `--off-delay`, so the Litra isn't toggled by a
+/// webcam which is only on or off for a moment. Each event replaces the one being held back and
+/// starts the wait again, so only the state the webcam settles in is sent. With
+/// `--min-session-duration`, events turning the webcam on are held back for at least that long, so
+/// short sessions are ignored.
+///
+/// The `log` event source on macOS uses this, and it doesn't depend on the platform, so programs
+/// embedding the listener can debounce their own events the same way.
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    on_delay: Duration,
+    off_delay: Duration,
+    min_session_duration: Option<Duration>,
+    /// The event being held back, and when it is due to be sent
+    pending: Option<(CameraEvent, Instant)>,
+}
+
+impl Debouncer {
+    pub fn new(
+        on_delay: Duration,
+        off_delay: Duration,
+        min_session_duration: Option<Duration>,
+    ) -> Self {
+        Debouncer {
+            on_delay,
+            off_delay,
+            min_session_duration,
+            pending: None,
+        }
+    }
+
+    /// How long `event` is held back before it is sent.
+    pub fn delay_for(&self, event: &CameraEvent) -> Duration {
+        if event.on {
+            self.min_session_duration
+                .map_or(self.on_delay, |min_session_duration| {
+                    self.on_delay.max(min_session_duration)
+                })
+        } else {
+            self.off_delay
+        }
+    }
+
+    /// Holds back `event`, received at `now`, in place of any event still being held back, which
+    /// is returned. If that was the webcam turning on, and `event` turns it off, the session was
+    /// too short for the Litra to be turned on.
+    pub fn push(&mut self, event: CameraEvent, now: Instant) -> Option<CameraEvent> {
+        let due_at = now + self.delay_for(&event);
+        self.pending
+            .replace((event, due_at))
+            .map(|(replaced, _)| replaced)
+    }
+
+    /// When the event being held back is due to be sent, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, due_at)| *due_at)
+    }
+
+    /// Takes the event being held back, if it is due to be sent by `now`.
+    pub fn take_due(&mut self, now: Instant) -> Option<CameraEvent> {
+        match self.deadline() {
+            Some(due_at) if due_at <= now => self.pending.take().map(|(event, _)| event),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn event(on: bool) -> CameraEvent {
+        CameraEvent {
+            on,
+            camera: None,
+            app: Some("zoom.us".to_string()),
+        }
+    }
+
+    #[test]
+    fn holds_events_back_for_their_delay() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(SECOND, SECOND * 3, None);
+        assert_eq!(debouncer.deadline(), None);
+
+        assert_eq!(debouncer.push(event(true), start), None);
+        assert_eq!(debouncer.deadline(), Some(start + SECOND));
+        assert_eq!(debouncer.take_due(start), None);
+        assert_eq!(debouncer.take_due(start + SECOND), Some(event(true)));
+        assert_eq!(debouncer.take_due(start + SECOND), None);
+        assert_eq!(debouncer.deadline(), None);
+
+        debouncer.push(event(false), start + SECOND * 2);
+        assert_eq!(debouncer.take_due(start + SECOND * 4), None);
+        assert_eq!(debouncer.take_due(start + SECOND * 5), Some(event(false)));
+    }
+
+    #[test]
+    fn only_sends_the_state_the_webcam_settles_in() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(SECOND, SECOND, None);
+
+        debouncer.push(event(true), start);
+        assert_eq!(
+            debouncer.push(event(false), start + SECOND / 2),
+            Some(event(true))
+        );
+        // The wait starts again from the latest event
+        assert_eq!(debouncer.take_due(start + SECOND), None);
+        assert_eq!(
+            debouncer.take_due(start + SECOND * 3 / 2),
+            Some(event(false))
+        );
+    }
+
+    #[test]
+    fn waits_for_the_minimum_session_duration_before_turning_on() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(SECOND, SECOND, Some(SECOND * 10));
+        assert_eq!(debouncer.delay_for(&event(true)), SECOND * 10);
+        assert_eq!(debouncer.delay_for(&event(false)), SECOND);
+
+        debouncer.push(event(true), start);
+        assert_eq!(debouncer.take_due(start + SECOND * 9), None);
+        // A session shorter than the minimum is never sent
+        assert_eq!(
+            debouncer.push(event(false), start + SECOND * 9),
+            Some(event(true))
+        );
+        assert_eq!(debouncer.take_due(start + SECOND * 10), Some(event(false)));
+
+        // The minimum doesn't shorten a longer `--on-delay`
+        let debouncer = Debouncer::new(SECOND * 20, SECOND, Some(SECOND * 10));
+        assert_eq!(debouncer.delay_for(&event(true)), SECOND * 20);
+    }
+}
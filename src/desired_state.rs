@@ -0,0 +1,633 @@
+//! The rules the listener uses to decide whether to turn Litra devices on or off. They don't
+//! touch any devices, so a GUI or another frontend can use them to behave exactly like the
+//! listener. Inputs and decisions can be serialized, e.g. to send them between processes.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A state forced with `ctl force-on` or `ctl force-off`, which overrides automatic toggling
+/// until it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Forced {
+    pub on: bool,
+    pub seconds_remaining: u64,
+}
+
+/// Everything besides the webcam which affects whether devices are turned on or off.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conditions {
+    /// Whether devices are left alone with `ctl maintenance on`, e.g. while updating their
+    /// firmware
+    #[serde(default)]
+    pub maintenance: bool,
+    /// Whether automatic toggling is paused with `ctl pause`
+    pub paused: bool,
+    pub forced: Option<Forced>,
+    /// How many seconds are left of `--manual-override-grace-period`, if a device was recently
+    /// turned on or off outside litra-autotoggle
+    #[serde(default)]
+    pub manual_override: Option<u64>,
+    /// The quiet hours set with `--quiet-hours`, e.g. `22:00-07:00`, if it is currently quiet
+    /// hours
+    pub quiet_hours: Option<String>,
+}
+
+/// What should change the state of the devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    /// The webcam was turned on or off
+    Camera { on: bool },
+    /// A device was plugged in, while the webcam was or wasn't in use
+    Hotplug { webcam_in_use: bool },
+    /// A forced state expired, while the webcam was or wasn't in use
+    ForcedStateExpired { webcam_in_use: bool },
+    /// Maintenance mode was turned off, while the webcam was or wasn't in use
+    MaintenanceEnded { webcam_in_use: bool },
+    /// A snooze started with `ctl snooze` ended, while the webcam was or wasn't in use
+    SnoozeEnded { webcam_in_use: bool },
+    /// It was time to check the devices haven't drifted from where they should be, while the
+    /// webcam was or wasn't in use
+    Reconcile { webcam_in_use: bool },
+}
+
+impl Trigger {
+    /// Whether the trigger would turn devices on, if nothing else decides otherwise.
+    pub fn turns_on(self) -> bool {
+        match self {
+            Trigger::Camera { on } => on,
+            Trigger::Hotplug { webcam_in_use }
+            | Trigger::ForcedStateExpired { webcam_in_use }
+            | Trigger::MaintenanceEnded { webcam_in_use }
+            | Trigger::SnoozeEnded { webcam_in_use }
+            | Trigger::Reconcile { webcam_in_use } => webcam_in_use,
+        }
+    }
+}
+
+/// Why devices are left as they are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    Maintenance,
+    Paused,
+    Forced(Forced),
+    ManualOverride { seconds_remaining: u64 },
+    QuietHours { period: String },
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Maintenance => write!(f, "maintenance mode is on"),
+            SkipReason::Paused => write!(f, "automatic toggling is paused"),
+            SkipReason::Forced(forced) => write!(
+                f,
+                "it is forced {} for another {}s",
+                if forced.on { "on" } else { "off" },
+                forced.seconds_remaining
+            ),
+            SkipReason::ManualOverride { seconds_remaining } => write!(
+                f,
+                "it was recently turned on or off by hand, so it is left alone for another {}s",
+                seconds_remaining
+            ),
+            SkipReason::QuietHours { period } => write!(f, "it is quiet hours ({})", period),
+        }
+    }
+}
+
+/// What to do about a [`Trigger`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "decision")]
+pub enum Decision {
+    /// Turn the devices on or off
+    Turn { on: bool },
+    /// Leave the devices as they are, rather than turning them on or off
+    Skip { on: bool, reason: SkipReason },
+}
+
+/// Decides what to do about `trigger`, given the current `conditions`.
+///
+/// - When the webcam changes, devices follow it, unless automatic toggling is paused or forced,
+///   or a device was recently turned on or off by hand.
+/// - When a device is plugged in, it is set to the forced state if there is one, and otherwise
+///   follows the webcam unless automatic toggling is paused.
+/// - When a forced state expires, devices follow the webcam unless automatic toggling is paused.
+/// - When maintenance mode or a snooze ends, devices are brought up to date like when they are
+///   plugged in.
+/// - When checking whether devices have drifted, they are brought up to date like when they are
+///   plugged in, but are left alone if they were recently turned on or off by hand.
+/// - Whatever the trigger, devices aren't turned on during quiet hours unless a state is forced,
+///   but are still turned off.
+/// - While maintenance mode is on, devices are always left alone.
+pub fn decide(trigger: Trigger, conditions: &Conditions) -> Decision {
+    match decide_outside_maintenance(trigger, conditions) {
+        Decision::Turn { on } if conditions.maintenance => Decision::Skip {
+            on,
+            reason: SkipReason::Maintenance,
+        },
+        decision => decision,
+    }
+}
+
+fn decide_outside_maintenance(trigger: Trigger, conditions: &Conditions) -> Decision {
+    let (on, forced) = match trigger {
+        Trigger::Camera { on } => (on, conditions.forced),
+        Trigger::Hotplug { webcam_in_use }
+        | Trigger::MaintenanceEnded { webcam_in_use }
+        | Trigger::SnoozeEnded { webcam_in_use }
+        | Trigger::Reconcile { webcam_in_use } => match conditions.forced {
+            Some(forced) => return Decision::Turn { on: forced.on },
+            None => (webcam_in_use, None),
+        },
+        Trigger::ForcedStateExpired { webcam_in_use } => (webcam_in_use, None),
+    };
+
+    let reason = if conditions.paused {
+        Some(SkipReason::Paused)
+    } else if let Some(forced) = forced {
+        Some(SkipReason::Forced(forced))
+    } else if let (Trigger::Camera { .. } | Trigger::Reconcile { .. }, Some(seconds_remaining)) =
+        (trigger, conditions.manual_override)
+    {
+        Some(SkipReason::ManualOverride { seconds_remaining })
+    } else if let (true, Some(period)) = (on, &conditions.quiet_hours) {
+        Some(SkipReason::QuietHours {
+            period: period.clone(),
+        })
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Decision::Skip { on, reason },
+        None => Decision::Turn { on },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forced(on: bool) -> Option<Forced> {
+        Some(Forced {
+            on,
+            seconds_remaining: 60,
+        })
+    }
+
+    #[test]
+    fn camera_is_followed() {
+        let conditions = Conditions::default();
+        assert_eq!(
+            decide(Trigger::Camera { on: true }, &conditions),
+            Decision::Turn { on: true }
+        );
+        assert_eq!(
+            decide(Trigger::Camera { on: false }, &conditions),
+            Decision::Turn { on: false }
+        );
+    }
+
+    #[test]
+    fn maintenance_skips_everything() {
+        let conditions = Conditions {
+            maintenance: true,
+            ..Conditions::default()
+        };
+        for trigger in [
+            Trigger::Camera { on: true },
+            Trigger::Hotplug {
+                webcam_in_use: false,
+            },
+            Trigger::ForcedStateExpired {
+                webcam_in_use: true,
+            },
+            Trigger::Reconcile {
+                webcam_in_use: true,
+            },
+        ] {
+            assert!(matches!(
+                decide(trigger, &conditions),
+                Decision::Skip {
+                    reason: SkipReason::Maintenance,
+                    ..
+                }
+            ));
+        }
+
+        // Even a forced state isn't applied
+        let conditions = Conditions {
+            forced: forced(true),
+            ..conditions
+        };
+        assert_eq!(
+            decide(
+                Trigger::Hotplug {
+                    webcam_in_use: false
+                },
+                &conditions
+            ),
+            Decision::Skip {
+                on: true,
+                reason: SkipReason::Maintenance
+            }
+        );
+    }
+
+    #[test]
+    fn paused_skips_camera_and_hotplug() {
+        let conditions = Conditions {
+            paused: true,
+            ..Conditions::default()
+        };
+        assert_eq!(
+            decide(Trigger::Camera { on: true }, &conditions),
+            Decision::Skip {
+                on: true,
+                reason: SkipReason::Paused
+            }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Hotplug {
+                    webcam_in_use: false
+                },
+                &conditions
+            ),
+            Decision::Skip {
+                on: false,
+                reason: SkipReason::Paused
+            }
+        );
+    }
+
+    #[test]
+    fn forced_state_skips_camera_but_applies_to_hotplug() {
+        let conditions = Conditions {
+            forced: forced(false),
+            ..Conditions::default()
+        };
+        assert_eq!(
+            decide(Trigger::Camera { on: true }, &conditions),
+            Decision::Skip {
+                on: true,
+                reason: SkipReason::Forced(forced(false).unwrap())
+            }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Hotplug {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Turn { on: false }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Reconcile {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Turn { on: false }
+        );
+    }
+
+    #[test]
+    fn forced_state_applies_even_when_paused() {
+        let conditions = Conditions {
+            paused: true,
+            forced: forced(true),
+            ..Conditions::default()
+        };
+        assert_eq!(
+            decide(
+                Trigger::MaintenanceEnded {
+                    webcam_in_use: false
+                },
+                &conditions
+            ),
+            Decision::Turn { on: true }
+        );
+    }
+
+    #[test]
+    fn snooze_ending_brings_devices_up_to_date() {
+        assert_eq!(
+            decide(
+                Trigger::SnoozeEnded {
+                    webcam_in_use: true
+                },
+                &Conditions::default()
+            ),
+            Decision::Turn { on: true }
+        );
+
+        // A state forced during the snooze still applies
+        let conditions = Conditions {
+            forced: forced(false),
+            ..Conditions::default()
+        };
+        assert_eq!(
+            decide(
+                Trigger::SnoozeEnded {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Turn { on: false }
+        );
+
+        // Like other triggers, nothing is turned on or off while paused
+        let conditions = Conditions {
+            paused: true,
+            ..Conditions::default()
+        };
+        assert!(matches!(
+            decide(
+                Trigger::SnoozeEnded {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Skip {
+                reason: SkipReason::Paused,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn forced_state_expiry_follows_webcam() {
+        assert_eq!(
+            decide(
+                Trigger::ForcedStateExpired {
+                    webcam_in_use: true
+                },
+                &Conditions::default()
+            ),
+            Decision::Turn { on: true }
+        );
+    }
+
+    #[test]
+    fn manual_override_skips_camera_and_reconcile_only() {
+        let conditions = Conditions {
+            manual_override: Some(30),
+            ..Conditions::default()
+        };
+        let skipped = SkipReason::ManualOverride {
+            seconds_remaining: 30,
+        };
+        assert_eq!(
+            decide(Trigger::Camera { on: false }, &conditions),
+            Decision::Skip {
+                on: false,
+                reason: skipped.clone()
+            }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Reconcile {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Skip {
+                on: true,
+                reason: skipped
+            }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Hotplug {
+                    webcam_in_use: true
+                },
+                &conditions
+            ),
+            Decision::Turn { on: true }
+        );
+    }
+
+    #[test]
+    fn quiet_hours_only_stop_turning_on() {
+        let conditions = Conditions {
+            quiet_hours: Some("22:00-07:00".to_string()),
+            ..Conditions::default()
+        };
+        assert_eq!(
+            decide(Trigger::Camera { on: true }, &conditions),
+            Decision::Skip {
+                on: true,
+                reason: SkipReason::QuietHours {
+                    period: "22:00-07:00".to_string()
+                }
+            }
+        );
+        assert_eq!(
+            decide(Trigger::Camera { on: false }, &conditions),
+            Decision::Turn { on: false }
+        );
+        assert_eq!(
+            decide(
+                Trigger::Reconcile {
+                    webcam_in_use: false
+                },
+                &conditions
+            ),
+            Decision::Turn { on: false }
+        );
+    }
+
+    #[test]
+    fn quiet_hours_stop_every_trigger_turning_on() {
+        let conditions = Conditions {
+            quiet_hours: Some("22:00-07:00".to_string()),
+            ..Conditions::default()
+        };
+        for webcam_in_use in [true, false] {
+            for trigger in [
+                Trigger::Hotplug { webcam_in_use },
+                Trigger::ForcedStateExpired { webcam_in_use },
+                Trigger::MaintenanceEnded { webcam_in_use },
+                Trigger::SnoozeEnded { webcam_in_use },
+                Trigger::Reconcile { webcam_in_use },
+            ] {
+                let expected = if webcam_in_use {
+                    Decision::Skip {
+                        on: true,
+                        reason: SkipReason::QuietHours {
+                            period: "22:00-07:00".to_string(),
+                        },
+                    }
+                } else {
+                    Decision::Turn { on: false }
+                };
+                assert_eq!(decide(trigger, &conditions), expected, "{:?}", trigger);
+            }
+        }
+
+        // A forced state is still applied
+        let conditions = Conditions {
+            forced: forced(true),
+            ..conditions
+        };
+        assert_eq!(
+            decide(
+                Trigger::Hotplug {
+                    webcam_in_use: false
+                },
+                &conditions
+            ),
+            Decision::Turn { on: true }
+        );
+    }
+
+    /// Every trigger, with the webcam both on and off.
+    fn every_trigger() -> Vec<Trigger> {
+        [true, false]
+            .into_iter()
+            .flat_map(|on| {
+                [
+                    Trigger::Camera { on },
+                    Trigger::Hotplug { webcam_in_use: on },
+                    Trigger::ForcedStateExpired { webcam_in_use: on },
+                    Trigger::MaintenanceEnded { webcam_in_use: on },
+                    Trigger::SnoozeEnded { webcam_in_use: on },
+                    Trigger::Reconcile { webcam_in_use: on },
+                ]
+            })
+            .collect()
+    }
+
+    /// Every combination of conditions which makes a difference to a decision.
+    fn every_conditions() -> Vec<Conditions> {
+        let mut every_conditions = Vec::new();
+        for maintenance in [false, true] {
+            for paused in [false, true] {
+                for forced in [None, forced(true), forced(false)] {
+                    for manual_override in [None, Some(30)] {
+                        for quiet_hours in [None, Some("22:00-07:00".to_string())] {
+                            every_conditions.push(Conditions {
+                                maintenance,
+                                paused,
+                                forced,
+                                manual_override,
+                                quiet_hours,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        every_conditions
+    }
+
+    #[test]
+    fn every_trigger_and_conditions_follow_the_rules() {
+        for trigger in every_trigger() {
+            for conditions in every_conditions() {
+                let decision = decide(trigger, &conditions);
+                let context = format!("{:?} with {:?} gave {:?}", trigger, conditions, decision);
+
+                // Triggers which bring devices up to date apply a forced state straight away
+                let applies_forced = conditions.forced.filter(|_| {
+                    matches!(
+                        trigger,
+                        Trigger::Hotplug { .. }
+                            | Trigger::MaintenanceEnded { .. }
+                            | Trigger::SnoozeEnded { .. }
+                            | Trigger::Reconcile { .. }
+                    )
+                });
+                let on = match applies_forced {
+                    Some(forced) => forced.on,
+                    None => trigger.turns_on(),
+                };
+                let (decided_on, reason) = match &decision {
+                    Decision::Turn { on } => (*on, None),
+                    Decision::Skip { on, reason } => (*on, Some(reason)),
+                };
+                assert_eq!(decided_on, on, "{}", context);
+
+                // Devices are always left alone in maintenance mode, though another reason to leave
+                // them alone may be given instead, and otherwise a forced state which applies wins
+                if conditions.maintenance {
+                    assert!(reason.is_some(), "{}", context);
+                    continue;
+                }
+                if applies_forced.is_some() {
+                    assert_eq!(reason, None, "{}", context);
+                    continue;
+                }
+
+                // Otherwise, the first reason to leave the devices alone is given
+                let expected_reason = if conditions.paused {
+                    Some(SkipReason::Paused)
+                } else if let (Trigger::Camera { .. }, Some(forced)) = (trigger, conditions.forced)
+                {
+                    Some(SkipReason::Forced(forced))
+                } else if let (
+                    Trigger::Camera { .. } | Trigger::Reconcile { .. },
+                    Some(seconds_remaining),
+                ) = (trigger, conditions.manual_override)
+                {
+                    Some(SkipReason::ManualOverride { seconds_remaining })
+                } else if let (true, Some(period)) = (on, &conditions.quiet_hours) {
+                    Some(SkipReason::QuietHours {
+                        period: period.clone(),
+                    })
+                } else {
+                    None
+                };
+                assert_eq!(reason, expected_reason.as_ref(), "{}", context);
+
+                // However the rules are ordered, quiet hours never stop devices turning off, and
+                // nothing is turned on by a webcam event during quiet hours
+                if !on {
+                    assert!(
+                        !matches!(reason, Some(SkipReason::QuietHours { .. })),
+                        "{}",
+                        context
+                    );
+                }
+                if conditions.quiet_hours.is_some() {
+                    assert_ne!(decision, Decision::Turn { on: true }, "{}", context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_decision_round_trips_through_json() {
+        for trigger in every_trigger() {
+            let json = serde_json::to_string(&trigger).unwrap();
+            assert_eq!(serde_json::from_str::<Trigger>(&json).unwrap(), trigger);
+
+            for conditions in every_conditions() {
+                let json = serde_json::to_string(&conditions).unwrap();
+                assert_eq!(
+                    serde_json::from_str::<Conditions>(&json).unwrap(),
+                    conditions
+                );
+
+                let decision = decide(trigger, &conditions);
+                let json = serde_json::to_string(&decision).unwrap();
+                assert_eq!(serde_json::from_str::<Decision>(&json).unwrap(), decision);
+            }
+        }
+    }
+
+    #[test]
+    fn decisions_round_trip_through_json() {
+        let decision = Decision::Skip {
+            on: true,
+            reason: SkipReason::Forced(forced(false).unwrap()),
+        };
+        let json = serde_json::to_string(&decision).unwrap();
+        assert_eq!(serde_json::from_str::<Decision>(&json).unwrap(), decision);
+    }
+}
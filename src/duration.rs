@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+/// The longest duration we accept, so typos like `5000h` are caught rather than silently
+/// accepted.
+const MAXIMUM_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The unit used for a number without a unit. This keeps arguments that used to be a plain number
+/// of milliseconds or seconds working.
+#[derive(Debug, Clone, Copy)]
+enum DefaultUnit {
+    Milliseconds,
+    Seconds,
+}
+
+/// Parses a duration like `750ms`, `1.5s`, `45m` or `2h`, rejecting durations that are negative,
+/// longer than a week, or zero (unless `allow_zero` is set).
+fn parse(value: &str, default_unit: DefaultUnit, allow_zero: bool) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration `{}`. Use a number followed by `ms`, `s`, `m` or `h`, e.g. `750ms`, `1.5s` or `45m`.",
+            value
+        )
+    };
+
+    let trimmed = value.trim();
+    let (number, seconds_per_unit) = if let Some(number) = trimmed.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = trimmed.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = trimmed.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = trimmed.strip_suffix('h') {
+        (number, 60.0 * 60.0)
+    } else {
+        match default_unit {
+            DefaultUnit::Milliseconds => (trimmed, 0.001),
+            DefaultUnit::Seconds => (trimmed, 1.0),
+        }
+    };
+
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    let duration = Duration::try_from_secs_f64(number * seconds_per_unit).map_err(|_| invalid())?;
+
+    if duration.is_zero() && !allow_zero {
+        return Err(format!(
+            "Invalid duration `{}`. It must be more than zero.",
+            value
+        ));
+    }
+
+    if duration > MAXIMUM_DURATION {
+        return Err(format!(
+            "Invalid duration `{}`. It must be no longer than a week.",
+            value
+        ));
+    }
+
+    Ok(duration)
+}
+
+/// Parses a duration for an interval or timeout, where a number without a unit is a number of
+/// seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    parse(value, DefaultUnit::Seconds, false)
+}
+
+/// Parses a duration for an interval, where a number without a unit is a number of milliseconds.
+pub fn parse_duration_in_milliseconds(value: &str) -> Result<Duration, String> {
+    parse(value, DefaultUnit::Milliseconds, false)
+}
+
+/// Parses a delay, where a number without a unit is a number of milliseconds. Unlike intervals,
+/// delays can be zero.
+pub fn parse_delay(value: &str) -> Result<Duration, String> {
+    parse(value, DefaultUnit::Milliseconds, true)
+}
+
+/// Formats a duration for log messages, e.g. `750ms` or `1.5s`.
+pub fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse_duration("750ms"), Ok(Duration::from_millis(750)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("45m"), Ok(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration(" 2h "), Ok(Duration::from_secs(2 * 60 * 60)));
+    }
+
+    #[test]
+    fn uses_default_unit_for_plain_numbers() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(
+            parse_duration_in_milliseconds("250"),
+            Ok(Duration::from_millis(250))
+        );
+        assert_eq!(parse_delay("100"), Ok(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn only_delays_can_be_zero() {
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration_in_milliseconds("0").is_err());
+        assert_eq!(parse_delay("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in ["", "s", "abc", "-1s", "1d", "NaNs", "inf"] {
+            assert!(parse_duration(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn rejects_durations_longer_than_a_week() {
+        assert_eq!(parse_duration("168h"), Ok(MAXIMUM_DURATION));
+        assert!(parse_duration("169h").is_err());
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(750)), "750ms");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_secs(60)), "60s");
+    }
+}
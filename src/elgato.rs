@@ -0,0 +1,547 @@
+use crate::logging::{log_error, log_info};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::watch;
+
+/// The port Elgato Key Lights serve their HTTP API on.
+const DEFAULT_PORT: u16 = 9123;
+
+/// How long to wait for a Key Light to respond before giving up on it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for Key Lights to answer when discovering them.
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where mDNS queries are sent.
+const MDNS_ADDRESS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// The mDNS service Elgato Key Lights advertise.
+const SERVICE: &str = "_elg._tcp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Parses the address of a Key Light given with `--elgato-light`, e.g. `192.168.1.20`,
+/// `key-light.local:9123` or `[fe80::1]:9123`, adding the default port if there isn't one. IPv6
+/// addresses must be in brackets, so their colons can't be mistaken for a port.
+pub fn parse_key_light(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let invalid = || {
+        format!(
+            "Invalid Elgato Key Light `{}`. It must be a host name or IP address, optionally with a port, e.g. `192.168.1.20`, `key-light.local:9123` or `[fe80::1]:9123`.",
+            value
+        )
+    };
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+
+    let (host, port) = match value.strip_prefix('[') {
+        // An IPv6 address, e.g. `[fe80::1]` or `[fe80::1]:9123`
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']').ok_or_else(invalid)?;
+            ip.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match port {
+                "" => None,
+                port => Some(port.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (format!("[{}]", ip), port)
+        }
+        None => match value.split_once(':') {
+            Some((host, port)) => (host.to_string(), Some(port)),
+            None => (value.to_string(), None),
+        },
+    };
+    if host.is_empty() || port.is_some_and(|port| port.parse::<u16>().is_err()) {
+        return Err(invalid());
+    }
+
+    Ok(match port {
+        Some(port) => format!("{}:{}", host, port),
+        None => format!("{}:{}", host, DEFAULT_PORT),
+    })
+}
+
+/// Sends `request` to the Key Light at `address`, checking that it succeeded.
+async fn send_request(address: &str, request: &str) -> std::io::Result<()> {
+    let status_line = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).await?;
+        Ok::<_, std::io::Error>(status_line)
+    })
+    .await
+    .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"))??;
+
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(std::io::Error::other(format!(
+            "Unexpected response `{}`",
+            status_line.trim()
+        ))),
+    }
+}
+
+/// Turns the Key Light at `address` on or off with its HTTP API.
+async fn set_key_light_on(address: &str, on: bool) -> std::io::Result<()> {
+    let body = serde_json::json!({
+        "numberOfLights": 1,
+        "lights": [{ "on": u8::from(on) }],
+    })
+    .to_string();
+    let request = format!(
+        "PUT /elgato/lights HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        address,
+        body.len(),
+        body
+    );
+
+    send_request(address, &request).await
+}
+
+/// Checks that the Key Light at `address` answers its HTTP API, without changing it, for
+/// `--check-integrations`.
+pub async fn check_key_light(address: &str) -> Result<String, String> {
+    let request = format!(
+        "GET /elgato/lights HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        address
+    );
+
+    match send_request(address, &request).await {
+        Ok(()) => Ok(format!("the Key Light at {} answered", address)),
+        Err(error) => Err(format!(
+            "the Key Light at {} didn't answer: {}",
+            address, error
+        )),
+    }
+}
+
+/// The Elgato Key Lights turned on and off with the webcam. Each light has a task which sends it
+/// one request at a time, skipping straight to the latest state if it changes while a request is
+/// in flight, so a slow light can't be left in an older state than the Litra devices. Failures
+/// are logged.
+#[derive(Default)]
+pub struct KeyLights {
+    lights: Vec<KeyLight>,
+    /// How many times the lights have been turned on or off, so [`KeyLights::settled`] knows
+    /// which request to wait for.
+    generation: u64,
+}
+
+struct KeyLight {
+    address: String,
+    /// The latest generation and state for the light's task to send.
+    requested: watch::Sender<(u64, bool)>,
+    /// The latest generation the light's task has finished sending.
+    sent: watch::Receiver<u64>,
+}
+
+impl KeyLights {
+    /// Starts turning the Key Light at `address` on and off, unless it already is. Returns whether
+    /// it was added.
+    pub fn add(&mut self, address: String) -> bool {
+        if self.contains(&address) {
+            return false;
+        }
+
+        let (requested, requested_receiver) = watch::channel((0, false));
+        let (sent_sender, sent) = watch::channel(0);
+        tokio::spawn(drive_key_light(
+            address.clone(),
+            requested_receiver,
+            sent_sender,
+        ));
+        self.lights.push(KeyLight {
+            address,
+            requested,
+            sent,
+        });
+        true
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.lights.iter().any(|light| light.address == address)
+    }
+
+    /// Turns the Key Lights on or off in the background, so a slow or unreachable light doesn't
+    /// hold up the Litra devices.
+    pub fn set_on(&mut self, on: bool) {
+        self.generation += 1;
+        for light in &self.lights {
+            light.requested.send_replace((self.generation, on));
+        }
+    }
+
+    /// Waits until each Key Light has been sent the latest state, e.g. before exiting.
+    pub fn settled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let generation = self.generation;
+        let mut sent: Vec<_> = self.lights.iter().map(|light| light.sent.clone()).collect();
+        async move {
+            for sent in &mut sent {
+                // An error means the light's task has stopped, so there's nothing to wait for
+                let _ = sent.wait_for(|sent| *sent >= generation).await;
+            }
+        }
+    }
+}
+
+/// Sends each state requested with [`KeyLights::set_on`] to the Key Light at `address`, one at a
+/// time, until the [`KeyLights`] are dropped.
+async fn drive_key_light(
+    address: String,
+    mut requested: watch::Receiver<(u64, bool)>,
+    sent: watch::Sender<u64>,
+) {
+    while requested.changed().await.is_ok() {
+        let (generation, on) = *requested.borrow_and_update();
+        match set_key_light_on(&address, on).await {
+            Ok(()) => log_info!(
+                "Turned {} Elgato Key Light {}",
+                if on { "on" } else { "off" },
+                address
+            ),
+            Err(error) => log_error!(
+                "Failed to turn {} Elgato Key Light {}: {}",
+                if on { "on" } else { "off" },
+                address,
+                error
+            ),
+        }
+        sent.send_replace(generation);
+    }
+}
+
+/// Builds an mDNS query for the Key Light service.
+fn build_query() -> Vec<u8> {
+    // The header, with an ID of 0 and a single question
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in SERVICE.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+fn read_u16(packet: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *packet.get(position)?,
+        *packet.get(position + 1)?,
+    ]))
+}
+
+/// Reads the domain name at `position`, following compression pointers, returning it in lower
+/// case with the position just after it.
+fn read_name(packet: &[u8], mut position: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // Limit how far we go, so a packet with a pointer loop can't keep us here forever
+    for _ in 0..128 {
+        let length = usize::from(*packet.get(position)?);
+        if length == 0 {
+            return Some((labels.join("."), end.unwrap_or(position + 1)));
+        }
+
+        if length & 0xC0 == 0xC0 {
+            let pointer = ((length & 0x3F) << 8) | usize::from(*packet.get(position + 1)?);
+            end.get_or_insert(position + 2);
+            position = pointer;
+        } else {
+            let label = packet.get(position + 1..position + 1 + length)?;
+            labels.push(String::from_utf8_lossy(label).to_lowercase());
+            position += 1 + length;
+        }
+    }
+
+    None
+}
+
+/// The records from mDNS responses needed to find Key Lights.
+#[derive(Default)]
+struct Records {
+    /// The host name and port of each Key Light, by its service instance name
+    services: HashMap<String, (String, u16)>,
+    /// The IPv4 address of each host
+    hosts: HashMap<String, Ipv4Addr>,
+}
+
+impl Records {
+    /// Reads the SRV and A records from an mDNS response. Returns `None` if it is malformed.
+    fn add_response(&mut self, packet: &[u8]) -> Option<()> {
+        let questions = read_u16(packet, 4)?;
+        let records = [6, 8, 10]
+            .into_iter()
+            .map(|position| read_u16(packet, position).map(usize::from))
+            .sum::<Option<usize>>()?;
+
+        let mut position = 12;
+        for _ in 0..questions {
+            let (_, next) = read_name(packet, position)?;
+            position = next + 4;
+        }
+
+        for _ in 0..records {
+            let (name, next) = read_name(packet, position)?;
+            let record_type = read_u16(packet, next)?;
+            let length = usize::from(read_u16(packet, next + 8)?);
+            let data_start = next + 10;
+            let data = packet.get(data_start..data_start + length)?;
+
+            match record_type {
+                TYPE_SRV if length > 6 && name.ends_with(&format!(".{}", SERVICE)) => {
+                    let port = read_u16(packet, data_start + 4)?;
+                    let (target, _) = read_name(packet, data_start + 6)?;
+                    self.services.insert(name, (target, port));
+                }
+                TYPE_A if length == 4 => {
+                    self.hosts
+                        .insert(name, Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+                }
+                _ => {}
+            }
+
+            position = data_start + length;
+        }
+
+        Some(())
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = self
+            .services
+            .values()
+            .filter_map(|(host, port)| {
+                let ip = self.hosts.get(host)?;
+                Some(SocketAddr::from((*ip, *port)).to_string())
+            })
+            .collect();
+        addresses.sort();
+        addresses
+    }
+}
+
+/// Looks for Elgato Key Lights on the local network with mDNS, waiting `timeout` for them to
+/// answer, and returns their addresses.
+pub async fn discover_key_lights(timeout: Duration) -> std::io::Result<Vec<String>> {
+    // Querying from a port other than 5353 asks responders to answer us directly
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.send_to(&build_query(), MDNS_ADDRESS).await?;
+
+    let mut records = Records::default();
+    let mut buffer = [0; 9000];
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(result) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let (length, _) = result?;
+        if records.add_response(&buffer[..length]).is_none() {
+            log_error!("Ignoring malformed mDNS response while discovering Elgato Key Lights");
+        }
+    }
+
+    Ok(records.addresses())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn record(name: Vec<u8>, record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name;
+        record.extend_from_slice(&record_type.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&120u32.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    /// A response to our query, with the SRV record for a Key Light and the A record for its host.
+    fn response() -> Vec<u8> {
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&9123u16.to_be_bytes());
+        srv.extend(name("elgato-key-light.local"));
+
+        let mut packet = vec![0, 0, 0x84, 0, 0, 1, 0, 2, 0, 0, 0, 0];
+        packet.extend(name(SERVICE));
+        packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet.extend(record(
+            name(&format!("Elgato Key Light 1A2B.{}", SERVICE)),
+            TYPE_SRV,
+            &srv,
+        ));
+        packet.extend(record(
+            name("elgato-key-light.local"),
+            TYPE_A,
+            &[192, 168, 1, 20],
+        ));
+        packet
+    }
+
+    #[test]
+    fn reads_names_in_lower_case() {
+        let packet = name("Key-Light.Local");
+        assert_eq!(
+            read_name(&packet, 0),
+            Some(("key-light.local".to_string(), packet.len()))
+        );
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        // `local` at 0, then `elgato` followed by a pointer back to it
+        let mut packet = name("local");
+        packet.extend_from_slice(&[6, b'e', b'l', b'g', b'a', b't', b'o', 0xC0, 0]);
+        assert_eq!(
+            read_name(&packet, 7),
+            Some(("elgato.local".to_string(), packet.len()))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_pointer_loops() {
+        assert_eq!(read_name(&[0xC0, 0], 0), None);
+        assert_eq!(read_name(&[1, b'a', 0xC0, 0], 0), None);
+    }
+
+    #[test]
+    fn rejects_truncated_names() {
+        assert_eq!(read_name(&[], 0), None);
+        assert_eq!(read_name(&[5, b'l', b'o'], 0), None);
+        assert_eq!(read_name(&[3, b'f', b'o', b'o'], 0), None);
+        assert_eq!(read_name(&[0xC0], 0), None);
+        assert_eq!(read_name(&[0xC0, 10], 0), None);
+    }
+
+    #[test]
+    fn finds_key_lights_in_responses() {
+        let mut records = Records::default();
+        assert_eq!(records.add_response(&response()), Some(()));
+        assert_eq!(records.addresses(), vec!["192.168.1.20:9123".to_string()]);
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let response = response();
+        for length in [0, 11, 12, response.len() - 1] {
+            let mut records = Records::default();
+            assert_eq!(
+                records.add_response(&response[..length]),
+                None,
+                "accepted a response truncated to {} bytes",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_key_lights_without_an_address() {
+        let mut response = response();
+        // Only one record, so the A record is ignored
+        response[7] = 1;
+        let mut records = Records::default();
+        assert_eq!(records.add_response(&response), Some(()));
+        assert!(records.addresses().is_empty());
+    }
+
+    #[test]
+    fn parses_key_light_addresses() {
+        assert_eq!(
+            parse_key_light(" 192.168.1.20 "),
+            Ok("192.168.1.20:9123".to_string())
+        );
+        assert_eq!(
+            parse_key_light("key-light.local:80"),
+            Ok("key-light.local:80".to_string())
+        );
+        assert_eq!(
+            parse_key_light("[fe80::1]:9123"),
+            Ok("[fe80::1]:9123".to_string())
+        );
+        assert_eq!(
+            parse_key_light("[fe80::1]"),
+            Ok("[fe80::1]:9123".to_string())
+        );
+        assert!(parse_key_light("").is_err());
+        assert!(parse_key_light("key light").is_err());
+        assert!(parse_key_light("fe80::1").is_err());
+        assert!(parse_key_light("[fe80::1]9123").is_err());
+        assert!(parse_key_light("[key-light.local]:9123").is_err());
+        assert!(parse_key_light("key-light.local:").is_err());
+        assert!(parse_key_light(":9123").is_err());
+    }
+
+    /// Answers a request to turn a Key Light on or off, returning the state it asked for.
+    async fn answer(stream: TcpStream) -> u64 {
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if let Some(length) = line.strip_prefix("Content-Length: ") {
+                content_length = length.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\n\r\n")
+            .await
+            .unwrap();
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["lights"][0]["on"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_key_lights_the_latest_state_one_request_at_a_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut key_lights = KeyLights::default();
+        assert!(key_lights.add(listener.local_addr().unwrap().to_string()));
+        assert!(!key_lights.add(listener.local_addr().unwrap().to_string()));
+
+        key_lights.set_on(true);
+        let (stream, _) = listener.accept().await.unwrap();
+
+        // Changes while a request is in flight are sent afterwards, skipping to the latest
+        key_lights.set_on(false);
+        key_lights.set_on(true);
+        key_lights.set_on(false);
+        assert_eq!(answer(stream).await, 1);
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(answer(stream).await, 0);
+
+        key_lights.settled().await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), listener.accept())
+                .await
+                .is_err()
+        );
+    }
+}
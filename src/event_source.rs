@@ -0,0 +1,155 @@
+use crate::CliError;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// A webcam turning on or off, detected by an [`EventSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraEvent {
+    pub on: bool,
+    /// The camera, if the event source can tell, e.g. `/dev/video0` or `FaceTime HD Camera`
+    pub camera: Option<String>,
+    /// The app using the camera, if the event source can tell, e.g. `zoom.us`
+    pub app: Option<String>,
+}
+
+impl CameraEvent {
+    /// Describes the camera and the app using it, as far as they are known, for log messages.
+    pub fn describe(&self) -> String {
+        match (&self.camera, &self.app) {
+            (Some(camera), Some(app)) => format!("{}, used by {}", camera, app),
+            (Some(camera), None) => camera.clone(),
+            (None, Some(app)) => format!("used by {}", app),
+            (None, None) => "unknown camera".to_string(),
+        }
+    }
+}
+
+/// Something which detects webcams turning on and off, e.g. the `log` process on macOS or
+/// inotify on Linux. The listener picks one at runtime and turns the Litra on and off in
+/// response to its events, so a new way of detecting webcam events only has to produce
+/// [`CameraEvent`]s.
+pub trait EventSource: Send {
+    /// What kind of event source this is, e.g. `inotify`, for `ctl status` and `GET /status`
+    fn kind(&self) -> &'static str;
+
+    /// Starts detecting webcam events, sending them to the returned receiver. The listener exits
+    /// if an error is sent, or if the sender is dropped. Event sources which can tell they are
+    /// still working between events should say so with [`EventSourceHealth::record_heartbeat`].
+    fn start(
+        self: Box<Self>,
+        health: EventSourceHealth,
+    ) -> mpsc::Receiver<Result<CameraEvent, CliError>>;
+}
+
+struct EventSourceState {
+    started_at: Instant,
+    last_event_at: Option<(Instant, SystemTime)>,
+    /// When the event source last showed it was still working, e.g. by waking up to check for
+    /// events
+    last_heartbeat_at: Instant,
+    event_count: u64,
+    /// How many times the event source has had to be restarted, e.g. because the `log` process
+    /// exited
+    restarts: u64,
+}
+
+/// Tracks when the [`EventSource`] started, and when it last reported an event, so when the Litra
+/// stops reacting you can tell whether detection or device control is the problem. Cloned handles
+/// share the same state.
+#[derive(Clone)]
+pub struct EventSourceHealth {
+    kind: &'static str,
+    state: Arc<Mutex<EventSourceState>>,
+}
+
+/// A snapshot of the health of the event source.
+#[derive(Debug, Serialize)]
+pub struct EventSourceStatus {
+    kind: &'static str,
+    uptime_in_seconds: u64,
+    event_count: u64,
+    restarts: u64,
+    /// When the last event was detected, in seconds since the Unix epoch
+    last_event_at: Option<u64>,
+    seconds_since_last_event: Option<u64>,
+}
+
+impl EventSourceHealth {
+    pub fn new(kind: &'static str) -> Self {
+        EventSourceHealth {
+            kind,
+            state: Arc::new(Mutex::new(EventSourceState {
+                started_at: Instant::now(),
+                last_event_at: None,
+                last_heartbeat_at: Instant::now(),
+                event_count: 0,
+                restarts: 0,
+            })),
+        }
+    }
+
+    /// Records that the event source has detected a webcam event.
+    pub fn record_event(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.last_event_at = Some((Instant::now(), SystemTime::now()));
+        state.last_heartbeat_at = Instant::now();
+        state.event_count += 1;
+    }
+
+    /// Records that the event source has been restarted after it stopped, so a source which keeps
+    /// failing shows up in `ctl status` and `GET /health`.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    pub fn record_restart(&self) {
+        self.state.lock().unwrap().restarts += 1;
+    }
+
+    /// Records that the event source is still working, even though it hasn't detected an event.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn record_heartbeat(&self) {
+        self.state.lock().unwrap().last_heartbeat_at = Instant::now();
+    }
+
+    /// How long it has been since the event source last showed it was still working, so the
+    /// systemd watchdog can tell when it has hung.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn time_since_heartbeat(&self) -> Duration {
+        self.state.lock().unwrap().last_heartbeat_at.elapsed()
+    }
+
+    pub fn status(&self) -> EventSourceStatus {
+        let state = self.state.lock().unwrap();
+
+        EventSourceStatus {
+            kind: self.kind,
+            uptime_in_seconds: state.started_at.elapsed().as_secs(),
+            event_count: state.event_count,
+            restarts: state.restarts,
+            last_event_at: state.last_event_at.and_then(|(_, at)| {
+                at.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|duration| duration.as_secs())
+            }),
+            seconds_since_last_event: state.last_event_at.map(|(at, _)| at.elapsed().as_secs()),
+        }
+    }
+}
+
+impl EventSourceStatus {
+    /// Formats the status for humans, e.g. `inotify (running for 3600s, 2 events, 0 restarts, last
+    /// event 60s ago)`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} (running for {}s, {} events, {} restarts, {})",
+            self.kind,
+            self.uptime_in_seconds,
+            self.event_count,
+            self.restarts,
+            match self.seconds_since_last_event {
+                Some(seconds) => format!("last event {}s ago", seconds),
+                None => "no events yet".to_string(),
+            }
+        )
+    }
+}
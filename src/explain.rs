@@ -0,0 +1,222 @@
+use crate::logging::{self, Event};
+use crate::{DeviceContext, DeviceFilter};
+use std::time::Instant;
+
+/// A check the listener made when deciding what to do about a webcam event, and its outcome.
+#[derive(Debug, Clone)]
+struct Step {
+    check: String,
+    outcome: String,
+}
+
+/// Why the listener did what it did about a webcam event, for `--explain` and `ctl explain-last`.
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    event: String,
+    detected_at: Instant,
+    steps: Vec<Step>,
+    action: String,
+}
+
+impl Explanation {
+    fn step(&mut self, check: impl Into<String>, outcome: impl Into<String>) {
+        self.steps.push(Step {
+            check: check.into(),
+            outcome: outcome.into(),
+        });
+    }
+
+    /// Formats the explanation for humans, with a line for each check.
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "Event: {} ({}s ago)\n",
+            self.event,
+            self.detected_at.elapsed().as_secs()
+        );
+        for step in &self.steps {
+            description.push_str(&format!("- {}: {}\n", step.check, step.outcome));
+        }
+        description.push_str(&format!("Action: {}\n", self.action));
+        description
+    }
+}
+
+/// Works out what the listener will do about the webcam being turned on or off, and why. Devices
+/// are checked against `device_filter` as of the last time we looked for them.
+fn explain_webcam_event(
+    context: &DeviceContext,
+    device_filter: &DeviceFilter,
+    camera: Option<&str>,
+    on: bool,
+) -> Explanation {
+    let turn = if on { "on" } else { "off" };
+    let mut explanation = Explanation {
+        event: format!(
+            "webcam turned {} (triggered by: {})",
+            turn,
+            camera.unwrap_or("unknown camera")
+        ),
+        detected_at: Instant::now(),
+        steps: Vec::new(),
+        action: String::new(),
+    };
+
+    explanation.step(
+        "Automatic toggling paused with `ctl pause`",
+        if context.paused { "yes" } else { "no" },
+    );
+    if context.paused {
+        explanation.action = format!(
+            "Not turning {} Litra device, as automatic toggling is paused",
+            turn
+        );
+        return explanation;
+    }
+
+    let forced = context.forced.map(|forced| {
+        format!(
+            "forced {} for another {}s",
+            if forced.on { "on" } else { "off" },
+            forced
+                .until
+                .saturating_duration_since(Instant::now())
+                .as_secs()
+        )
+    });
+    explanation.step(
+        "Forced with `ctl force-on` or `ctl force-off`",
+        forced.as_deref().unwrap_or("no"),
+    );
+    if let Some(forced) = forced {
+        explanation.action = format!("Not turning {} Litra device, as it is {}", turn, forced);
+        return explanation;
+    }
+
+    let manual_override = context.manual_override_seconds_remaining();
+    explanation.step(
+        "Turned on or off by hand within `--manual-override-grace-period`",
+        match manual_override {
+            Some(seconds_remaining) => format!("yes ({}s left)", seconds_remaining),
+            None => "no".to_string(),
+        },
+    );
+    if manual_override.is_some() {
+        explanation.action = format!(
+            "Not turning {} Litra device, as it was recently turned on or off by hand",
+            turn
+        );
+        return explanation;
+    }
+
+    if on {
+        let quiet_hours = context
+            .options
+            .quiet_hours
+            .filter(|quiet_hours| quiet_hours.is_now());
+        explanation.step(
+            "Quiet hours (`--quiet-hours`)",
+            match quiet_hours {
+                Some(quiet_hours) => format!("yes ({})", quiet_hours),
+                None => "no".to_string(),
+            },
+        );
+        if quiet_hours.is_some() {
+            explanation.action = "Not turning on Litra device, as it is quiet hours".to_string();
+            return explanation;
+        }
+    }
+
+    explanation.step(
+        "Maintenance mode on with `ctl maintenance on`",
+        if context.maintenance { "yes" } else { "no" },
+    );
+    if context.maintenance {
+        explanation.action = format!(
+            "Not turning {} Litra device, as maintenance mode is on",
+            turn
+        );
+        return explanation;
+    }
+
+    explanation.step(
+        "Observe mode (`--observe`)",
+        if context.options.observe { "yes" } else { "no" },
+    );
+    if context.options.observe {
+        explanation.action = format!(
+            "Not turning {} Litra device, as observe mode is enabled",
+            turn
+        );
+        return explanation;
+    }
+
+    let mut selected_devices = 0;
+    for device in context.context.get_connected_devices() {
+        let outcome = match device_filter.check(&device) {
+            Ok(()) if selected_devices > 0 && !device_filter.selects_all() => {
+                "not selected, as only the first device is used unless devices are specified with `--serial-number` or excluded"
+            }
+            Ok(()) => {
+                selected_devices += 1;
+                "selected"
+            }
+            Err(reason) => reason,
+        };
+        explanation.step(
+            format!(
+                "{} (serial number: {}, path: {})",
+                device.device_type(),
+                device.device_info().serial_number().unwrap_or("-"),
+                device.device_info().path().to_string_lossy()
+            ),
+            outcome,
+        );
+    }
+
+    explanation.action = if selected_devices == 0 {
+        format!(
+            "Not turning {} Litra device, as no connected device is selected",
+            turn
+        )
+    } else if let Some(percentage) = context.options.idle_brightness_percentage.filter(|_| !on) {
+        format!(
+            "Dimming {} selected Litra device(s) to {}% brightness, as `--idle-brightness-percentage` is set",
+            selected_devices, percentage
+        )
+    } else if !on && context.options.restore_previous_state {
+        format!(
+            "Restoring the previous state of {} selected Litra device(s), as `--restore-previous-state` is enabled",
+            selected_devices
+        )
+    } else {
+        format!(
+            "Turning {} {} selected Litra device(s)",
+            turn, selected_devices
+        )
+    };
+    explanation
+}
+
+/// Explains what the listener will do about the webcam being turned on or off, printing the
+/// explanation if `--explain` is enabled and keeping it for `ctl explain-last`.
+pub fn record_explanation(
+    context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    camera: Option<&str>,
+    on: bool,
+) {
+    let explanation = explain_webcam_event(context, device_filter, camera, on);
+    if context.options.explain {
+        logging::log(
+            &Event {
+                kind: "explanation",
+                message: explanation.describe().trim_end().to_string(),
+                camera: camera.map(str::to_string),
+                on: Some(on),
+                ..Event::default()
+            },
+            false,
+        );
+    }
+    context.last_explanation = Some(explanation);
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How many steps a fade with `--fade` is made up of.
+const FADE_STEPS: u32 = 20;
+
+/// What happens to a device once it has faded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeEnd {
+    /// It stays on at the brightness it faded to
+    StayOn,
+    /// It is turned off, having faded down to its dimmest
+    TurnOff,
+}
+
+/// A device's brightness stepping from one level to another over the `--fade` duration, rather
+/// than changing all at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fade {
+    pub from: u16,
+    pub to: u16,
+    started_at: Instant,
+    duration: Duration,
+    next_step_at: Instant,
+    pub end: FadeEnd,
+}
+
+impl Fade {
+    pub fn new(from: u16, to: u16, duration: Duration, end: FadeEnd) -> Self {
+        Fade::starting_at(Instant::now(), from, to, duration, end)
+    }
+
+    fn starting_at(
+        started_at: Instant,
+        from: u16,
+        to: u16,
+        duration: Duration,
+        end: FadeEnd,
+    ) -> Self {
+        Fade {
+            from,
+            to,
+            started_at,
+            duration,
+            next_step_at: started_at + duration / FADE_STEPS,
+            end,
+        }
+    }
+
+    fn is_finished_at(&self, now: Instant) -> bool {
+        now >= self.started_at + self.duration
+    }
+
+    /// The brightness the device should have reached by `now`.
+    fn brightness_at(&self, now: Instant) -> u16 {
+        if self.is_finished_at(now) {
+            return self.to;
+        }
+
+        let progress =
+            now.duration_since(self.started_at).as_secs_f64() / self.duration.as_secs_f64();
+        let brightness_in_lumen =
+            f64::from(self.from) + (f64::from(self.to) - f64::from(self.from)) * progress;
+        brightness_in_lumen.round() as u16
+    }
+}
+
+/// What to do for a device whose fade has a step due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeStep {
+    /// Set the device to this brightness
+    Brightness(u16),
+    /// The fade is over, so set the device to its final brightness and do what `end` says
+    Finished { to: u16, end: FadeEnd },
+}
+
+/// The fades in progress, by the serial number of the device being faded. The device worker steps
+/// them between other requests, so a fade doesn't hold it up, and several devices fade at once.
+#[derive(Debug, Default)]
+pub struct Fades {
+    fades: HashMap<String, Fade>,
+}
+
+impl Fades {
+    /// Starts fading the device with `serial_number`, replacing any fade it already had.
+    pub fn start(&mut self, serial_number: String, fade: Fade) {
+        self.fades.insert(serial_number, fade);
+    }
+
+    /// Stops fading the device with `serial_number`, e.g. because it is being changed again,
+    /// returning the fade if there was one.
+    pub fn cancel(&mut self, serial_number: &str) -> Option<Fade> {
+        self.fades.remove(serial_number)
+    }
+
+    /// How long until the next step of any fade is due, if there are fades in progress.
+    pub fn next_step_in(&self, now: Instant) -> Option<Duration> {
+        self.fades
+            .values()
+            .map(|fade| {
+                fade.next_step_at
+                    .min(fade.started_at + fade.duration)
+                    .saturating_duration_since(now)
+            })
+            .min()
+    }
+
+    /// Takes the steps which are due by `now`, by serial number. Finished fades are removed.
+    pub fn take_due_steps(&mut self, now: Instant) -> Vec<(String, FadeStep)> {
+        let mut steps = Vec::new();
+        self.fades.retain(|serial_number, fade| {
+            if fade.is_finished_at(now) {
+                steps.push((
+                    serial_number.clone(),
+                    FadeStep::Finished {
+                        to: fade.to,
+                        end: fade.end,
+                    },
+                ));
+                return false;
+            }
+
+            if now >= fade.next_step_at {
+                let interval = fade.duration / FADE_STEPS;
+                while fade.next_step_at <= now {
+                    fade.next_step_at += interval;
+                }
+                steps.push((
+                    serial_number.clone(),
+                    FadeStep::Brightness(fade.brightness_at(now)),
+                ));
+            }
+            true
+        });
+        steps.sort_by(|(a, _), (b, _)| a.cmp(b));
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn steps_brightness_towards_the_target() {
+        let start = Instant::now();
+        let fade = Fade::starting_at(start, 20, 220, SECOND, FadeEnd::StayOn);
+        assert_eq!(fade.brightness_at(start), 20);
+        assert_eq!(fade.brightness_at(start + SECOND / 4), 70);
+        assert_eq!(fade.brightness_at(start + SECOND / 2), 120);
+        assert_eq!(fade.brightness_at(start + SECOND), 220);
+        assert_eq!(fade.brightness_at(start + SECOND * 2), 220);
+
+        let fade = Fade::starting_at(start, 220, 20, SECOND, FadeEnd::TurnOff);
+        assert_eq!(fade.brightness_at(start + SECOND / 2), 120);
+    }
+
+    #[test]
+    fn steps_every_device_which_is_due() {
+        let start = Instant::now();
+        let mut fades = Fades::default();
+        assert_eq!(fades.next_step_in(start), None);
+
+        fades.start(
+            "A".to_string(),
+            Fade::starting_at(start, 20, 220, SECOND, FadeEnd::StayOn),
+        );
+        fades.start(
+            "B".to_string(),
+            Fade::starting_at(start, 220, 20, SECOND, FadeEnd::TurnOff),
+        );
+        assert_eq!(fades.next_step_in(start), Some(SECOND / FADE_STEPS));
+
+        // Nothing is due before the first step
+        assert_eq!(fades.take_due_steps(start), Vec::new());
+
+        let now = start + SECOND / 2;
+        assert_eq!(
+            fades.take_due_steps(now),
+            vec![
+                ("A".to_string(), FadeStep::Brightness(120)),
+                ("B".to_string(), FadeStep::Brightness(120)),
+            ]
+        );
+        // Missed steps are skipped rather than caught up on
+        assert_eq!(fades.take_due_steps(now), Vec::new());
+        assert_eq!(fades.next_step_in(now), Some(SECOND / FADE_STEPS));
+
+        assert_eq!(
+            fades.take_due_steps(start + SECOND),
+            vec![
+                (
+                    "A".to_string(),
+                    FadeStep::Finished {
+                        to: 220,
+                        end: FadeEnd::StayOn
+                    }
+                ),
+                (
+                    "B".to_string(),
+                    FadeStep::Finished {
+                        to: 20,
+                        end: FadeEnd::TurnOff
+                    }
+                ),
+            ]
+        );
+        assert_eq!(fades.next_step_in(start + SECOND), None);
+    }
+
+    #[test]
+    fn cancelling_stops_a_fade() {
+        let start = Instant::now();
+        let mut fades = Fades::default();
+        let fade = Fade::starting_at(start, 20, 220, SECOND, FadeEnd::StayOn);
+        fades.start("A".to_string(), fade);
+        assert_eq!(fades.cancel("A"), Some(fade));
+        assert_eq!(fades.cancel("A"), None);
+        assert_eq!(fades.take_due_steps(start + SECOND), Vec::new());
+    }
+}
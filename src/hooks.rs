@@ -0,0 +1,367 @@
+use crate::duration::{format_duration, parse_duration};
+use crate::logging::{log_error, log_info};
+use crate::queue::{QueueStatus, TaskPool};
+use std::collections::VecDeque;
+use std::fmt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+/// How many hooks can run at once.
+const HOOK_CONCURRENCY: usize = 4;
+
+/// How many hooks can be waiting to run or running at once. Any more are dropped.
+const HOOK_QUEUE_CAPACITY: usize = 16;
+
+/// The most hooks that can start within a period, set with `--hook-rate-limit`, e.g. `5/1m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    count: usize,
+    per: Duration,
+}
+
+pub fn parse_rate_limit(value: &str) -> Result<RateLimit, String> {
+    let invalid = || {
+        format!(
+            "Invalid rate limit `{}`. Use a number of hooks and a duration, e.g. `1/30s` or `5/1m`.",
+            value
+        )
+    };
+
+    let (count, per) = value.split_once('/').ok_or_else(invalid)?;
+    let count: usize = count.trim().parse().map_err(|_| invalid())?;
+    if count == 0 {
+        return Err(format!(
+            "Invalid rate limit `{}`. At least one hook must be allowed.",
+            value
+        ));
+    }
+
+    Ok(RateLimit {
+        count,
+        per: parse_duration(per)?,
+    })
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.count, format_duration(self.per))
+    }
+}
+
+/// A hook waiting to start.
+struct HookRun {
+    command: String,
+    on: bool,
+    camera: Option<String>,
+    /// The app using the webcam, if the event source can tell
+    app: Option<String>,
+}
+
+/// What `--hook-rate-limit` needs to know about the hooks started recently.
+#[derive(Default)]
+struct RateLimitState {
+    /// When hooks were started within the last rate limit period, oldest first
+    started_at: VecDeque<Instant>,
+    /// Whether the last hook to start was for the webcam turning on
+    last_started_on: Option<bool>,
+    /// The latest hook held back by the rate limit. Earlier ones are replaced, since the webcam
+    /// has changed since.
+    deferred: Option<HookRun>,
+}
+
+/// Commands to run when the webcam is turned on or off, set with `--on-camera-on` and
+/// `--on-camera-off`.
+pub struct CameraHooks {
+    pub on_camera_on: Option<String>,
+    pub on_camera_off: Option<String>,
+    pub timeout: Duration,
+    /// How long the webcam must stay on before the hooks run, set with
+    /// `--hook-min-session-duration`
+    pub min_session_duration: Option<Duration>,
+    pub rate_limit: Option<RateLimit>,
+    /// The `--on-camera-on` hook, waiting for the webcam to have been on for long enough
+    pending: Option<JoinHandle<()>>,
+    /// Runs the hooks, so slow hooks can't pile up without limit
+    pool: TaskPool,
+    rate_limit_state: Arc<Mutex<RateLimitState>>,
+}
+
+impl Default for CameraHooks {
+    fn default() -> Self {
+        CameraHooks::new(None, None, Duration::default(), None, None)
+    }
+}
+
+impl CameraHooks {
+    pub fn new(
+        on_camera_on: Option<String>,
+        on_camera_off: Option<String>,
+        timeout: Duration,
+        min_session_duration: Option<Duration>,
+        rate_limit: Option<RateLimit>,
+    ) -> Self {
+        CameraHooks {
+            on_camera_on,
+            on_camera_off,
+            timeout,
+            min_session_duration,
+            rate_limit,
+            pending: None,
+            pool: TaskPool::new("hooks", HOOK_CONCURRENCY, HOOK_QUEUE_CAPACITY),
+            rate_limit_state: Arc::default(),
+        }
+    }
+
+    /// Starts the hook for the webcam being turned on or off, if there is one, without waiting
+    /// for it to finish. With `--hook-min-session-duration`, the `--on-camera-on` hook only runs
+    /// once the webcam has been on for long enough, and neither hook runs if it is turned off
+    /// sooner. Must be called from within a Tokio runtime.
+    pub fn run(&mut self, on: bool, camera: Option<&str>, app: Option<&str>) {
+        if !on {
+            if let Some(pending) = self.pending.take() {
+                if !pending.is_finished() {
+                    pending.abort();
+                    log_info!("Not running hooks, as the webcam was turned off within the minimum session duration");
+                    return;
+                }
+            }
+        }
+
+        let command = if on {
+            &self.on_camera_on
+        } else {
+            &self.on_camera_off
+        };
+
+        match self.min_session_duration {
+            Some(min_session_duration) if on => {
+                let command = command.clone();
+                let camera = camera.map(str::to_string);
+                let app = app.map(str::to_string);
+                let limiter = self.limiter();
+
+                // Wait even if there's no `--on-camera-on` hook, so we know whether to run the
+                // `--on-camera-off` hook
+                self.pending = Some(tokio::spawn(async move {
+                    tokio::time::sleep(min_session_duration).await;
+                    if let Some(command) = command {
+                        limiter.start(HookRun {
+                            command,
+                            on,
+                            camera,
+                            app,
+                        });
+                    }
+                }));
+            }
+            _ => {
+                if let Some(command) = command.clone() {
+                    self.limiter().start(HookRun {
+                        command,
+                        on,
+                        camera: camera.map(str::to_string),
+                        app: app.map(str::to_string),
+                    });
+                }
+            }
+        }
+    }
+
+    fn limiter(&self) -> HookLimiter {
+        HookLimiter {
+            pool: self.pool.clone(),
+            timeout: self.timeout,
+            rate_limit: self.rate_limit,
+            state: self.rate_limit_state.clone(),
+        }
+    }
+
+    pub fn queue_status(&self) -> QueueStatus {
+        self.pool.status()
+    }
+}
+
+/// Starts hooks, holding them back when `--hook-rate-limit` has been reached.
+#[derive(Clone)]
+struct HookLimiter {
+    pool: TaskPool,
+    timeout: Duration,
+    rate_limit: Option<RateLimit>,
+    state: Arc<Mutex<RateLimitState>>,
+}
+
+impl HookLimiter {
+    /// Starts `run` now if the rate limit allows. Otherwise, it is held back until the rate limit
+    /// allows another hook, replacing any hook already held back, so a flapping webcam only
+    /// results in a hook for where it ended up. Must be called from within a Tokio runtime.
+    fn start(&self, run: HookRun) {
+        let Some(rate_limit) = self.rate_limit else {
+            start(&self.pool, run, self.timeout);
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        while state
+            .started_at
+            .front()
+            .is_some_and(|started_at| now.duration_since(*started_at) >= rate_limit.per)
+        {
+            state.started_at.pop_front();
+        }
+
+        if state.deferred.is_none() && state.started_at.len() < rate_limit.count {
+            state.started_at.push_back(now);
+            state.last_started_on = Some(run.on);
+            drop(state);
+            start(&self.pool, run, self.timeout);
+            return;
+        }
+
+        log_info!(
+            "Holding back hook `{}`, as `--hook-rate-limit` ({}) has been reached",
+            run.command,
+            rate_limit
+        );
+        if state.deferred.replace(run).is_some() {
+            // A hook is already held back, and will be replaced by this one when it starts
+            return;
+        }
+
+        // The oldest hook is always within the period, or we wouldn't have been held back
+        let wait_until = state
+            .started_at
+            .front()
+            .map_or(now, |started_at| *started_at + rate_limit.per);
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(wait_until.into()).await;
+            limiter.start_deferred();
+        });
+    }
+
+    /// Starts the hook held back by the rate limit, unless the webcam has since gone back to the
+    /// state the last hook ran for, in which case there's nothing to tell the hook about.
+    fn start_deferred(&self) {
+        let mut state = self.state.lock().unwrap();
+        let Some(run) = state.deferred.take() else {
+            return;
+        };
+
+        if state.last_started_on == Some(run.on) {
+            log_info!(
+                "Not running hook `{}`, as the webcam is back to how it was when the last hook ran",
+                run.command
+            );
+            return;
+        }
+
+        state.started_at.push_back(Instant::now());
+        state.last_started_on = Some(run.on);
+        drop(state);
+        start(&self.pool, run, self.timeout);
+    }
+}
+
+/// Queues `run` to run as the hook for the webcam being turned on or off, and to be stopped if it
+/// is still running after `timeout`.
+fn start(pool: &TaskPool, run: HookRun, timeout: Duration) {
+    let HookRun {
+        command,
+        on,
+        camera,
+        app,
+    } = run;
+    let queued_command = command.clone();
+    let queued = pool.spawn(async move {
+        log_info!("Running hook `{}`", command);
+
+        let mut child = match shell_command(&command)
+            .env("LITRA_AUTOTOGGLE_CAMERA", if on { "on" } else { "off" })
+            .env("LITRA_AUTOTOGGLE_CAMERA_DEVICE", camera.unwrap_or_default())
+            .env("LITRA_AUTOTOGGLE_APP", app.unwrap_or_default())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(error) => {
+                log_error!("Failed to run hook `{}`: {}", command, error);
+                return;
+            }
+        };
+
+        match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(Ok(status)) if status.success() => {}
+            Ok(Ok(status)) => log_error!("Hook `{}` failed - {}", command, status),
+            Ok(Err(error)) => log_error!("Failed to wait for hook `{}`: {}", command, error),
+            Err(_) => {
+                log_error!(
+                    "Hook `{}` was still running after {}, so it has been stopped",
+                    command,
+                    format_duration(timeout)
+                );
+                let _ = child.kill().await;
+            }
+        }
+    });
+
+    if !queued {
+        log_error!(
+            "Not running hook `{}`, as too many hooks are already waiting to run",
+            queued_command
+        );
+    }
+}
+
+/// Builds a command which runs `command` with the platform's shell, so hooks can use pipes and
+/// arguments.
+fn shell_command(command: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut shell_command = Command::new("sh");
+        shell_command.arg("-c").arg(command);
+        shell_command
+    }
+
+    #[cfg(windows)]
+    {
+        let mut shell_command = Command::new("cmd");
+        shell_command.arg("/C").arg(command);
+        shell_command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rate_limits() {
+        assert_eq!(
+            parse_rate_limit("5/1m"),
+            Ok(RateLimit {
+                count: 5,
+                per: Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            parse_rate_limit(" 1 /30"),
+            Ok(RateLimit {
+                count: 1,
+                per: Duration::from_secs(30)
+            })
+        );
+        assert_eq!(parse_rate_limit("5/1m").unwrap().to_string(), "5/60s");
+    }
+
+    #[test]
+    fn rejects_invalid_rate_limits() {
+        for value in ["", "5", "0/1m", "-1/1m", "five/1m", "5/", "5/0s", "5/1d"] {
+            assert!(parse_rate_limit(value).is_err(), "{} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use crate::control::{
+    get_listener_status, handle_control_command, handle_set_command, ControlCommand,
+    REQUEST_TIMEOUT,
+};
+use crate::duration::parse_duration;
+use crate::event_source::EventSourceHealth;
+use crate::logging::{log_error, log_info};
+use crate::worker::DeviceWorker;
+use crate::{DeviceFilter, TriggerSource};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The longest request head (request line and headers) we'll read from a client. Requests don't
+/// need a body, so anything longer than this is a misbehaving client.
+const MAX_REQUEST_LENGTH: u64 = 8192;
+
+/// The dashboard served at `GET /`, which uses the rest of the API to control the Litra from a
+/// browser.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// The headers we check before handling a request.
+#[derive(Debug, Default)]
+struct Headers {
+    host: Option<String>,
+    origin: Option<String>,
+}
+
+impl Headers {
+    fn add(&mut self, line: &str) {
+        let Some((name, value)) = line.split_once(':') else {
+            return;
+        };
+        let value = Some(value.trim().to_string());
+        if name.trim().eq_ignore_ascii_case("host") {
+            self.host = value;
+        } else if name.trim().eq_ignore_ascii_case("origin") {
+            self.origin = value;
+        }
+    }
+}
+
+/// Whether `host`, from a `Host` header, is an IP address or `localhost`, with or without a port.
+fn is_address_or_localhost(host: &str) -> bool {
+    if host.parse::<SocketAddr>().is_ok() || host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    if host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .is_some_and(|host| host.parse::<IpAddr>().is_ok())
+    {
+        return true;
+    }
+
+    let (name, port) = host.split_once(':').unwrap_or((host, ""));
+    name.eq_ignore_ascii_case("localhost") && port.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Checks that a request didn't come from another website, since browsers let any page send
+/// requests to addresses like `127.0.0.1`. Requests from a browser must come from the dashboard
+/// (i.e. have an `Origin` matching the `Host`), and be addressed to an IP address or `localhost`,
+/// so a website can't point its own domain name at the listener (DNS rebinding). Requests from
+/// tools like `curl` have no `Origin`, so are allowed.
+fn check_origin(headers: &Headers) -> Result<(), String> {
+    if let Some(host) = &headers.host {
+        if !is_address_or_localhost(host) {
+            return Err(format!(
+                "Requests must be made to an IP address or `localhost`, not `{}`",
+                host
+            ));
+        }
+    }
+
+    match (&headers.origin, &headers.host) {
+        (None, _) => Ok(()),
+        (Some(origin), Some(host)) if origin.eq_ignore_ascii_case(&format!("http://{}", host)) => {
+            Ok(())
+        }
+        (Some(origin), _) => Err(format!("Requests from `{}` aren't allowed", origin)),
+    }
+}
+
+/// Decodes a query string component, where spaces can be given as `+` and other characters as
+/// `%` followed by two hex digits, e.g. `Key%20Light`.
+fn percent_decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let digits = rest
+                    .get(..2)
+                    .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|digits| std::str::from_utf8(digits).ok())
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| format!("Invalid percent-encoding in `{}`", value))?;
+                bytes.push(digits);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| format!("`{}` isn't valid UTF-8 once decoded", value))
+}
+
+/// Splits a query string like `serial_number=ABC%20123&duration=45m` into decoded names and
+/// values.
+fn parse_query(query: &str) -> Result<Vec<(String, String)>, String> {
+    query
+        .split('&')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            Ok((percent_decode(name)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+/// The body of a response.
+enum Body {
+    Json(serde_json::Value),
+    Html(&'static str),
+}
+
+/// A response to send back to an HTTP client.
+struct Response {
+    status: &'static str,
+    body: Option<Body>,
+}
+
+impl Response {
+    fn new(status: &'static str, body: Option<serde_json::Value>) -> Self {
+        Response {
+            status,
+            body: body.map(Body::Json),
+        }
+    }
+
+    fn html(status: &'static str, html: &'static str) -> Self {
+        Response {
+            status,
+            body: Some(Body::Html(html)),
+        }
+    }
+
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Response::new(status, Some(serde_json::json!({ "error": message.into() })))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", self.status);
+        let body = match &self.body {
+            Some(Body::Json(body)) => Some(("application/json", body.to_string())),
+            Some(Body::Html(html)) => Some(("text/html; charset=utf-8", html.to_string())),
+            None => None,
+        };
+        match body {
+            Some((content_type, body)) => {
+                response.push_str(&format!(
+                    "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                    content_type,
+                    body.len(),
+                    body
+                ));
+            }
+            None => response.push_str("\r\n"),
+        }
+
+        response.into_bytes()
+    }
+}
+
+/// Accepts connections on the HTTP listener and handles their requests. Each connection is served
+/// in its own task, so a slow client doesn't hold up the others, and is limited to a single short
+/// request.
+///
+/// The API exposes `GET /status`, which returns whether automatic toggling is paused or overridden,
+/// the health of the event source and the state of the connected Litra devices as JSON, and
+/// `GET /health`, which only returns the health of the event source, without waiting for the
+/// devices. The
+/// `POST /on`, `POST /off`, `POST /pause`, `POST /resume`, `POST /force-on?duration=45m`,
+/// `POST /force-off?duration=45m`, `POST /brightness-up`, `POST /brightness-down`,
+/// `POST /temperature-up` and `POST /temperature-down` endpoints return `204 No Content` on
+/// success, and take a `serial_number` parameter to only control one device. `GET /` serves a
+/// dashboard which uses them, so the Litra can be controlled from a phone's browser. Requests from
+/// other websites are rejected, so visiting a web page can't control the Litra.
+pub async fn serve_http(
+    listener: TcpListener,
+    worker: DeviceWorker,
+    event_source: EventSourceHealth,
+    device_filter: DeviceFilter,
+    require_device: bool,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                log_error!("Failed to accept HTTP connection: {}", error);
+                continue;
+            }
+        };
+
+        tokio::spawn(handle_connection(
+            stream,
+            worker.clone(),
+            event_source.clone(),
+            device_filter.clone(),
+            require_device,
+        ));
+    }
+}
+
+/// Reads a request from an HTTP client, and sends back the response.
+async fn handle_connection(
+    stream: TcpStream,
+    worker: DeviceWorker,
+    event_source: EventSourceHealth,
+    device_filter: DeviceFilter,
+    require_device: bool,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_LENGTH));
+    let read_result = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+
+        // Read the headers, up to the blank line that ends them
+        let mut headers = Headers::default();
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 0 && !header.trim().is_empty() {
+            headers.add(&header);
+            header.clear();
+        }
+
+        Ok::<_, std::io::Error>((request_line, headers))
+    })
+    .await;
+
+    let response = match read_result {
+        Ok(Ok((request_line, headers))) => {
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or_default();
+            let path = parts.next().unwrap_or_default();
+
+            match check_origin(&headers) {
+                Ok(()) => {
+                    handle_request(
+                        method,
+                        path,
+                        &worker,
+                        &event_source,
+                        &device_filter,
+                        require_device,
+                    )
+                    .await
+                }
+                Err(message) => {
+                    log_error!(
+                        "Rejected `{} {}` request on HTTP API: {}",
+                        method,
+                        path,
+                        message
+                    );
+                    Response::error("403 Forbidden", message)
+                }
+            }
+        }
+        Ok(Err(error)) => Response::error(
+            "400 Bad Request",
+            format!("Failed to read request: {}", error),
+        ),
+        Err(_) => Response::error("408 Request Timeout", "Timed out waiting for request"),
+    };
+
+    if let Err(error) = writer.write_all(&response.to_bytes()).await {
+        log_error!("Failed to write HTTP response: {}", error);
+    }
+}
+
+async fn handle_request(
+    method: &str,
+    path: &str,
+    worker: &DeviceWorker,
+    event_source: &EventSourceHealth,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+) -> Response {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    if path == "/" {
+        return if method == "GET" {
+            Response::html("200 OK", DASHBOARD)
+        } else {
+            Response::error("405 Method Not Allowed", "`/` only supports `GET` requests")
+        };
+    }
+
+    // Only reads the event source's health, so it answers even when the device worker is busy
+    if path == "/health" {
+        return if method == "GET" {
+            match serde_json::to_value(event_source.status()) {
+                Ok(body) => Response::new("200 OK", Some(body)),
+                Err(error) => Response::error("500 Internal Server Error", error.to_string()),
+            }
+        } else {
+            Response::error(
+                "405 Method Not Allowed",
+                "`/health` only supports `GET` requests",
+            )
+        };
+    }
+
+    let command = match path {
+        "/status" => ControlCommand::Status,
+        "/on" => ControlCommand::On,
+        "/off" => ControlCommand::Off,
+        "/pause" => ControlCommand::Pause,
+        "/resume" => ControlCommand::Resume,
+        "/snooze" => ControlCommand::Snooze,
+        "/force-on" => ControlCommand::ForceOn,
+        "/force-off" => ControlCommand::ForceOff,
+        "/brightness-up" => ControlCommand::BrightnessUp,
+        "/brightness-down" => ControlCommand::BrightnessDown,
+        "/temperature-up" => ControlCommand::TemperatureUp,
+        "/temperature-down" => ControlCommand::TemperatureDown,
+        "/set" => ControlCommand::Set,
+        _ => return Response::error("404 Not Found", format!("Unknown path `{}`", path)),
+    };
+
+    let expected_method = if command == ControlCommand::Status {
+        "GET"
+    } else {
+        "POST"
+    };
+    if method != expected_method {
+        return Response::error(
+            "405 Method Not Allowed",
+            format!("`{}` only supports `{}` requests", path, expected_method),
+        );
+    }
+
+    let parameters = match parse_query(query) {
+        Ok(parameters) => parameters,
+        Err(message) => return Response::error("400 Bad Request", message),
+    };
+    let parameter = |name: &str| {
+        parameters
+            .iter()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let device_filter = &match parameter("serial_number") {
+        Some(serial_number) => match device_filter.only(serial_number) {
+            Ok(device_filter) => device_filter,
+            Err(message) => return Response::error("400 Bad Request", message),
+        },
+        None => device_filter.clone(),
+    };
+
+    if command == ControlCommand::Set {
+        log_info!("Received `{} {}` request on HTTP API", method, path);
+        return match handle_set_command(worker, parameter("setting"), parameter("value")).await {
+            Ok(_) => Response::new("204 No Content", None),
+            Err(message) => Response::error("400 Bad Request", message),
+        };
+    }
+
+    let duration = match parameter("duration").map(parse_duration).transpose() {
+        Ok(duration) => duration,
+        Err(message) => return Response::error("400 Bad Request", message),
+    };
+    if duration.is_none()
+        && matches!(
+            command,
+            ControlCommand::ForceOn | ControlCommand::ForceOff | ControlCommand::Snooze
+        )
+    {
+        return Response::error(
+            "400 Bad Request",
+            format!(
+                "`{}` requires a `duration` parameter, e.g. `?duration=45m`",
+                path
+            ),
+        );
+    }
+
+    log_info!("Received `{} {}` request on HTTP API", method, path);
+
+    if command == ControlCommand::Status {
+        return match get_listener_status(worker, event_source, device_filter).await {
+            Ok(status) => match serde_json::to_value(status) {
+                Ok(body) => Response::new("200 OK", Some(body)),
+                Err(error) => Response::error("500 Internal Server Error", error.to_string()),
+            },
+            Err(message) => Response::error("500 Internal Server Error", message),
+        };
+    }
+
+    match handle_control_command(
+        command,
+        duration,
+        worker,
+        event_source,
+        device_filter,
+        require_device,
+        TriggerSource::Remote,
+    )
+    .await
+    {
+        Ok(_) => Response::new("204 No Content", None),
+        Err(message) => Response::error("500 Internal Server Error", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(host: Option<&str>, origin: Option<&str>) -> Headers {
+        Headers {
+            host: host.map(str::to_string),
+            origin: origin.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn reads_host_and_origin_headers() {
+        let mut headers = Headers::default();
+        headers.add("HOST: 127.0.0.1:8080\r\n");
+        headers.add("origin:http://127.0.0.1:8080\r\n");
+        headers.add("Accept: */*\r\n");
+        assert_eq!(headers.host.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(headers.origin.as_deref(), Some("http://127.0.0.1:8080"));
+    }
+
+    #[test]
+    fn allows_requests_from_tools_and_the_dashboard() {
+        for (host, origin) in [
+            (None, None),
+            (Some("127.0.0.1:8080"), None),
+            (Some("127.0.0.1:8080"), Some("http://127.0.0.1:8080")),
+            (Some("192.168.1.20:8080"), Some("http://192.168.1.20:8080")),
+            (Some("[::1]:8080"), Some("http://[::1]:8080")),
+            (Some("[::1]"), None),
+            (Some("LOCALHOST:8080"), Some("http://localhost:8080")),
+        ] {
+            assert_eq!(check_origin(&headers(host, origin)), Ok(()), "{:?}", host);
+        }
+    }
+
+    #[test]
+    fn rejects_requests_from_other_websites() {
+        for (host, origin) in [
+            (Some("127.0.0.1:8080"), Some("https://example.com")),
+            (Some("127.0.0.1:8080"), Some("null")),
+            (Some("127.0.0.1:8080"), Some("http://127.0.0.1:9090")),
+            (None, Some("http://127.0.0.1:8080")),
+            (Some("example.com:8080"), Some("http://example.com:8080")),
+            (Some("example.com:8080"), None),
+            (Some("localhost.example.com"), None),
+        ] {
+            assert!(check_origin(&headers(host, origin)).is_err(), "{:?}", host);
+        }
+    }
+
+    #[test]
+    fn decodes_query_parameters() {
+        assert_eq!(
+            parse_query("serial_number=ABC%20123&value=warm+white&setting=fade&empty=&flag"),
+            Ok(vec![
+                ("serial_number".to_string(), "ABC 123".to_string()),
+                ("value".to_string(), "warm white".to_string()),
+                ("setting".to_string(), "fade".to_string()),
+                ("empty".to_string(), String::new()),
+                ("flag".to_string(), String::new()),
+            ])
+        );
+        assert_eq!(parse_query(""), Ok(Vec::new()));
+        assert_eq!(percent_decode("caf%C3%A9%2b%2F"), Ok("café+/".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_percent_encoding() {
+        for value in ["100%", "%4", "%zz", "%+1", "%FF"] {
+            assert!(percent_decode(value).is_err(), "{} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::profiles::{self, default_profiles_file_path, DEFAULT_PROFILE};
+use crate::{
+    describe_parse_error, get_connected_devices_info, parse_profile, Cli, CliError, CliResult,
+};
+use clap::Parser;
+use litra::Litra;
+use serde_json::{Map, Value};
+use std::io::Write;
+
+fn other_error(message: String) -> CliError {
+    CliError::IoError(std::io::Error::other(message))
+}
+
+/// Asks `question`, returning the answer without surrounding whitespace. Fails if there's no
+/// more input, so `init` doesn't spin when it isn't run in a terminal.
+fn ask(question: &str) -> Result<String, CliError> {
+    print!("{} ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        return Err(CliError::IoError(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "No answer was given",
+        )));
+    }
+
+    Ok(answer.trim().to_string())
+}
+
+/// Checks that `value` is valid for `--name`, using the same checks as the command line, so the
+/// profile we write is one the listener will accept.
+fn validate_option(name: &str, value: &str) -> Result<(), String> {
+    let option = format!("--{}", name);
+    Cli::try_parse_from(["litra-autotoggle", option.as_str(), value])
+        .map(|_| ())
+        .map_err(|error| describe_parse_error(&error))
+}
+
+/// Asks `question` until the answer is blank or a valid value for `--name`, returning the value
+/// if one was given.
+fn ask_for_option(question: &str, name: &str) -> Result<Option<Value>, CliError> {
+    loop {
+        let answer = ask(question)?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+
+        match validate_option(name, &answer) {
+            Ok(()) => return Ok(Some(profiles::argument_to_value(answer))),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+/// Asks which of `choices` to use, by the numbers they were listed with, until the answer is
+/// blank or a comma-separated list of those numbers.
+fn ask_for_choices<'a, T>(question: &str, choices: &'a [T]) -> Result<Vec<&'a T>, CliError> {
+    loop {
+        let answer = ask(question)?;
+        let selected: Option<Vec<&T>> = answer
+            .split(',')
+            .map(str::trim)
+            .filter(|number| !number.is_empty())
+            .map(|number| {
+                number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| choices.get(number.checked_sub(1)?))
+            })
+            .collect();
+
+        match selected {
+            Some(selected) => return Ok(selected),
+            None => println!(
+                "Enter numbers between 1 and {}, separated by commas",
+                choices.len()
+            ),
+        }
+    }
+}
+
+/// Asks which Litra devices to use, returning their serial numbers. Devices without a serial
+/// number can't be picked, since there's no other way to choose them that survives a reboot.
+fn ask_for_devices(args: &Cli) -> Result<Vec<String>, CliError> {
+    let context = Litra::new()?;
+    let devices: Vec<(String, String)> =
+        get_connected_devices_info(&context, &args.device_filter())
+            .into_iter()
+            .filter_map(|device| Some((device.device_type, device.serial_number?)))
+            .collect();
+
+    if devices.is_empty() {
+        println!("No Litra devices with serial numbers found, so the first device found when the listener starts will be used");
+        return Ok(Vec::new());
+    }
+
+    println!("Found these Litra devices:");
+    for (number, (device_type, serial_number)) in devices.iter().enumerate() {
+        println!(
+            "  {}. {} (serial number: {})",
+            number + 1,
+            device_type,
+            serial_number
+        );
+    }
+    let selected = ask_for_choices(
+        "Which Litra devices should be turned on and off? Enter their numbers separated by commas, or leave blank to use the first one found:",
+        &devices,
+    )?;
+
+    Ok(selected
+        .into_iter()
+        .map(|(_, serial_number)| serial_number.clone())
+        .collect())
+}
+
+/// Asks which webcams to watch, returning the options which select them. Webcams are chosen by
+/// name where the driver reports one, since paths like `/dev/video0` can change between reboots.
+#[cfg(target_os = "linux")]
+fn ask_for_cameras(args: &Cli) -> Result<Vec<(&'static str, String)>, CliError> {
+    let mut cameras: Vec<(&'static str, String)> = Vec::new();
+    for path in crate::get_video_device_paths(&args.watch_dir)? {
+        let camera = match crate::get_video_device_name(&path) {
+            Some(name) => ("video-device-name", name),
+            None => ("video-device", path.to_string_lossy().to_string()),
+        };
+        // Webcams often have several video devices with the same name, e.g. for metadata
+        if !cameras.contains(&camera) {
+            cameras.push(camera);
+        }
+    }
+
+    if cameras.is_empty() {
+        println!("No webcams found, so any webcam will be watched");
+        return Ok(Vec::new());
+    }
+
+    println!("Found these webcams:");
+    for (number, (_, camera)) in cameras.iter().enumerate() {
+        println!("  {}. {}", number + 1, camera);
+    }
+    let selected = ask_for_choices(
+        "Which webcams should turn your Litra on? Enter their numbers separated by commas, or leave blank to watch them all:",
+        &cameras,
+    )?;
+
+    Ok(selected.into_iter().cloned().collect())
+}
+
+/// Sets up a profile by asking which devices and webcams to use and how the Litra should be
+/// turned on, then writes it to the profiles file, for `init`.
+pub fn run_init_wizard(args: &Cli) -> CliResult {
+    let path = args
+        .profiles_file
+        .clone()
+        .or_else(default_profiles_file_path)
+        .ok_or_else(|| {
+            other_error(
+                "Unable to determine where to store the profiles file. Choose a path with `--profiles-file`.".to_string(),
+            )
+        })?;
+
+    let profile = match &args.profile {
+        Some(profile) => profile.clone(),
+        None => loop {
+            let answer = ask(&format!(
+                "What should this profile be called? Leave blank for `{}`:",
+                DEFAULT_PROFILE
+            ))?;
+            if answer.is_empty() {
+                break DEFAULT_PROFILE.to_string();
+            }
+            match parse_profile(&answer) {
+                Ok(profile) => break profile,
+                Err(message) => println!("{}", message),
+            }
+        },
+    };
+
+    if profiles::has_profile(&path, &profile).map_err(other_error)? {
+        let answer = ask(&format!(
+            "{} already has a profile called `{}`. Replace it? [y/N]",
+            path.display(),
+            profile
+        ))?;
+        if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+            println!("Leaving the profile as it is");
+            return Ok(());
+        }
+    }
+
+    let mut options = Map::new();
+
+    let serial_numbers = ask_for_devices(args)?;
+    if !serial_numbers.is_empty() {
+        options.insert(
+            "serial-number".to_string(),
+            Value::from_iter(serial_numbers),
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    for (name, camera) in ask_for_cameras(args)? {
+        match options.get_mut(name) {
+            Some(Value::Array(cameras)) => cameras.push(Value::String(camera)),
+            _ => {
+                options.insert(name.to_string(), Value::Array(vec![Value::String(camera)]));
+            }
+        }
+    }
+
+    #[cfg_attr(not(target_os = "macos"), allow(unused_mut))]
+    let mut questions = vec![
+        (
+            "brightness-percentage",
+            "How bright should your Litra be when it turns on, as a percentage (e.g. `70`)? Leave blank to keep its current brightness:",
+        ),
+        (
+            "temperature",
+            "What color temperature should it be, in Kelvin (e.g. `4000`)? Leave blank to keep its current temperature:",
+        ),
+        (
+            "fade",
+            "How long should it take to fade in and out (e.g. `500ms`)? Leave blank to turn it on and off straight away:",
+        ),
+    ];
+    #[cfg(target_os = "macos")]
+    questions.push((
+        "off-delay",
+        "How long should it wait after the webcam turns off before turning off (e.g. `10s`)? Leave blank for the default:",
+    ));
+    for (name, question) in questions {
+        if let Some(value) = ask_for_option(question, name)? {
+            options.insert(name.to_string(), value);
+        }
+    }
+
+    // Check the profile as a whole, as the listener will when it loads it
+    let arguments = profiles::profile_to_arguments(&options).map_err(other_error)?;
+    Cli::try_parse_from(std::iter::once("litra-autotoggle".to_string()).chain(arguments)).map_err(
+        |error| {
+            other_error(format!(
+                "The profile isn't valid: {}",
+                describe_parse_error(&error)
+            ))
+        },
+    )?;
+
+    profiles::save_profile(&path, &profile, options).map_err(other_error)?;
+    println!(
+        "Saved profile `{}` to {}. Start the listener with `litra-autotoggle --profile {}`.",
+        profile,
+        path.display(),
+        profile
+    );
+
+    Ok(())
+}
@@ -0,0 +1,434 @@
+#[cfg(unix)]
+use crate::control::default_control_socket_path;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::logging::LogTarget;
+use crate::logging::{self, Event};
+use crate::state::default_state_file_path;
+use crate::Cli;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// The result of checking that an integration will work, for `--check-integrations`.
+#[derive(Debug, Serialize)]
+pub struct IntegrationCheck {
+    integration: &'static str,
+    ok: bool,
+    message: String,
+}
+
+impl IntegrationCheck {
+    fn new(integration: &'static str, result: Result<String, String>) -> Self {
+        let (ok, message) = match result {
+            Ok(message) => (true, message),
+            Err(message) => (false, message),
+        };
+
+        IntegrationCheck {
+            integration,
+            ok,
+            message,
+        }
+    }
+}
+
+/// Checks that each integration enabled in `args` will work, without starting it.
+pub async fn check_integrations(args: &Cli) -> Vec<IntegrationCheck> {
+    let mut checks = Vec::new();
+
+    if let Some(address) = args.listen_http {
+        // The listener is dropped straight away, so the port is free for the HTTP API
+        let result = match tokio::net::TcpListener::bind(address).await {
+            Ok(_) => Ok(format!("able to listen on {}", address)),
+            Err(error) => Err(format!("unable to listen on {}: {}", address, error)),
+        };
+        checks.push(IntegrationCheck::new("HTTP API", result));
+    }
+
+    #[cfg(unix)]
+    if let Some(control_socket) = &args.control_socket {
+        let path = control_socket
+            .clone()
+            .unwrap_or_else(|| default_control_socket_path(args.profile.as_deref()));
+        checks.push(IntegrationCheck::new(
+            "Control socket",
+            check_control_socket_path(&path),
+        ));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if args.notify_external_changes {
+        #[cfg(target_os = "linux")]
+        let program = "notify-send";
+        #[cfg(target_os = "macos")]
+        let program = "osascript";
+
+        checks.push(IntegrationCheck::new(
+            "Desktop notifications",
+            check_program(program),
+        ));
+    }
+
+    if args.on_camera_on.is_some() || args.on_camera_off.is_some() {
+        #[cfg(unix)]
+        let shell = "sh";
+        #[cfg(windows)]
+        let shell = "cmd";
+
+        checks.push(IntegrationCheck::new("Hooks", check_program(shell)));
+    }
+
+    for route in args.notification_routes() {
+        let result = match route.check().await {
+            Ok(message) => Ok(format!("{}: {}", route, message)),
+            Err(error) => Err(format!("{}: {}", route, error)),
+        };
+        checks.push(IntegrationCheck::new("Notification channel", result));
+    }
+
+    for address in &args.elgato_light {
+        checks.push(IntegrationCheck::new(
+            "Elgato Key Light",
+            crate::elgato::check_key_light(address).await,
+        ));
+    }
+
+    if args.remember_settings {
+        let result = match default_state_file_path(args.profile.as_deref()) {
+            Some(path) => Ok(format!("settings will be stored in {}", path.display())),
+            None => Err("unable to determine where to store remembered settings".to_string()),
+        };
+        checks.push(IntegrationCheck::new("Remembered settings", result));
+    }
+
+    checks
+}
+
+/// Prints the results of `checks` as a single block, followed by the same results in JSON format
+/// so they can be picked out of logs by other tools. Returns whether every check passed.
+pub fn log_integration_checks(checks: &[IntegrationCheck]) -> bool {
+    let all_ok = checks.iter().all(|check| check.ok);
+
+    // With `--log-format json`, the checks are logged as a single event
+    if !logging::is_text() {
+        logging::log(
+            &Event {
+                kind: "integration_checks",
+                message: format!(
+                    "Integration checks {}",
+                    if all_ok { "passed" } else { "failed" }
+                ),
+                outcome: Some(if all_ok { "succeeded" } else { "failed" }),
+                details: serde_json::to_value(checks).ok(),
+                ..Event::default()
+            },
+            !all_ok,
+        );
+        return all_ok;
+    }
+
+    if checks.is_empty() {
+        println!("Integration checks: no integrations are enabled");
+    } else {
+        println!("Integration checks:");
+        for check in checks {
+            println!(
+                "- {}: {} ({})",
+                check.integration,
+                if check.ok { "ok" } else { "FAILED" },
+                check.message
+            );
+        }
+    }
+
+    match serde_json::to_string(checks) {
+        Ok(json) => println!("Integration checks (JSON): {}", json),
+        Err(error) => eprintln!("Failed to serialize integration checks: {}", error),
+    }
+
+    all_ok
+}
+
+/// Whether an optional feature is in use, for the capabilities report printed with `--verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CapabilityStatus {
+    /// Enabled, and expected to work
+    Active,
+    /// Not enabled with its option, or not needed in this environment
+    Inactive,
+    /// Not supported on this platform, or enabled but unable to work here
+    Unavailable,
+}
+
+/// A feature in the capabilities report, with why it is or isn't in use.
+#[derive(Debug, Serialize)]
+pub struct Capability {
+    feature: &'static str,
+    status: CapabilityStatus,
+    detail: String,
+}
+
+impl Capability {
+    fn new(feature: &'static str, status: CapabilityStatus, detail: impl Into<String>) -> Self {
+        Capability {
+            feature,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Works out which optional features are in use in this build and environment, so it's clear why
+/// an option seems to do nothing, e.g. because it isn't supported on this platform.
+pub fn get_capabilities(args: &Cli) -> Vec<Capability> {
+    use CapabilityStatus::{Active, Inactive, Unavailable};
+
+    let mut capabilities = Vec::new();
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    capabilities.push(Capability::new(
+        "Webcam detection",
+        Active,
+        args.detection_method
+            .to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string()),
+    ));
+    #[cfg(target_os = "windows")]
+    capabilities.push(Capability::new(
+        "Webcam detection",
+        Active,
+        format!(
+            "checking the webcam consent store every {}",
+            crate::duration::format_duration(args.poll_interval)
+        ),
+    ));
+
+    #[cfg(unix)]
+    capabilities.push(match &args.control_socket {
+        Some(control_socket) => Capability::new(
+            "Control socket",
+            Active,
+            control_socket
+                .clone()
+                .unwrap_or_else(|| default_control_socket_path(args.profile.as_deref()))
+                .display()
+                .to_string(),
+        ),
+        None => Capability::new("Control socket", Inactive, "enable with `--control-socket`"),
+    });
+    #[cfg(windows)]
+    capabilities.push(Capability::new(
+        "Control socket",
+        Unavailable,
+        "not supported on Windows",
+    ));
+
+    capabilities.push(match args.listen_http {
+        Some(address) => Capability::new("HTTP API", Active, format!("on {}", address)),
+        None => Capability::new("HTTP API", Inactive, "enable with `--listen-http`"),
+    });
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    capabilities.push(if args.notify || args.notify_external_changes {
+        #[cfg(target_os = "linux")]
+        let program = "notify-send";
+        #[cfg(target_os = "macos")]
+        let program = "osascript";
+
+        match check_program(program) {
+            Ok(message) => Capability::new("Desktop notifications", Active, message),
+            Err(message) => Capability::new("Desktop notifications", Unavailable, message),
+        }
+    } else {
+        Capability::new(
+            "Desktop notifications",
+            Inactive,
+            "enable with `--notify` or `--notify-external-changes`",
+        )
+    });
+    #[cfg(target_os = "windows")]
+    capabilities.push(Capability::new(
+        "Desktop notifications",
+        Unavailable,
+        "not supported on Windows",
+    ));
+
+    capabilities.push(if args.notification_channel.is_empty() {
+        Capability::new(
+            "Notification channels",
+            Inactive,
+            "enable with `--notification-channel`",
+        )
+    } else {
+        Capability::new(
+            "Notification channels",
+            Active,
+            args.notification_channel
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    });
+
+    capabilities.push(
+        if args.on_camera_on.is_some() || args.on_camera_off.is_some() {
+            Capability::new(
+                "Hooks",
+                Active,
+                match args.hook_rate_limit {
+                    Some(rate_limit) => format!("rate limited to {}", rate_limit),
+                    None => "not rate limited".to_string(),
+                },
+            )
+        } else {
+            Capability::new(
+                "Hooks",
+                Inactive,
+                "enable with `--on-camera-on` or `--on-camera-off`",
+            )
+        },
+    );
+
+    capabilities.push(if args.remember_settings {
+        match default_state_file_path(args.profile.as_deref()) {
+            Some(path) => {
+                Capability::new("Remembered settings", Active, path.display().to_string())
+            }
+            None => Capability::new(
+                "Remembered settings",
+                Unavailable,
+                "unable to determine where to store remembered settings",
+            ),
+        }
+    } else {
+        Capability::new(
+            "Remembered settings",
+            Inactive,
+            "enable with `--remember-settings`",
+        )
+    });
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    capabilities.push(match args.log_target {
+        LogTarget::Stdout => Capability::new("System log", Inactive, "enable with `--log-target`"),
+        #[cfg(target_os = "linux")]
+        LogTarget::Journald if !crate::systemd::is_journal_available() => Capability::new(
+            "System log",
+            Unavailable,
+            "the systemd journal isn't running, so logs are written to stderr",
+        ),
+        target => Capability::new(
+            "System log",
+            Active,
+            target
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_string()),
+        ),
+    });
+
+    #[cfg(target_os = "linux")]
+    capabilities.push(if std::env::var_os("NOTIFY_SOCKET").is_some() {
+        Capability::new(
+            "systemd notifications",
+            Active,
+            match crate::systemd::watchdog_interval() {
+                Some(interval) => format!(
+                    "with watchdog keepalives every {}",
+                    crate::duration::format_duration(interval)
+                ),
+                None => "without the watchdog".to_string(),
+            },
+        )
+    } else {
+        Capability::new(
+            "systemd notifications",
+            Inactive,
+            "not started by systemd with `Type=notify`",
+        )
+    });
+
+    capabilities
+}
+
+/// Prints which optional features are in use as a single block, or with `--log-format json`, as a
+/// single event.
+pub fn log_capabilities(capabilities: &[Capability]) {
+    if !logging::is_text() {
+        logging::log(
+            &Event {
+                kind: "capabilities",
+                message: "Capabilities".to_string(),
+                details: serde_json::to_value(capabilities).ok(),
+                ..Event::default()
+            },
+            false,
+        );
+        return;
+    }
+
+    let mut report = "Capabilities:".to_string();
+    for capability in capabilities {
+        report.push_str(&format!(
+            "\n- {}: {} ({})",
+            capability.feature,
+            match capability.status {
+                CapabilityStatus::Active => "active",
+                CapabilityStatus::Inactive => "inactive",
+                CapabilityStatus::Unavailable => "UNAVAILABLE",
+            },
+            capability.detail
+        ));
+    }
+    logging::info(report);
+}
+
+/// Checks that the control socket can be created at `path`: its directory must exist, and no
+/// other listener can be using it.
+#[cfg(unix)]
+fn check_control_socket_path(path: &std::path::Path) -> Result<String, String> {
+    if path.exists() && std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(format!(
+            "another listener is already using {}",
+            path.display()
+        ));
+    }
+
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() && !directory.is_dir() => {
+            Err(format!("{} doesn't exist", directory.display()))
+        }
+        _ => Ok(format!("able to listen on {}", path.display())),
+    }
+}
+
+/// Checks that `program` can be found on the `PATH`.
+pub fn check_program(program: &str) -> Result<String, String> {
+    match find_program(program) {
+        Some(path) => Ok(format!("found `{}` at {}", program, path.display())),
+        None => Err(format!("`{}` wasn't found on the PATH", program)),
+    }
+}
+
+fn find_program(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+
+    std::env::split_paths(&paths).find_map(|directory| {
+        let path = directory.join(program);
+        if path.is_file() {
+            return Some(path);
+        }
+
+        #[cfg(windows)]
+        {
+            let path = directory.join(format!("{}.exe", program));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+
+        None
+    })
+}
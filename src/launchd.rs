@@ -0,0 +1,160 @@
+use crate::CliError;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The label of the LaunchAgent. This is different from the label Homebrew Services uses, so the
+/// two don't clash.
+const LABEL: &str = "com.timrogers.litra-autotoggle";
+
+/// Returns the label of the LaunchAgent, with each `--profile` getting its own LaunchAgent, e.g.
+/// `com.timrogers.litra-autotoggle.office`.
+fn label(profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("{}.{}", LABEL, profile),
+        None => LABEL.to_string(),
+    }
+}
+
+fn home_directory() -> Result<PathBuf, CliError> {
+    std::env::var_os("HOME").map(PathBuf::from).ok_or_else(|| {
+        CliError::IoError(std::io::Error::other(
+            "Unable to determine where to write the LaunchAgent, as `$HOME` isn't set",
+        ))
+    })
+}
+
+fn plist_path(home_directory: &Path, label: &str) -> PathBuf {
+    home_directory
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", label))
+}
+
+/// Escapes the characters that are special in XML.
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn plist_contents(label: &str, executable: &Path, args: &[String], log_path: &Path) -> String {
+    let program_arguments = std::iter::once(executable.to_string_lossy().to_string())
+        .chain(args.iter().cloned())
+        .map(|argument| format!("        <string>{}</string>\n", escape_xml(&argument)))
+        .collect::<String>();
+    let log_path = escape_xml(&log_path.to_string_lossy());
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        escape_xml(label),
+        program_arguments,
+        log_path,
+        log_path
+    )
+}
+
+/// Runs `launchctl` with `args`, returning an error if it fails.
+fn launchctl(args: &[&str]) -> Result<(), CliError> {
+    let status = Command::new("launchctl").args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CliError::IoError(std::io::Error::other(format!(
+            "`launchctl {}` failed - {}",
+            args.join(" "),
+            status
+        ))))
+    }
+}
+
+/// Writes a LaunchAgent that runs the listener with `args` whenever you log in, using the path of
+/// the current executable, and loads it. With a `profile`, the LaunchAgent and its log are named
+/// after it, and it runs the listener with `--profile`. Refuses to replace an existing
+/// LaunchAgent unless `force` is set.
+pub fn install_launch_agent(
+    args: &[String],
+    force: bool,
+    profile: Option<&str>,
+) -> Result<(), CliError> {
+    let home_directory = home_directory()?;
+    let label = label(profile);
+    let path = plist_path(&home_directory, &label);
+    let path_string = path.to_string_lossy().to_string();
+
+    if path.exists() {
+        if !force {
+            return Err(CliError::IoError(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "A LaunchAgent already exists at {}. Use `--force` to replace it.",
+                    path.display()
+                ),
+            )));
+        }
+
+        // The existing LaunchAgent may not be loaded, so this is allowed to fail
+        let _ = launchctl(&["unload", "-w", &path_string]);
+    }
+
+    let executable = std::env::current_exe()?;
+    let log_path = home_directory.join(match profile {
+        Some(profile) => format!("Library/Logs/litra-autotoggle-{}.log", profile),
+        None => "Library/Logs/litra-autotoggle.log".to_string(),
+    });
+    let args: Vec<String> = profile
+        .map(|profile| vec!["--profile".to_string(), profile.to_string()])
+        .unwrap_or_default()
+        .into_iter()
+        .chain(args.iter().cloned())
+        .collect();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, plist_contents(&label, &executable, &args, &log_path))?;
+    println!("Wrote LaunchAgent to {}", path.display());
+
+    launchctl(&["load", "-w", &path_string])?;
+    println!(
+        "Loaded LaunchAgent. litra-autotoggle is now running, and will start whenever you log in. Logs are written to {}.",
+        log_path.display()
+    );
+
+    Ok(())
+}
+
+/// Unloads and removes the LaunchAgent written by [`install_launch_agent`].
+pub fn uninstall_launch_agent(profile: Option<&str>) -> Result<(), CliError> {
+    let path = plist_path(&home_directory()?, &label(profile));
+    if !path.exists() {
+        println!("No LaunchAgent found at {}", path.display());
+        return Ok(());
+    }
+
+    if let Err(error) = launchctl(&["unload", "-w", &path.to_string_lossy()]) {
+        eprintln!("Failed to unload LaunchAgent: {}", error);
+    }
+    std::fs::remove_file(&path)?;
+    println!("Removed LaunchAgent from {}", path.display());
+
+    Ok(())
+}
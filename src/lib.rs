@@ -0,0 +1,5127 @@
+//! Automatically turn your Logitech Litra device on when your webcam turns on, and off when your
+//! webcam turns off. The `litra-autotoggle` binary is a thin wrapper around [`run`], so other
+//! programs, like a tray app, can embed the same behavior:
+//!
+//! - [`Cli`] holds the configuration, parsed from command line arguments with [`clap::Parser`], or
+//!   with [`Cli::parse_with_profile`] to add the options from the `--profile`'s profiles file.
+//! - [`run_listener`] watches the webcam with the platform's [`event_source::EventSource`] and
+//!   toggles the selected devices until it fails or the process is asked to stop.
+//! - [`DeviceFilter`] and [`get_selected_devices`] select and open devices like the listener does,
+//!   retrying with a [`retry::RetryPolicy`].
+//! - [`desired_state`] decides whether devices should be on or off, without touching them.
+//! - [`get_event_source`] picks the platform's event source like the listener does. Each one can
+//!   also be created and started on its own, e.g. `VideoDeviceEventSource` on Linux or
+//!   `LogStreamEventSource` on macOS.
+//! - [`debounce::Debouncer`] holds back webcam events for `--on-delay` and `--off-delay`, like the
+//!   `log` event source on macOS.
+
+#[cfg(target_os = "macos")]
+mod cmio;
+mod control;
+pub mod debounce;
+pub mod desired_state;
+mod duration;
+mod elgato;
+pub mod event_source;
+mod explain;
+mod fade;
+mod hooks;
+mod http;
+mod init;
+mod integrations;
+#[cfg(target_os = "macos")]
+mod launchd;
+mod light;
+mod logging;
+mod notifications;
+mod overrides;
+#[cfg(target_os = "linux")]
+mod permissions;
+mod profiles;
+mod queue;
+mod quiet_hours;
+mod reload;
+pub mod retry;
+mod session;
+mod solar;
+mod state;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod timer;
+mod worker;
+
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+#[cfg(target_os = "macos")]
+use debounce::Debouncer;
+use desired_state::{Conditions, Decision, Trigger};
+use event_source::{CameraEvent, EventSource, EventSourceHealth};
+use explain::{record_explanation, Explanation};
+use fade::{Fade, FadeEnd, FadeStep, Fades};
+use hooks::CameraHooks;
+#[cfg(target_os = "linux")]
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use light::Light;
+use litra::{Device, DeviceError, DeviceHandle, DeviceType, Litra};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use logging::LogTarget;
+use logging::{log_error, log_info, Event, LogFormat};
+use notifications::Severity;
+use quiet_hours::QuietHours;
+use retry::{retry, RetryPolicy};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use session::Session;
+use state::{
+    default_state_file_path, DeviceSettings, JsonFileStore, State, StateStore, VerifiedState,
+};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+#[cfg(target_os = "macos")]
+use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use timer::CoalescedInterval;
+#[cfg(target_os = "macos")]
+use tokio::io::{AsyncBufReadExt, BufReader};
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use worker::DeviceWorker;
+
+/// Automatically turn your Logitech Litra device on when your webcam turns on, and off when your webcam turns off (macOS, Linux and Windows only).
+#[derive(Debug, Parser)]
+#[clap(name = "litra-autotoggle", version, args_override_self = true)]
+#[clap(group(
+    ArgGroup::new("manual_change_detection")
+        .args(["detect_external_changes", "reconcile_interval"])
+        .multiple(true)
+))]
+pub struct Cli {
+    #[clap(subcommand)]
+    command: Option<Commands>,
+
+    #[clap(
+        long,
+        short,
+        global = true,
+        value_name = "SERIAL_NUMBER",
+        value_delimiter = ',',
+        help = "The serial number of the Logitech Litra device to use. Can be specified multiple times (or as a comma-separated list) to use several devices at once. By default, the first device found is used."
+    )]
+    serial_number: Vec<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "SERIAL_NUMBER",
+        value_delimiter = ',',
+        help = "The serial number of a Logitech Litra device to leave alone. Can be specified multiple times. Excluding any device means every other device is used, rather than just the first."
+    )]
+    exclude_serial: Vec<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "The path of a Logitech Litra device to leave alone, as shown by the `devices` command. Can be specified multiple times. Excluding any device means every other device is used, rather than just the first."
+    )]
+    exclude_path: Vec<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        value_name = "TYPE",
+        value_delimiter = ',',
+        help = "A type of Logitech Litra device to leave alone. Can be specified multiple times. Excluding any device means every other device is used, rather than just the first."
+    )]
+    exclude_type: Vec<DeviceTypeArg>,
+
+    #[clap(
+        long,
+        short,
+        value_enum,
+        global = true,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = RequireDevice::Never,
+        default_missing_value = "always",
+        help = "When to exit with an error if no Litra device is found. `at-startup` only fails if no device is found when the program starts, `always` fails whenever the program looks for a Litra device and none is found, and `never` keeps running but does nothing. Passing `--require-device` without a value means `always`. Defaults to `never`."
+    )]
+    require_device: RequireDevice,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OpenStrategy::PerToggle,
+        help = "How to open the Litra device when toggling it. `per-toggle` finds and opens the device every time, which copes with the device being unplugged and plugged back in. `persistent` keeps the device open between toggles for the lowest latency, and reopens it if a write fails."
+    )]
+    open_strategy: OpenStrategy,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "Read the state of the Litra device at this interval (e.g. `30s` or `5m`, or a number of seconds) to keep it awake. Some USB hubs aggressively put idle devices to sleep, which can cause the next toggle to fail. By default, no keepalive is sent."
+    )]
+    keepalive_interval: Option<Duration>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "LUMENS",
+        conflicts_with = "brightness_percentage",
+        help = "The brightness to set, in lumens, when turning the Litra device on. By default, the device keeps whatever brightness it had before."
+    )]
+    brightness: Option<u16>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PERCENTAGE",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "The brightness to set, as a percentage of the device's maximum brightness, when turning the Litra device on. By default, the device keeps whatever brightness it had before."
+    )]
+    brightness_percentage: Option<u8>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "KELVIN",
+        help = "The color temperature to set, in Kelvin, when turning the Litra device on. Must be a multiple of 100 within the range your device supports (usually between 2700 and 6500). By default, the device keeps whatever temperature it had before."
+    )]
+    temperature: Option<u16>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "LATITUDE,LONGITUDE",
+        value_parser = solar::parse_location,
+        conflicts_with = "temperature",
+        help = "Set the color temperature when turning the Litra device on based on the position of the sun where you are (e.g. `51.5,-0.13`): warmest while the sun is down, and getting cooler as it rises, up to the coolest temperature your device supports once it is high in the sky."
+    )]
+    solar_temperature: Option<solar::Location>,
+
+    #[clap(
+        long,
+        value_name = "PERCENTAGE",
+        default_value = "10",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "How much `ctl brightness-up` and `ctl brightness-down` change the brightness by, as a percentage of the device's brightness range"
+    )]
+    brightness_step: u8,
+
+    #[clap(
+        long,
+        value_name = "KELVIN",
+        default_value = "300",
+        value_parser = parse_temperature_step,
+        help = "How much `ctl temperature-up` and `ctl temperature-down` change the color temperature by, in Kelvin. Must be a multiple of 100."
+    )]
+    temperature_step: u16,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "TYPE=DURATION",
+        value_parser = parse_write_delay,
+        help = "Wait this long after turning a type of Logitech Litra device (`glow`, `beam` or `beam-lx`) on before setting its brightness and temperature (e.g. `beam=50ms`, or a number of milliseconds). This works around devices which ignore commands sent straight after they are turned on. Can be specified multiple times."
+    )]
+    write_delay: Vec<WriteDelay>,
+
+    #[clap(
+        long,
+        global = true,
+        action,
+        help = "After turning a Logitech Litra device on or off or changing its brightness or temperature, read its state back to check the change was applied. If it wasn't, the change is made once more, and a warning is printed if it still doesn't match. Some Litra Beam firmware silently ignores commands after USB suspend."
+    )]
+    verify_writes: bool,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = retry::DEFAULT_ATTEMPTS,
+        value_name = "ATTEMPTS",
+        value_parser = clap::value_parser!(u32).range(1..=10),
+        help = "How many times to try opening, turning on or off, or reading the serial number of a Litra device before giving up, so transient USB errors don't leave it in the wrong state (1 to 10)"
+    )]
+    retry_attempts: u32,
+
+    #[clap(
+        long,
+        global = true,
+        default_value = "100ms",
+        value_name = "DURATION",
+        value_parser = duration::parse_duration_in_milliseconds,
+        help = "How long to wait before retrying an operation on a Litra device (e.g. `250ms`). The wait doubles with each retry."
+    )]
+    retry_backoff: Duration,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration_in_milliseconds,
+        help = "Fade the Logitech Litra device's brightness up from its dimmest when turning it on, and down to its dimmest before turning it off, over this long (e.g. `500ms`, or a number of milliseconds), rather than changing it all at once. By default, the device turns on and off instantly."
+    )]
+    fade: Option<Duration>,
+
+    #[clap(
+        long,
+        action,
+        conflicts_with = "keepalive_interval",
+        help = "Detect and log webcam events, but never send any commands to the Litra device. This is useful for checking that detection works on your machine, or when another tool controls your lights."
+    )]
+    observe: bool,
+
+    #[clap(
+        long,
+        value_name = "START-END",
+        value_parser = quiet_hours::parse_quiet_hours,
+        help = "Don't turn the Litra on when the webcam turns on between these local times (e.g. `22:00-07:00`). Webcam events are still logged, and the Litra is still turned off when the webcam turns off. By default, there are no quiet hours."
+    )]
+    quiet_hours: Option<QuietHours>,
+
+    #[clap(
+        long,
+        action,
+        help = "Print an explanation of each decision made about a webcam event: whether automatic toggling is paused or forced, whether observe mode is enabled, which devices were selected or excluded and why, and what was done as a result"
+    )]
+    explain: bool,
+
+    #[clap(
+        long,
+        action,
+        help = "When your webcam turns off, restore the power, brightness and temperature your Litra device had before your webcam turned on, rather than turning it off. This is useful if you also use your Litra as a desk lamp."
+    )]
+    restore_previous_state: bool,
+
+    #[clap(
+        long,
+        action,
+        help = "Turn the selected Litra devices off when the listener stops, e.g. with Ctrl+C or `SIGTERM`, or because it failed, so they aren't left on. With `--restore-previous-state`, devices are put back how they were before the webcam turned them on instead. By default, devices are left as they are."
+    )]
+    turn_off_on_exit: bool,
+
+    #[clap(
+        long,
+        value_name = "PERCENTAGE",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        conflicts_with = "restore_previous_state",
+        help = "When your webcam turns off, dim your Litra device to this percentage of its brightness range rather than turning it off, so it works as a desk lamp between calls. It goes back to its usual brightness when your webcam turns on. Turning it off with `off` or `ctl off` still turns it off."
+    )]
+    idle_brightness_percentage: Option<u8>,
+
+    #[clap(
+        long,
+        action,
+        help = "Remember the brightness and temperature of each Litra device when it is turned off, and apply them when it is next turned on, even after a restart. Values set with `--brightness`, `--brightness-percentage`, `--temperature` or `--solar-temperature` take precedence."
+    )]
+    remember_settings: bool,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        conflicts_with = "observe",
+        help = "Read the state of the Litra device at this interval (e.g. `30s` or `5m`, or a number of seconds), and log whenever it has been turned on or off, or had its brightness or temperature changed, by something other than litra-autotoggle (e.g. Logitech's own software). By default, external changes aren't detected."
+    )]
+    detect_external_changes: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        requires = "manual_change_detection",
+        help = "When the Litra device is turned on or off by something other than litra-autotoggle (e.g. its power button or the `litra` CLI), leave it that way for this long (e.g. `10m`), rather than toggling it at the next webcam event or `--reconcile-interval` check. Requires `--detect-external-changes` or `--reconcile-interval`, which is how these changes are noticed. By default, the next webcam event toggles the device as usual."
+    )]
+    manual_override_grace_period: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        conflicts_with = "observe",
+        help = "Check whether the Litra device is on at this interval (e.g. `1m`), and turn it on or off again if it doesn't match your webcam (or a forced state), e.g. after a USB glitch, a power cut or other software changing it. This includes devices turned on or off by hand, unless you use `--manual-override-grace-period`, which treats a device that doesn't match as changed by hand and leaves it alone until the grace period is over. With `--idle-brightness-percentage` or `--restore-previous-state`, devices are only turned back on, since they may be on while the webcam is off. By default, devices are only changed when something happens."
+    )]
+    reconcile_interval: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "Look for Litra devices being plugged in or unplugged at this interval (e.g. `2s`), and turn newly plugged in devices on or off to match your webcam. This is useful if you dock and undock your laptop. By default, devices plugged in after the listener starts are only used from the next webcam event."
+    )]
+    detect_hotplug: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        help = "A command to run with your shell when the webcam turns on, e.g. to light up an \"on air\" sign. It runs even if automatic toggling is paused or forced, or observe mode is enabled. The `LITRA_AUTOTOGGLE_CAMERA` environment variable is set to `on`, and `LITRA_AUTOTOGGLE_CAMERA_DEVICE` to the camera which triggered it, where known."
+    )]
+    on_camera_on: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        help = "A command to run with your shell when the webcam turns off. It runs even if automatic toggling is paused or forced, or observe mode is enabled. The `LITRA_AUTOTOGGLE_CAMERA` environment variable is set to `off`, and `LITRA_AUTOTOGGLE_CAMERA_DEVICE` to the camera which triggered it, where known."
+    )]
+    on_camera_off: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "30s",
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "How long `--on-camera-on` and `--on-camera-off` commands can run for before they are stopped (e.g. `10s`)"
+    )]
+    hook_timeout: Duration,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "Only run `--on-camera-on` once the webcam has been on for this long (e.g. `1m`), and don't run `--on-camera-off` if it wasn't. This doesn't delay toggling the Litra, so it can come on straight away while, for example, your \"on a call\" status only changes for longer calls. By default, hooks run straight away."
+    )]
+    hook_min_session_duration: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "COUNT/DURATION",
+        value_parser = hooks::parse_rate_limit,
+        help = "Start at most this many `--on-camera-on` and `--on-camera-off` hooks in a period (e.g. `1/30s` or `5/1m`), so a flapping webcam can't get you rate limited by the services your hooks call. Hooks over the limit are held back until it allows another, and only the latest is run, skipping it if the webcam is back to how it was when the last hook ran."
+    )]
+    hook_rate_limit: Option<hooks::RateLimit>,
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[clap(
+        long,
+        action,
+        requires = "detect_external_changes",
+        help = "Show a desktop notification when an external change to the Litra device is detected (macOS and Linux only). Requires `--detect-external-changes`."
+    )]
+    notify_external_changes: bool,
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[clap(
+        long,
+        global = true,
+        action,
+        help = "Show a desktop notification when the Litra device is turned on or off, fails to turn on or off, or can't be found (macOS and Linux only)"
+    )]
+    notify: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "[SEVERITY=]CHANNEL",
+        value_parser = notifications::parse_route,
+        help = "Send notifications to this channel: `desktop` (macOS and Linux only), a webhook (`http://host[:port]/path`), email through an SMTP server (`smtp://host[:port]/ops@example.com?from=litra@example.com`) or an MQTT broker (`mqtt://host[:port]/topic`). Prefix it with the least serious notifications to send (`info`, `warning` or `error`), e.g. `error=smtp://mail.example.com/ops@example.com`. Defaults to `info`. Can be specified multiple times."
+    )]
+    notification_channel: Vec<notifications::Route>,
+
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        help = "Listen for commands from `litra-autotoggle ctl` on a Unix socket, so you can turn your Litra on or off, or pause automatic toggling, while the listener keeps running. The socket is created at `$XDG_RUNTIME_DIR/litra-autotoggle.sock` (or in the temporary directory if `$XDG_RUNTIME_DIR` isn't set), unless you specify a path."
+    )]
+    control_socket: Option<Option<PathBuf>>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME",
+        value_parser = parse_profile,
+        help = "Run a separate listener under this name (e.g. `office`), with its own control socket and remembered settings, so one machine can run several listeners for different Litra devices. Pass the same name to `ctl` and `service` to talk to it. If the profiles file has options for this profile, they are used too, with options on the command line taking precedence."
+    )]
+    profile: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        requires = "profile",
+        help = "The path of the profiles file, a JSON object with the options for each `--profile`, e.g. `{\"work\": {\"serial-number\": [\"ABC123\"], \"brightness\": 200}}`. Defaults to `litra-autotoggle/profiles.json` in your configuration directory (e.g. `~/.config` on Linux)."
+    )]
+    profiles_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "The format of log output. With `json`, each line is a JSON object with a `timestamp`, `level`, `event` (e.g. `camera`, `toggle` or `message`) and `message`, plus details like the device's `serial_number`, the `camera`, the `trigger_source`, the `outcome` and any `error` where relevant."
+    )]
+    log_format: LogFormat,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogTarget::Stdout,
+        help = "Where to write log output (macOS and Linux only). With `journald`, logs go to the systemd journal, with the details of each event (e.g. `LITRA_AUTOTOGGLE_EVENT` and `LITRA_AUTOTOGGLE_SERIAL_NUMBER`) as journal fields."
+    )]
+    log_target: LogTarget,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogTarget::Stdout,
+        help = "Where to write log output (macOS and Linux only). With `syslog`, logs go to the system log, which you can read with Console or `log show --predicate 'process == \"litra-autotoggle\"'`."
+    )]
+    log_target: LogTarget,
+
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        help = "Serve an HTTP API on this address (e.g. `127.0.0.1:8080`) while listening for webcam events, so you can check the state of your Litra with `GET /status`, or control it with `POST /on`, `POST /off`, `POST /pause` and `POST /resume`. Open the address in a browser for a simple dashboard. The API has no authentication, so only listen on addresses you trust. Requests from other websites are rejected, and the API must be reached by IP address or `localhost`. By default, no HTTP API is served."
+    )]
+    listen_http: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        value_delimiter = ',',
+        value_parser = elgato::parse_key_light,
+        help = "Turn the Elgato Key Light at this address (e.g. `192.168.1.20`, `key-light.local:9123` or `[fe80::1]:9123`) on and off with your webcam, alongside your Litra devices. Can be specified multiple times or as a comma-separated list."
+    )]
+    elgato_light: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Look for Elgato Key Lights on your local network with mDNS when the listener starts, and turn them on and off with your webcam, alongside your Litra devices"
+    )]
+    discover_elgato_lights: bool,
+
+    #[clap(
+        long,
+        action,
+        help = "Check that each enabled integration (the HTTP API, the control socket, desktop notifications, notification channels, Elgato Key Lights, hooks and remembered settings) will work when the listener starts, and print the results"
+    )]
+    check_integrations: bool,
+
+    #[clap(
+        long,
+        action,
+        help = "Like `--check-integrations`, but exit with an error if any check fails. This is useful when running the listener as a service, so a misconfiguration is caught straight away."
+    )]
+    strict_integrations: bool,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        short = 'd',
+        value_name = "PATH",
+        value_delimiter = ',',
+        help = "The path of the video device to monitor (e.g. `/dev/video0`) (Linux only). Can be specified multiple times (or as a comma-separated list) to monitor several devices. By default, all devices are monitored."
+    )]
+    video_device: Vec<String>,
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "The name of a video device to monitor, as reported by the driver (e.g. `HD Pro Webcam C920`) (macOS with `--detection-method core-media-io`, and Linux only). Unlike paths, names don't change between reboots. On macOS, the device's unique ID can be used instead. Can be specified multiple times. On Linux, devices matching either `--video-device` or `--video-device-name` are monitored."
+    )]
+    video_device_name: Vec<String>,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        value_name = "DIRECTORY[=PATTERNS]",
+        value_parser = parse_video_device_directory,
+        help = "Another directory to look for video devices in, as well as `/dev` (Linux only), optionally followed by a comma-separated list of filename patterns where `*` matches anything (e.g. `/srv/container/dev` or `/srv/container/dev=video*,cam*`). By default, files starting with `video` are used. Can be specified multiple times."
+    )]
+    watch_dir: Vec<VideoDeviceDirectory>,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        value_name = "[DEVICE=]DURATION",
+        value_parser = parse_assume_closed_after,
+        help = "Assume a video device has been closed if it hasn't sent a close event this long after being opened (e.g. `30s` or `/dev/video2=30s`) (Linux only). Some camera drivers don't send close events reliably, which would otherwise leave the Litra on. Can be specified multiple times, with device-specific values taking precedence. By default, we wait for a close event however long it takes."
+    )]
+    assume_closed_after: Vec<AssumeClosedAfter>,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = DetectionMethod::Inotify,
+        help = "How to detect whether your webcam is in use (macOS and Linux only). On Linux, both methods treat a video device as in use while any program has it open, even if it isn't streaming."
+    )]
+    detection_method: DetectionMethod,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = DetectionMethod::LogStream,
+        help = "How to detect whether your webcam is in use (macOS and Linux only)"
+    )]
+    detection_method: DetectionMethod,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        short,
+        default_value = "1500ms",
+        value_name = "DURATION",
+        value_parser = duration::parse_delay,
+        help = "The delay between detecting a webcam event and toggling the Litra (e.g. `750ms` or `1.5s`, or a number of milliseconds) (macOS only). When your webcam turns on or off, multiple events may be generated in quick succession. Setting a delay allows the program to wait for all events before taking action, avoiding flickering."
+    )]
+    delay: Duration,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_delay,
+        help = "The delay between detecting that the webcam has turned on and turning the Litra on (e.g. `0ms` or `250ms`, or a number of milliseconds) (macOS only). Defaults to `--delay`."
+    )]
+    on_delay: Option<Duration>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_delay,
+        help = "The delay between detecting that the webcam has turned off and turning the Litra off (e.g. `10s`, or a number of milliseconds) (macOS only). A longer delay avoids the Litra going dark when an app briefly restarts your webcam. Defaults to `--delay`."
+    )]
+    off_delay: Option<Duration>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "Only turn the Litra on once a video device has been on for this long (e.g. `3s`) (macOS only). When you deny an app permission to use your webcam, it is turned on and then straight off again. Setting this avoids the Litra flashing on and off when that happens. By default, the Litra is turned on after `--on-delay`."
+    )]
+    min_session_duration: Option<Duration>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = LogStreamLevel::Default,
+        help = "The level of messages that the `log` process used to detect webcam events should stream (macOS only). Lower levels stream fewer messages, which uses less CPU."
+    )]
+    log_stream_level: LogStreamLevel,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "PROCESS",
+        help = "Only stream messages from this process (name or PID) when listening for webcam events, which reduces the CPU used by the `log` process (macOS only). Can be specified multiple times. By default, messages from all processes are streamed."
+    )]
+    log_stream_process: Vec<String>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "APP",
+        value_delimiter = ',',
+        help = "Only toggle the Litra for webcam events from this app, as named by `log stream` (e.g. `zoom.us`) (macOS only). Can be specified multiple times (or as a comma-separated list). By default, webcam events from all apps are used."
+    )]
+    only_app: Vec<String>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "APP",
+        value_delimiter = ',',
+        help = "Ignore webcam events from this app, as named by `log stream` (e.g. `Photo Booth`) (macOS only). Can be specified multiple times (or as a comma-separated list)."
+    )]
+    ignore_app: Vec<String>,
+
+    #[cfg(target_os = "macos")]
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration,
+        help = "Print the CPU usage of the `log` process used to detect webcam events at this interval (e.g. `30s` or `5m`, or a number of seconds) (macOS only). By default, CPU usage isn't reported."
+    )]
+    log_stream_cpu_report_interval: Option<Duration>,
+
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse_duration_in_milliseconds,
+        help = "Allow periodic work, like `--keepalive-interval`, `--detect-hotplug`, `--detect-external-changes` and `--poll-interval`, to run up to this much late (e.g. `5s`), so work that is due at around the same time is done in a single wakeup. This saves battery, at the cost of reacting more slowly. Periodic work is always lined up so work with the same interval happens together. By default, periodic work runs on time."
+    )]
+    timer_slack: Option<Duration>,
+
+    #[clap(
+        long,
+        default_value = "1s",
+        value_name = "DURATION",
+        value_parser = duration::parse_duration_in_milliseconds,
+        help = "How often to check whether any application is using your webcam (e.g. `500ms` or `2s`, or a number of milliseconds) (Windows, macOS with `--detection-method core-media-io`, or Linux with `--detection-method proc` or when inotify isn't available)"
+    )]
+    poll_interval: Duration,
+
+    #[clap(
+        long,
+        short,
+        action,
+        global = true,
+        help = "Output detailed log messages"
+    )]
+    verbose: bool,
+
+    /// The command line arguments, without the options from the profile, so the profiles file
+    /// can be reloaded with `ctl reload`. Empty unless parsed with [`Cli::parse_with_profile`].
+    #[clap(skip)]
+    arguments: Vec<OsString>,
+
+    /// The options from the profiles file for `--profile`, to compare against when it is
+    /// reloaded.
+    #[clap(skip)]
+    profile_options: Map<String, Value>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Turn your Logitech Litra device on, without listening for webcam events
+    On,
+    /// Turn your Logitech Litra device off, without listening for webcam events
+    Off,
+    /// Toggle your Logitech Litra device on or off, without listening for webcam events
+    Toggle,
+    /// List connected Logitech Litra devices, with their serial numbers and current state
+    Devices {
+        #[clap(long, short, action, help = "Return the results in JSON format")]
+        json: bool,
+    },
+    /// Show whether your Logitech Litra device is on, and its current brightness and temperature
+    Status {
+        #[clap(long, short, action, help = "Return the results in JSON format")]
+        json: bool,
+
+        #[clap(
+            long,
+            action,
+            conflicts_with = "json",
+            help = "Return the results as plain `name: value` lines, one per line, with units written out in full. The names and format are stable, so this is suitable for scripts and screen readers."
+        )]
+        plain: bool,
+    },
+    /// Set up a profile by answering a few questions about your Litra devices and webcams, and
+    /// save it to the profiles file, ready to use with `--profile`
+    Init,
+    /// Print options for the profiles file, either those you've given or an example with every
+    /// option
+    Profile {
+        #[clap(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Send a command to a running listener started with `--control-socket`
+    #[cfg(unix)]
+    Ctl {
+        #[clap(value_enum)]
+        command: control::ControlCommand,
+
+        #[clap(
+            value_name = "ARGUMENTS",
+            help = "For `force-on` and `force-off`, how long to force the Litra device on or off for, e.g. `30s`, `45m` or `2h`. For `snooze`, how long to pause automatic toggling for, e.g. `15m`. For `set`, the setting to change and its new value, e.g. `fade 500ms`. For `maintenance`, `on` or `off`."
+        )]
+        arguments: Vec<String>,
+
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "The path of the listener's control socket. Defaults to `$XDG_RUNTIME_DIR/litra-autotoggle.sock`, or a socket in the temporary directory if `$XDG_RUNTIME_DIR` isn't set."
+        )]
+        socket: Option<PathBuf>,
+    },
+    /// Simulate the webcam turning on or off in a running listener started with
+    /// `--control-socket`, handling it like a detected webcam event and explaining what was done
+    #[cfg(unix)]
+    Simulate {
+        #[clap(value_enum)]
+        event: control::SimulatedEvent,
+
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "The path of the listener's control socket. Defaults to `$XDG_RUNTIME_DIR/litra-autotoggle.sock`, or a socket in the temporary directory if `$XDG_RUNTIME_DIR` isn't set."
+        )]
+        socket: Option<PathBuf>,
+    },
+    /// Install the udev rule that lets the `video` group access Litra devices, and add you to the
+    /// group. Run with `sudo` (Linux only).
+    #[cfg(target_os = "linux")]
+    FixPermissions,
+    /// Run litra-autotoggle in the background as a service (macOS and Linux only)
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    Service {
+        #[clap(subcommand)]
+        command: ServiceCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ProfileCommand {
+    /// Print the options in effect, from the command line and the `--profile`, as a profile, so
+    /// you can save a setup you've been trying out
+    Dump,
+    /// Print an example profile with every option, set to its default, as a starting point for
+    /// the profiles file
+    Example,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Debug, Subcommand)]
+enum ServiceCommand {
+    /// Install a service that runs litra-autotoggle whenever you log in (a systemd user unit on
+    /// Linux, or a LaunchAgent on macOS)
+    Install {
+        #[clap(long, action, help = "Replace the unit if it already exists")]
+        force: bool,
+
+        #[clap(
+            last = true,
+            value_name = "ARGS",
+            help = "Arguments to run litra-autotoggle with, after `--` (e.g. `-- --brightness 100`)"
+        )]
+        args: Vec<String>,
+    },
+    /// Stop and remove the service installed with `service install`
+    Uninstall,
+}
+
+impl Cli {
+    /// Parses the command line like [`Parser::parse`], adding the options for `--profile` from
+    /// the profiles file, if it has any. Options from the profile come first, so options given
+    /// on the command line take precedence. Exits with an error if the profiles file is invalid.
+    pub fn parse_with_profile() -> Self {
+        Cli::try_parse_with_profile_from(std::env::args_os().collect())
+            .unwrap_or_else(|error| error.exit())
+    }
+
+    /// Parses `arguments` like [`Parser::try_parse_from`], adding the options for `--profile`
+    /// from the profiles file, if it has any.
+    fn try_parse_with_profile_from(arguments: Vec<OsString>) -> Result<Self, clap::Error> {
+        let mut args = Cli::try_parse_from(arguments.iter())?;
+        let Some(profile) = &args.profile else {
+            args.arguments = arguments;
+            return Ok(args);
+        };
+
+        let profile =
+            profiles::load_profile(args.profiles_file.as_deref(), profile).map_err(|message| {
+                Cli::command().error(clap::error::ErrorKind::InvalidValue, message)
+            })?;
+        if let Some(profile) = profile {
+            let mut profile_arguments = arguments.iter().cloned();
+            let program = profile_arguments.next();
+            args = Cli::try_parse_from(
+                program
+                    .into_iter()
+                    .chain(profile.arguments.into_iter().map(Into::into))
+                    .chain(profile_arguments),
+            )?;
+            args.profile_options = profile.options;
+        }
+        args.arguments = arguments;
+
+        Ok(args)
+    }
+
+    /// Which devices to use, from `--serial-number` and the `--exclude-*` arguments.
+    pub fn device_filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            serial_numbers: self.serial_number.clone(),
+            exclude_serial_numbers: self.exclude_serial.clone(),
+            exclude_paths: self.exclude_path.clone(),
+            exclude_types: self.exclude_type.clone(),
+        }
+    }
+
+    fn device_options(&self) -> DeviceOptions {
+        DeviceOptions {
+            open_strategy: self.open_strategy,
+            power_on_settings: self.power_on_settings(),
+            observe: self.observe,
+            explain: self.explain,
+            restore_previous_state: self.restore_previous_state,
+            idle_brightness_percentage: self.idle_brightness_percentage,
+            remember_settings: self.remember_settings,
+            detect_external_changes: self.detect_external_changes.is_some(),
+            manual_override_grace_period: self.manual_override_grace_period,
+            brightness_step_percentage: self.brightness_step,
+            temperature_step_in_kelvin: self.temperature_step,
+            write_delays: WriteDelays::new(&self.write_delay),
+            verify_writes: self.verify_writes,
+            fade: self.fade,
+            quiet_hours: self.quiet_hours,
+            retry_policy: RetryPolicy::new(self.retry_attempts, self.retry_backoff),
+        }
+    }
+
+    fn camera_hooks(&self) -> CameraHooks {
+        CameraHooks::new(
+            self.on_camera_on.clone(),
+            self.on_camera_off.clone(),
+            self.hook_timeout,
+            self.hook_min_session_duration,
+            self.hook_rate_limit,
+        )
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn notify_external_changes(&self) -> bool {
+        self.notify_external_changes
+    }
+
+    #[cfg(target_os = "windows")]
+    fn notify_external_changes(&self) -> bool {
+        false
+    }
+
+    /// Where notifications are sent, from `--notification-channel` and `--notify`.
+    fn notification_routes(&self) -> Vec<notifications::Route> {
+        #[cfg_attr(target_os = "windows", allow(unused_mut))]
+        let mut routes = self.notification_channel.clone();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if self.notify {
+            routes.push(notifications::Route::new(
+                Severity::Info,
+                "desktop",
+                std::sync::Arc::new(notifications::DesktopChannel),
+            ));
+        }
+        routes
+    }
+
+    /// The delay before turning the Litra on after the webcam turns on, on macOS.
+    #[cfg(target_os = "macos")]
+    fn on_delay(&self) -> Duration {
+        self.on_delay.unwrap_or(self.delay)
+    }
+
+    /// The delay before turning the Litra off after the webcam turns off, on macOS.
+    #[cfg(target_os = "macos")]
+    fn off_delay(&self) -> Duration {
+        self.off_delay.unwrap_or(self.delay)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn app_filter(&self) -> AppFilter {
+        AppFilter {
+            only_apps: self.only_app.clone(),
+            ignored_apps: self.ignore_app.clone(),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn log_stream_options(&self) -> LogStreamOptions {
+        LogStreamOptions {
+            level: self.log_stream_level,
+            processes: self.log_stream_process.clone(),
+            cpu_report_interval: self.log_stream_cpu_report_interval,
+        }
+    }
+
+    fn power_on_settings(&self) -> PowerOnSettings {
+        PowerOnSettings {
+            brightness: self
+                .brightness
+                .map(Brightness::Lumen)
+                .or(self.brightness_percentage.map(Brightness::Percentage)),
+            temperature: self
+                .temperature
+                .map(Temperature::Kelvin)
+                .or(self.solar_temperature.map(Temperature::Solar)),
+        }
+    }
+}
+
+/// Describes an error from parsing options in a single line, without the usage that clap adds
+/// for the command line, e.g. for options from the profiles file.
+fn describe_parse_error(error: &clap::Error) -> String {
+    error
+        .to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("error: ")
+        .to_string()
+}
+
+fn parse_profile(value: &str) -> Result<String, String> {
+    if !value.is_empty()
+        && value.chars().all(|character| {
+            character.is_ascii_alphanumeric() || character == '-' || character == '_'
+        })
+    {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "Invalid profile `{}`. It can only contain letters, numbers, `-` and `_`, e.g. `office`.",
+            value
+        ))
+    }
+}
+
+fn parse_temperature_step(value: &str) -> Result<u16, String> {
+    value
+        .parse::<u16>()
+        .ok()
+        .filter(|step| *step > 0 && step % 100 == 0)
+        .ok_or_else(|| {
+            format!(
+                "Invalid temperature step `{}`. It must be a multiple of 100, e.g. `300`.",
+                value
+            )
+        })
+}
+
+/// A directory to look for video devices in, and the filename patterns that video devices match.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+struct VideoDeviceDirectory {
+    path: PathBuf,
+    patterns: Vec<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl VideoDeviceDirectory {
+    fn new(path: impl Into<PathBuf>) -> Self {
+        VideoDeviceDirectory {
+            path: path.into(),
+            patterns: vec!["video*".to_string()],
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parse_video_device_directory(value: &str) -> Result<VideoDeviceDirectory, String> {
+    let (path, patterns) = match value.split_once('=') {
+        Some((path, patterns)) => (path, Some(patterns)),
+        None => (value, None),
+    };
+
+    if path.is_empty() {
+        return Err(format!(
+            "Invalid directory `{}`. It must be a path, optionally followed by `=` and a comma-separated list of patterns, e.g. `/srv/container/dev=video*`.",
+            value
+        ));
+    }
+
+    let mut directory = VideoDeviceDirectory::new(path);
+    if let Some(patterns) = patterns {
+        directory.patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if directory.patterns.is_empty() {
+            return Err(format!(
+                "Invalid directory `{}`. At least one pattern must be given after `=`.",
+                value
+            ));
+        }
+    }
+
+    Ok(directory)
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any number of characters.
+#[cfg(target_os = "linux")]
+fn matches_filename_pattern(name: &str, pattern: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // There is no `*`, so the whole name must match
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Reads the name of the video device at `path` from sysfs, e.g. `HD Pro Webcam C920`. This is
+/// the same as the card name V4L2 reports for the device.
+#[cfg(target_os = "linux")]
+fn get_video_device_name(path: &std::path::Path) -> Option<String> {
+    let name_path = std::path::Path::new("/sys/class/video4linux")
+        .join(path.file_name()?)
+        .join("name");
+
+    std::fs::read_to_string(name_path)
+        .ok()
+        .map(|name| name.trim().to_string())
+}
+
+/// Lists the video devices in `/dev` and any extra directories given with `--watch-dir`.
+#[cfg(target_os = "linux")]
+fn get_video_device_paths(
+    extra_directories: &[VideoDeviceDirectory],
+) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut paths = Vec::new();
+
+    for directory in std::iter::once(&VideoDeviceDirectory::new("/dev")).chain(extra_directories) {
+        let entries = std::fs::read_dir(&directory.path).map_err(|error| {
+            std::io::Error::new(
+                error.kind(),
+                format!(
+                    "Failed to list video devices in {}: {}",
+                    directory.path.display(),
+                    error
+                ),
+            )
+        })?;
+
+        paths.extend(entries.filter_map(|entry| entry.ok()).filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .filter(|name| {
+                    directory
+                        .patterns
+                        .iter()
+                        .any(|pattern| matches_filename_pattern(name, pattern))
+                })
+                .map(|_| e.path())
+        }));
+    }
+
+    Ok(paths)
+}
+
+/// A type of Litra device, for `--exclude-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DeviceTypeArg {
+    Glow,
+    Beam,
+    BeamLx,
+}
+
+impl DeviceTypeArg {
+    fn matches(&self, device_type: DeviceType) -> bool {
+        matches!(
+            (self, device_type),
+            (DeviceTypeArg::Glow, DeviceType::LitraGlow)
+                | (DeviceTypeArg::Beam, DeviceType::LitraBeam)
+                | (DeviceTypeArg::BeamLx, DeviceType::LitraBeamLX)
+        )
+    }
+}
+
+/// How long to wait after turning a type of device on, given with `--write-delay`.
+#[derive(Debug, Clone, Copy)]
+struct WriteDelay {
+    device_type: DeviceTypeArg,
+    delay: Duration,
+}
+
+fn parse_write_delay(value: &str) -> Result<WriteDelay, String> {
+    let (device_type, delay) = value.split_once('=').ok_or_else(|| {
+        format!(
+            "Invalid write delay `{}`. It must be a device type and a duration, e.g. `beam=50ms`.",
+            value
+        )
+    })?;
+
+    Ok(WriteDelay {
+        device_type: DeviceTypeArg::from_str(device_type, true).map_err(|_| {
+            format!(
+                "Invalid device type `{}`. It must be `glow`, `beam` or `beam-lx`.",
+                device_type
+            )
+        })?,
+        delay: duration::parse_delay(delay)?,
+    })
+}
+
+/// The `--write-delay` for each type of device.
+#[derive(Debug, Clone, Copy, Default)]
+struct WriteDelays {
+    glow: Option<Duration>,
+    beam: Option<Duration>,
+    beam_lx: Option<Duration>,
+}
+
+impl WriteDelays {
+    /// Collects `write_delays`, with later values for a device type taking precedence.
+    fn new(write_delays: &[WriteDelay]) -> Self {
+        let mut delays = WriteDelays::default();
+        for write_delay in write_delays {
+            let delay = match write_delay.device_type {
+                DeviceTypeArg::Glow => &mut delays.glow,
+                DeviceTypeArg::Beam => &mut delays.beam,
+                DeviceTypeArg::BeamLx => &mut delays.beam_lx,
+            };
+            *delay = Some(write_delay.delay);
+        }
+        delays
+    }
+
+    /// The delay for `light`, if it is a type of Litra device with one.
+    fn for_light(&self, light: &impl Light) -> Option<Duration> {
+        match light.device_type()? {
+            DeviceType::LitraGlow => self.glow,
+            DeviceType::LitraBeam => self.beam,
+            DeviceType::LitraBeamLX => self.beam_lx,
+        }
+    }
+}
+
+/// Which devices to use, from `--serial-number` and the `--exclude-*` arguments.
+#[derive(Debug, Clone)]
+pub struct DeviceFilter {
+    serial_numbers: Vec<String>,
+    exclude_serial_numbers: Vec<String>,
+    exclude_paths: Vec<String>,
+    exclude_types: Vec<DeviceTypeArg>,
+}
+
+impl DeviceFilter {
+    fn has_exclusions(&self) -> bool {
+        !self.exclude_serial_numbers.is_empty()
+            || !self.exclude_paths.is_empty()
+            || !self.exclude_types.is_empty()
+    }
+
+    /// Whether every matching device is used. Otherwise, only the first matching device is used.
+    /// Excluding devices means we use all of the others.
+    fn selects_all(&self) -> bool {
+        !self.serial_numbers.is_empty() || self.has_exclusions()
+    }
+
+    /// Narrows the filter down to the device with `serial_number`, e.g. to control a single device
+    /// from the dashboard. The `--exclude-*` arguments still apply, and the device must be one of
+    /// those given with `--serial-number`, if any were.
+    fn only(&self, serial_number: &str) -> Result<DeviceFilter, String> {
+        if !self.serial_numbers.is_empty()
+            && !self
+                .serial_numbers
+                .iter()
+                .any(|expected| expected == serial_number)
+        {
+            return Err(format!(
+                "Litra device with serial number {} isn't one of the devices given with `--serial-number`",
+                serial_number
+            ));
+        }
+
+        Ok(DeviceFilter {
+            serial_numbers: vec![serial_number.to_string()],
+            ..self.clone()
+        })
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        self.check(device).is_ok()
+    }
+
+    /// Where `device` comes in the list given with `--serial-number`, so devices can be turned on
+    /// in that order. Devices that aren't in the list come last.
+    fn position(&self, device: &Device) -> usize {
+        device
+            .device_info()
+            .serial_number()
+            .and_then(|actual| {
+                self.serial_numbers
+                    .iter()
+                    .position(|expected| expected == actual)
+            })
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Checks whether `device` should be used, returning why not if it shouldn't. Each check can
+    /// only rule a device out, so the device must get through all of them.
+    pub fn check(&self, device: &Device) -> Result<(), &'static str> {
+        let serial_number = device.device_info().serial_number();
+        let path = device.device_info().path().to_string_lossy();
+        let has_serial_number_in = |serial_numbers: &[String]| {
+            serial_number
+                .is_some_and(|actual| serial_numbers.iter().any(|expected| expected == actual))
+        };
+
+        let checks = [
+            (
+                self.serial_numbers.is_empty() || has_serial_number_in(&self.serial_numbers),
+                "not one of the devices given with `--serial-number`",
+            ),
+            (
+                !has_serial_number_in(&self.exclude_serial_numbers),
+                "excluded by `--exclude-serial`",
+            ),
+            (
+                !self.exclude_paths.iter().any(|excluded| *excluded == path),
+                "excluded by `--exclude-path`",
+            ),
+            (
+                !self
+                    .exclude_types
+                    .iter()
+                    .any(|excluded| excluded.matches(device.device_type())),
+                "excluded by `--exclude-type`",
+            ),
+        ];
+
+        match checks.into_iter().find(|(passed, _)| !passed) {
+            Some((_, reason)) => Err(reason),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    DeviceError(DeviceError),
+    IoError(std::io::Error),
+    NoDevicesFound,
+    DeviceNotFound(String),
+    SerializationError(serde_json::Error),
+    ControlError(String),
+    IntegrationChecksFailed,
+    MaintenanceMode,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::DeviceError(error) => error.fmt(f),
+            CliError::IoError(error) => write!(f, "Input/output error: {}", error),
+            CliError::NoDevicesFound => write!(f, "No Litra devices found"),
+            CliError::DeviceNotFound(serial_number) => write!(
+                f,
+                "Litra device with serial number {} not found",
+                serial_number
+            ),
+            CliError::SerializationError(error) => write!(f, "Serialization error: {}", error),
+            CliError::ControlError(message) => write!(f, "Listener returned an error: {}", message),
+            CliError::IntegrationChecksFailed => write!(
+                f,
+                "One or more integrations failed their checks, and `--strict-integrations` is enabled"
+            ),
+            CliError::MaintenanceMode => write!(
+                f,
+                "Litra devices are left alone while maintenance mode is on. Turn it off with `ctl maintenance off`."
+            ),
+        }
+    }
+}
+
+impl From<DeviceError> for CliError {
+    fn from(error: DeviceError) -> Self {
+        CliError::DeviceError(error)
+    }
+}
+
+impl From<serde_json::Error> for CliError {
+    fn from(error: serde_json::Error) -> Self {
+        CliError::SerializationError(error)
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(error: std::io::Error) -> Self {
+        CliError::IoError(error)
+    }
+}
+
+pub type CliResult = Result<(), CliError>;
+
+/// Returns an error unless every device in `serial_numbers` was found, or, if no serial numbers
+/// were given, at least one device was found.
+fn check_required_devices_found(
+    serial_numbers: &[String],
+    found_serial_numbers: &[Option<String>],
+) -> Result<(), CliError> {
+    if serial_numbers.is_empty() && found_serial_numbers.is_empty() {
+        return Err(CliError::NoDevicesFound);
+    }
+
+    match serial_numbers.iter().find(|expected| {
+        !found_serial_numbers
+            .iter()
+            .any(|actual| actual.as_deref() == Some(expected.as_str()))
+    }) {
+        Some(serial_number) => Err(CliError::DeviceNotFound(serial_number.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Opens the devices selected with `--serial-number` and the `--exclude-*` arguments: every
+/// connected device that matches `device_filter`, or just the first if no devices are specified
+/// or excluded.
+pub fn get_selected_devices(
+    context: &mut Litra,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    retry_policy: RetryPolicy,
+) -> Result<Vec<DeviceHandle>, CliError> {
+    {
+        context.refresh_connected_devices()?;
+    }
+
+    let mut devices: Vec<_> = context
+        .get_connected_devices()
+        .filter(|device| device_filter.matches(device))
+        .take(if device_filter.selects_all() {
+            usize::MAX
+        } else {
+            1
+        })
+        .collect();
+    devices.sort_by_key(|device| device_filter.position(device));
+
+    if require_device {
+        let found_serial_numbers: Vec<Option<String>> = devices
+            .iter()
+            .map(|device| {
+                device
+                    .device_info()
+                    .serial_number()
+                    .map(|serial_number| serial_number.to_string())
+            })
+            .collect();
+        check_required_devices_found(&device_filter.serial_numbers, &found_serial_numbers)?;
+    }
+
+    devices
+        .iter()
+        .map(|device| {
+            retry(retry_policy, "open Litra device", || device.open(context))
+                .map_err(CliError::DeviceError)
+        })
+        .collect()
+}
+
+/// When to exit with an error if no Litra device is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RequireDevice {
+    /// Only fail if no device is found when the program starts. Devices can be unplugged
+    /// afterwards without the listener exiting.
+    AtStartup,
+    /// Fail whenever the program looks for a device and none is found.
+    Always,
+    /// Never fail, but do nothing if no device is found.
+    Never,
+}
+
+impl RequireDevice {
+    /// Whether a device must be found when the program starts, including for one-off commands.
+    fn at_startup(self) -> bool {
+        self != RequireDevice::Never
+    }
+
+    /// Whether a device must be found when the listener toggles it in response to an event.
+    fn on_toggle(self) -> bool {
+        self == RequireDevice::Always
+    }
+}
+
+/// How the Litra device is opened when it needs to be toggled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OpenStrategy {
+    /// Find and open the device again every time it is toggled. This is robust to the device
+    /// being unplugged and plugged back in.
+    PerToggle,
+    /// Keep the device open between toggles for the lowest latency, falling back to reopening
+    /// the device if a write fails.
+    Persistent,
+}
+
+/// The brightness to set when turning the Litra device on.
+#[derive(Debug, Clone, Copy)]
+enum Brightness {
+    Lumen(u16),
+    Percentage(u8),
+}
+
+/// The color temperature to set when turning the Litra device on.
+#[derive(Debug, Clone, Copy)]
+enum Temperature {
+    Kelvin(u16),
+    /// Based on the position of the sun at a location
+    Solar(solar::Location),
+}
+
+/// Settings applied to the Litra device whenever it is turned on.
+#[derive(Debug, Clone, Copy)]
+struct PowerOnSettings {
+    brightness: Option<Brightness>,
+    temperature: Option<Temperature>,
+}
+
+fn percentage_within_range(percentage: u8, minimum: u16, maximum: u16) -> u16 {
+    let range = f64::from(maximum - minimum);
+    (f64::from(minimum) + range * f64::from(percentage) / 100.0).round() as u16
+}
+
+impl PowerOnSettings {
+    /// Works out the settings to apply to the device, falling back to the device's remembered
+    /// settings (if any) for anything that isn't configured.
+    fn resolve<L: Light>(
+        &self,
+        light: &L,
+        remembered_settings: Option<DeviceSettings>,
+    ) -> ExpectedState {
+        let brightness_in_lumen = match self.brightness {
+            Some(Brightness::Lumen(lumen)) => Some(lumen),
+            Some(Brightness::Percentage(percentage)) => Some(percentage_within_range(
+                percentage,
+                light.minimum_brightness_in_lumen(),
+                light.maximum_brightness_in_lumen(),
+            )),
+            None => remembered_settings.map(|settings| settings.brightness_in_lumen),
+        };
+
+        let temperature_in_kelvin = match self.temperature {
+            Some(Temperature::Kelvin(kelvin)) => Some(kelvin),
+            Some(Temperature::Solar(location)) => {
+                let (kelvin, sun_elevation) = location.temperature_in_kelvin(
+                    light.minimum_temperature_in_kelvin(),
+                    light.maximum_temperature_in_kelvin(),
+                );
+                log_info!(
+                    "Using a color temperature of {} K, as the sun is {:.0}° {} the horizon",
+                    kelvin,
+                    sun_elevation.abs(),
+                    if sun_elevation < 0.0 {
+                        "below"
+                    } else {
+                        "above"
+                    }
+                );
+                Some(kelvin)
+            }
+            None => remembered_settings.map(|settings| settings.temperature_in_kelvin),
+        };
+
+        ExpectedState {
+            on: None,
+            brightness_in_lumen,
+            temperature_in_kelvin,
+        }
+    }
+}
+
+/// The state of a Litra device, captured before turning it on so it can be restored later, or to
+/// spot changes made by other tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DeviceState {
+    on: bool,
+    brightness_in_lumen: u16,
+    temperature_in_kelvin: u16,
+}
+
+impl DeviceState {
+    fn read<L: Light>(light: &L) -> Result<Self, L::Error> {
+        Ok(DeviceState {
+            on: light.is_on()?,
+            brightness_in_lumen: light.brightness_in_lumen()?,
+            temperature_in_kelvin: light.temperature_in_kelvin()?,
+        })
+    }
+
+    fn restore<L: Light>(&self, light: &L, retry_policy: RetryPolicy) -> Result<(), L::Error> {
+        log_info!(
+            "Restoring previous state of {} device (on: {}, brightness: {} lm, temperature: {} K)",
+            light.kind(),
+            self.on,
+            self.brightness_in_lumen,
+            self.temperature_in_kelvin
+        );
+
+        ExpectedState::from(*self).write(light, None, retry_policy)
+    }
+}
+
+/// The parts of a device's state we've just changed, which are read back to check they were
+/// applied with `--verify-writes`.
+#[derive(Debug, Clone, Copy, Default)]
+struct ExpectedState {
+    on: Option<bool>,
+    brightness_in_lumen: Option<u16>,
+    temperature_in_kelvin: Option<u16>,
+}
+
+impl From<DeviceState> for ExpectedState {
+    fn from(state: DeviceState) -> Self {
+        ExpectedState {
+            on: Some(state.on),
+            brightness_in_lumen: Some(state.brightness_in_lumen),
+            temperature_in_kelvin: Some(state.temperature_in_kelvin),
+        }
+    }
+}
+
+impl ExpectedState {
+    /// Describes each part of `state` which doesn't match, e.g. `on: expected true, got false`.
+    fn mismatches(&self, state: &DeviceState) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if let Some(on) = self.on.filter(|on| *on != state.on) {
+            mismatches.push(format!("on: expected {}, got {}", on, state.on));
+        }
+        if let Some(brightness_in_lumen) = self
+            .brightness_in_lumen
+            .filter(|brightness_in_lumen| *brightness_in_lumen != state.brightness_in_lumen)
+        {
+            mismatches.push(format!(
+                "brightness: expected {} lm, got {} lm",
+                brightness_in_lumen, state.brightness_in_lumen
+            ));
+        }
+        if let Some(temperature_in_kelvin) = self
+            .temperature_in_kelvin
+            .filter(|temperature_in_kelvin| *temperature_in_kelvin != state.temperature_in_kelvin)
+        {
+            mismatches.push(format!(
+                "temperature: expected {} K, got {} K",
+                temperature_in_kelvin, state.temperature_in_kelvin
+            ));
+        }
+        mismatches
+    }
+
+    /// Writes the expected state to the device. When turning it on, the device is turned on
+    /// first (waiting for `write_delay`, if any), since some devices ignore settings while off.
+    /// Otherwise, it is turned off last.
+    fn write<L: Light>(
+        &self,
+        light: &L,
+        write_delay: Option<Duration>,
+        retry_policy: RetryPolicy,
+    ) -> Result<(), L::Error> {
+        if self.on == Some(true) {
+            retry(retry_policy, "turn on Litra device", || light.set_on(true))?;
+            if let Some(write_delay) = write_delay {
+                std::thread::sleep(write_delay);
+            }
+        }
+        if let Some(brightness_in_lumen) = self.brightness_in_lumen {
+            light.set_brightness_in_lumen(brightness_in_lumen)?;
+        }
+        if let Some(temperature_in_kelvin) = self.temperature_in_kelvin {
+            light.set_temperature_in_kelvin(temperature_in_kelvin)?;
+        }
+        if self.on == Some(false) {
+            retry(retry_policy, "turn off Litra device", || {
+                light.set_on(false)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Options controlling how the Litra device is opened and toggled.
+#[derive(Debug, Clone, Copy)]
+struct DeviceOptions {
+    open_strategy: OpenStrategy,
+    power_on_settings: PowerOnSettings,
+    observe: bool,
+    explain: bool,
+    restore_previous_state: bool,
+    idle_brightness_percentage: Option<u8>,
+    remember_settings: bool,
+    detect_external_changes: bool,
+    manual_override_grace_period: Option<Duration>,
+    brightness_step_percentage: u8,
+    temperature_step_in_kelvin: u16,
+    write_delays: WriteDelays,
+    verify_writes: bool,
+    fade: Option<Duration>,
+    quiet_hours: Option<QuietHours>,
+    retry_policy: RetryPolicy,
+}
+
+/// A step up or down in brightness or temperature, made with `ctl brightness-up` and friends.
+#[derive(Debug, Clone, Copy)]
+enum Adjustment {
+    Brightness { up: bool },
+    Temperature { up: bool },
+}
+
+/// What caused a device to be turned on or off. This is included in toggle logs and the session
+/// summary, so automatic toggling can be told apart from manual changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TriggerSource {
+    /// The webcam was turned on or off
+    Camera,
+    /// The `on`, `off` or `toggle` command, a command sent to the control socket, or `SIGUSR1`
+    Manual,
+    /// A request to the HTTP API
+    Remote,
+    /// A state forced with `ctl force-on` or `ctl force-off` expired
+    ForcedStateExpired,
+    /// A device was plugged in while the listener was running
+    Hotplug,
+    /// Maintenance mode was turned off with `ctl maintenance off`
+    MaintenanceEnded,
+    /// A snooze started with `ctl snooze` ended
+    SnoozeEnded,
+    /// The listener stopped, with `--turn-off-on-exit`
+    Exit,
+    /// A device had drifted from where it should be, with `--reconcile-interval`
+    Reconcile,
+}
+
+impl TriggerSource {
+    /// Whether the device is being brought into line with the webcam, rather than turned on or
+    /// off on request.
+    fn follows_webcam(self) -> bool {
+        matches!(
+            self,
+            TriggerSource::Camera
+                | TriggerSource::ForcedStateExpired
+                | TriggerSource::Hotplug
+                | TriggerSource::MaintenanceEnded
+                | TriggerSource::SnoozeEnded
+                | TriggerSource::Reconcile
+        )
+    }
+
+    /// Whether Elgato Key Lights are turned on or off too. They follow the webcam and requests to
+    /// turn devices on or off, but aren't affected by Litra devices being plugged in, maintenance
+    /// mode ending or Litra devices drifting.
+    fn moves_key_lights(self) -> bool {
+        matches!(
+            self,
+            TriggerSource::Camera
+                | TriggerSource::Manual
+                | TriggerSource::Remote
+                | TriggerSource::ForcedStateExpired
+                | TriggerSource::SnoozeEnded
+                | TriggerSource::Exit
+        )
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            TriggerSource::Camera => "camera",
+            TriggerSource::Manual => "manual",
+            TriggerSource::Remote => "remote",
+            TriggerSource::ForcedStateExpired => "forced-state-expired",
+            TriggerSource::Hotplug => "hotplug",
+            TriggerSource::MaintenanceEnded => "maintenance-ended",
+            TriggerSource::SnoozeEnded => "snooze-ended",
+            TriggerSource::Exit => "exit",
+            TriggerSource::Reconcile => "reconcile",
+        }
+    }
+}
+
+/// A state forced with `ctl force-on` or `ctl force-off`, which overrides automatic toggling until
+/// it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ForcedState {
+    on: bool,
+    until: Instant,
+}
+
+/// The Litra context, plus the device handles we keep open when using
+/// [`OpenStrategy::Persistent`], the states to restore when using `--restore-previous-state` and
+/// the settings remembered when using `--remember-settings`. Per-device state is keyed by serial
+/// number.
+struct DeviceContext {
+    context: Litra,
+    options: DeviceOptions,
+    persistent_device_handles: Vec<DeviceHandle>,
+    previous_states: HashMap<String, DeviceState>,
+    /// The brightness each device had before it was dimmed with `--idle-brightness-percentage`
+    /// or faded down with `--fade`, to go back to when the webcam turns on.
+    pre_idle_brightnesses: HashMap<String, u16>,
+    /// The fades started with `--fade` which haven't finished yet.
+    fades: Fades,
+    /// Where remembered settings are stored, with `--remember-settings`.
+    state_store: Option<Box<dyn StateStore>>,
+    state: State,
+    paused: bool,
+    /// When automatic toggling resumes by itself, if it was paused with `ctl snooze`.
+    snoozed_until: Option<Instant>,
+    /// Whether devices are left alone with `ctl maintenance on`, e.g. while their firmware is
+    /// updated, so we don't interleave our own HID traffic with the update.
+    maintenance: bool,
+    forced: Option<ForcedState>,
+    /// Whether the webcam was in use as of the last webcam event, so we can catch up when a
+    /// forced state expires.
+    webcam_in_use: bool,
+    /// The states we last set or saw the devices in, when using `--detect-external-changes`.
+    last_known_states: HashMap<String, DeviceState>,
+    /// When a device was last turned on or off outside litra-autotoggle, webcam events are
+    /// ignored until this time, with `--manual-override-grace-period`.
+    manual_override_until: Option<Instant>,
+    /// What the listener has done, for the summary printed when it stops.
+    session: Session,
+    /// Commands to run when the webcam is turned on or off.
+    camera_hooks: CameraHooks,
+    /// Why the listener did what it did about the most recent webcam event, for
+    /// `ctl explain-last`.
+    last_explanation: Option<Explanation>,
+    /// Settings changed with `ctl set`, until the listener restarts.
+    overrides: overrides::Overrides,
+    /// The Elgato Key Lights to turn on and off with the webcam.
+    key_lights: elgato::KeyLights,
+    /// What's needed to reload the profiles file with `ctl reload`.
+    profile_reloader: reload::ProfileReloader,
+}
+
+impl DeviceContext {
+    fn new(options: DeviceOptions, profile: Option<&str>) -> Result<Self, CliError> {
+        let state_store: Option<Box<dyn StateStore>> = if options.remember_settings {
+            match default_state_file_path(profile) {
+                Some(path) => Some(Box::new(JsonFileStore::new(path))),
+                None => {
+                    log_error!("Unable to determine where to store remembered settings, so settings won't be remembered");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let state = match &state_store {
+            Some(state_store) => state_store.load().unwrap_or_else(|error| {
+                log_error!(
+                    "Failed to load remembered settings from {}: {}",
+                    state_store,
+                    error
+                );
+                State::default()
+            }),
+            None => State::default(),
+        };
+
+        Ok(DeviceContext {
+            context: Litra::new()?,
+            options,
+            persistent_device_handles: Vec::new(),
+            previous_states: HashMap::new(),
+            pre_idle_brightnesses: HashMap::new(),
+            fades: Fades::default(),
+            state_store,
+            state,
+            paused: false,
+            snoozed_until: None,
+            maintenance: false,
+            forced: None,
+            webcam_in_use: false,
+            last_known_states: HashMap::new(),
+            manual_override_until: None,
+            session: Session::new(),
+            camera_hooks: CameraHooks::default(),
+            last_explanation: None,
+            overrides: overrides::Overrides::new(options),
+            key_lights: elgato::KeyLights::default(),
+            profile_reloader: reload::ProfileReloader::default(),
+        })
+    }
+
+    /// The conditions which, along with `trigger`, decide whether devices are turned on or off.
+    /// Quiet hours are only checked when they matter, since that means asking the operating
+    /// system for the time.
+    fn conditions(&self, trigger: Trigger) -> Conditions {
+        Conditions {
+            maintenance: self.maintenance,
+            paused: self.paused,
+            forced: self.forced.map(|forced| desired_state::Forced {
+                on: forced.on,
+                seconds_remaining: forced
+                    .until
+                    .saturating_duration_since(Instant::now())
+                    .as_secs(),
+            }),
+            manual_override: self.manual_override_seconds_remaining(),
+            quiet_hours: self
+                .options
+                .quiet_hours
+                .filter(|quiet_hours| trigger.turns_on() && quiet_hours.is_now())
+                .map(|quiet_hours| quiet_hours.to_string()),
+        }
+    }
+
+    /// How long is left of the grace period after a device was turned on or off outside
+    /// litra-autotoggle, if it hasn't ended yet.
+    fn manual_override_seconds_remaining(&self) -> Option<u64> {
+        let remaining = self
+            .manual_override_until?
+            .checked_duration_since(Instant::now())?;
+        // Round up, so we don't report 0s left while the grace period is still running
+        Some(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0))
+    }
+
+    fn remembered_settings<L: Light>(&self, light: &L) -> Option<DeviceSettings> {
+        // Settings are only remembered when using `--remember-settings`
+        self.state_store.as_ref()?;
+
+        let serial_number = light.serial_number().ok()??;
+        self.state.devices.get(&serial_number).copied()
+    }
+
+    /// Records the device's current brightness and temperature in the state file, so they can be
+    /// applied the next time the device is turned on. Failures are logged rather than returned,
+    /// since they shouldn't stop the device from being toggled.
+    fn remember_settings<L: Light>(&mut self, light: &L) {
+        if self.state_store.is_none() {
+            return;
+        }
+
+        let Ok(Some(serial_number)) = light.serial_number() else {
+            return;
+        };
+
+        let settings = match (light.brightness_in_lumen(), light.temperature_in_kelvin()) {
+            (Ok(brightness_in_lumen), Ok(temperature_in_kelvin)) => DeviceSettings {
+                brightness_in_lumen,
+                temperature_in_kelvin,
+            },
+            (Err(error), _) | (_, Err(error)) => {
+                log_error!("Failed to read settings to remember: {}", error);
+                return;
+            }
+        };
+
+        self.state.devices.insert(serial_number, settings);
+        self.save_state();
+    }
+
+    fn save_state(&self) {
+        let Some(state_store) = &self.state_store else {
+            return;
+        };
+
+        if let Err(error) = state_store.save(&self.state) {
+            log_error!("Failed to save state to {}: {}", state_store, error);
+        }
+    }
+
+    fn keep_device_handles_if_persistent(&mut self, device_handles: Vec<DeviceHandle>) {
+        if self.options.open_strategy == OpenStrategy::Persistent {
+            self.persistent_device_handles = device_handles;
+        }
+    }
+
+    /// Takes the persistent device handles, if we have one for every selected device.
+    fn take_persistent_device_handles(
+        &mut self,
+        device_filter: &DeviceFilter,
+    ) -> Vec<DeviceHandle> {
+        let device_handles = std::mem::take(&mut self.persistent_device_handles);
+        // A device that wasn't connected when we opened the others needs to be looked for again
+        if device_handles.len() < device_filter.serial_numbers.len() {
+            return Vec::new();
+        }
+
+        device_handles
+    }
+
+    fn set_on<L: Light>(&mut self, light: &L, on: bool) -> Result<(), L::Error> {
+        let serial_number = get_serial_number_with_fallback(light);
+
+        if on {
+            // If the device is turned on while it is already on, keep the state from before the
+            // first time we turned it on.
+            if self.options.restore_previous_state
+                && !self.previous_states.contains_key(&serial_number)
+            {
+                self.previous_states
+                    .insert(serial_number.clone(), DeviceState::read(light)?);
+            }
+
+            let remembered_settings = self.remembered_settings(light);
+            let mut settings = self
+                .options
+                .power_on_settings
+                .resolve(light, remembered_settings);
+            // If the device was dimmed when the webcam turned off, put it back how it was
+            if let Some(brightness_in_lumen) = self.pre_idle_brightnesses.remove(&serial_number) {
+                settings
+                    .brightness_in_lumen
+                    .get_or_insert(brightness_in_lumen);
+            }
+
+            // With `--fade`, fade up from the dimmest brightness to the brightness the device would
+            // otherwise have been turned on at. A device which is still fading from an earlier
+            // change fades from wherever it has got to.
+            let fading = self.fades.cancel(&serial_number);
+            let fade = match self.fade_for(light) {
+                Some(duration) => {
+                    let to = match settings.brightness_in_lumen {
+                        Some(brightness_in_lumen) => brightness_in_lumen,
+                        None => light.brightness_in_lumen()?,
+                    };
+                    let from = match fading {
+                        Some(_) => light.brightness_in_lumen()?,
+                        None => light.minimum_brightness_in_lumen(),
+                    };
+                    Some(Fade::new(from, to, duration, FadeEnd::StayOn))
+                }
+                None => None,
+            };
+
+            retry(self.options.retry_policy, "turn on Litra device", || {
+                light.set_on(true)
+            })?;
+            if let Some(write_delay) = self.options.write_delays.for_light(light) {
+                std::thread::sleep(write_delay);
+            }
+
+            // The device ignores changes while it is off, so it is only dimmed for the fade once
+            // it is on. The brightness is left to the fade, and checked once it finishes.
+            if let Some(fade) = fade {
+                light.set_brightness_in_lumen(fade.from)?;
+                settings.brightness_in_lumen = None;
+                self.fades.start(serial_number, fade);
+            }
+            settings.write(light, None, self.options.retry_policy)?;
+
+            self.verify_write(
+                light,
+                ExpectedState {
+                    on: Some(true),
+                    ..settings
+                },
+            )?;
+        } else {
+            let fading = self.fades.cancel(&serial_number);
+            self.remember_settings(light);
+            let previous_state = self.previous_states.get(&serial_number).copied();
+
+            // With `--fade`, fade down to the dimmest brightness, and only then turn the device
+            // off, unless it is being restored to a state where it was on
+            match self.fade_for(light) {
+                Some(duration) if !previous_state.is_some_and(|state| state.on) => {
+                    let brightness_in_lumen = light.brightness_in_lumen()?;
+                    // The device can't be brightened again once it is off, so it goes back to
+                    // this brightness the next time it is turned on
+                    self.pre_idle_brightnesses
+                        .entry(serial_number.clone())
+                        .or_insert(fading_to_or(fading, brightness_in_lumen));
+                    self.fades.start(
+                        serial_number,
+                        Fade::new(
+                            brightness_in_lumen,
+                            light.minimum_brightness_in_lumen(),
+                            duration,
+                            FadeEnd::TurnOff,
+                        ),
+                    );
+                    return Ok(());
+                }
+                _ => self.finish_turning_off(light)?,
+            }
+        }
+
+        self.record_known_state(light);
+
+        Ok(())
+    }
+
+    /// Turns the device off, or puts it back how it was with `--restore-previous-state`. With
+    /// `--fade`, this happens once it has faded down.
+    fn finish_turning_off<L: Light>(&mut self, light: &L) -> Result<(), L::Error> {
+        let serial_number = get_serial_number_with_fallback(light);
+        if let Some(previous_state) = self.previous_states.get(&serial_number).copied() {
+            previous_state.restore(light, self.options.retry_policy)?;
+            self.previous_states.remove(&serial_number);
+            self.verify_write(light, ExpectedState::from(previous_state))
+        } else {
+            retry(self.options.retry_policy, "turn off Litra device", || {
+                light.set_on(false)
+            })?;
+            self.verify_write(
+                light,
+                ExpectedState {
+                    on: Some(false),
+                    ..ExpectedState::default()
+                },
+            )
+        }
+    }
+
+    /// How long to fade the device for with `--fade`, if at all. Devices without a serial number
+    /// can't be found again to step the fade, so they change all at once.
+    fn fade_for<L: Light>(&self, light: &L) -> Option<Duration> {
+        self.options
+            .fade
+            .filter(|_| matches!(light.serial_number(), Ok(Some(_))))
+    }
+
+    /// Finishes a fade started with `--fade`, setting the device to the brightness it faded to,
+    /// and turning it off if that's what the fade was for.
+    fn finish_fade<L: Light>(&mut self, light: &L, to: u16, end: FadeEnd) -> Result<(), L::Error> {
+        light.set_brightness_in_lumen(to)?;
+        match end {
+            FadeEnd::StayOn => self.verify_write(
+                light,
+                ExpectedState {
+                    brightness_in_lumen: Some(to),
+                    ..ExpectedState::default()
+                },
+            )?,
+            FadeEnd::TurnOff => self.finish_turning_off(light)?,
+        }
+        self.record_known_state(light);
+
+        Ok(())
+    }
+
+    /// Dims the device to `percentage` of its brightness range, turning it on if it is off, rather
+    /// than turning it off when the webcam turns off. With `--fade`, it fades down.
+    fn set_idle<L: Light>(&mut self, light: &L, percentage: u8) -> Result<(), L::Error> {
+        let serial_number = get_serial_number_with_fallback(light);
+        self.remember_settings(light);
+
+        let idle_brightness_in_lumen = percentage_within_range(
+            percentage,
+            light.minimum_brightness_in_lumen(),
+            light.maximum_brightness_in_lumen(),
+        );
+        let fading = self.fades.cancel(&serial_number);
+        let brightness_in_lumen = light.brightness_in_lumen()?;
+        // If it is already dimmed, keep the brightness from before it was first dimmed
+        self.pre_idle_brightnesses
+            .entry(serial_number.clone())
+            .or_insert(fading_to_or(fading, brightness_in_lumen));
+
+        let was_on = light.is_on()?;
+        let mut expected_brightness_in_lumen = Some(idle_brightness_in_lumen);
+        match self.fade_for(light) {
+            Some(duration) if was_on => {
+                // The brightness is checked once the fade finishes
+                self.fades.start(
+                    serial_number,
+                    Fade::new(
+                        brightness_in_lumen,
+                        idle_brightness_in_lumen,
+                        duration,
+                        FadeEnd::StayOn,
+                    ),
+                );
+                expected_brightness_in_lumen = None;
+            }
+            _ => {
+                if !was_on {
+                    retry(self.options.retry_policy, "turn on Litra device", || {
+                        light.set_on(true)
+                    })?;
+                    if let Some(write_delay) = self.options.write_delays.for_light(light) {
+                        std::thread::sleep(write_delay);
+                    }
+                }
+                light.set_brightness_in_lumen(idle_brightness_in_lumen)?;
+            }
+        }
+
+        self.verify_write(
+            light,
+            ExpectedState {
+                on: Some(true),
+                brightness_in_lumen: expected_brightness_in_lumen,
+                ..ExpectedState::default()
+            },
+        )?;
+        self.record_known_state(light);
+
+        Ok(())
+    }
+
+    /// With `--verify-writes`, reads the device's state back to check that `expected` was
+    /// applied. If it wasn't, it is written once more, and a warning is printed if it still
+    /// wasn't applied. Verified states are recorded in the state file, if there is one.
+    fn verify_write<L: Light>(
+        &mut self,
+        light: &L,
+        expected: ExpectedState,
+    ) -> Result<(), L::Error> {
+        if !self.options.verify_writes {
+            return Ok(());
+        }
+
+        let mismatches = expected.mismatches(&DeviceState::read(light)?);
+        if mismatches.is_empty() {
+            self.record_verified_state(light);
+            return Ok(());
+        }
+
+        log_error!(
+            "{} device (serial number: {}) didn't apply the change ({}), so trying again",
+            light.kind(),
+            get_serial_number_with_fallback(light),
+            mismatches.join(", ")
+        );
+        expected.write(
+            light,
+            self.options.write_delays.for_light(light),
+            self.options.retry_policy,
+        )?;
+
+        let mismatches = expected.mismatches(&DeviceState::read(light)?);
+        if mismatches.is_empty() {
+            self.record_verified_state(light);
+        } else {
+            log_error!(
+                "Warning: {} device (serial number: {}) still didn't apply the change ({})",
+                light.kind(),
+                get_serial_number_with_fallback(light),
+                mismatches.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records the device's current state in the state file, once it has been verified with
+    /// `--verify-writes`.
+    fn record_verified_state<L: Light>(&mut self, light: &L) {
+        if self.state_store.is_none() {
+            return;
+        }
+
+        let Ok(Some(serial_number)) = light.serial_number() else {
+            return;
+        };
+        let Ok(state) = DeviceState::read(light) else {
+            return;
+        };
+
+        self.state.verified_states.insert(
+            serial_number,
+            VerifiedState {
+                on: state.on,
+                brightness_in_lumen: state.brightness_in_lumen,
+                temperature_in_kelvin: state.temperature_in_kelvin,
+                verified_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or_default(),
+            },
+        );
+        self.save_state();
+    }
+
+    /// Records the state we left the device in when using `--detect-external-changes`, so we
+    /// don't mistake our own change for someone else's.
+    fn record_known_state<L: Light>(&mut self, light: &L) {
+        if !self.options.detect_external_changes {
+            return;
+        }
+
+        let serial_number = get_serial_number_with_fallback(light);
+        match DeviceState::read(light) {
+            Ok(state) => {
+                self.last_known_states.insert(serial_number, state);
+            }
+            Err(_) => {
+                self.last_known_states.remove(&serial_number);
+            }
+        }
+    }
+
+    /// Steps the brightness or temperature of the device up or down, staying within the range
+    /// the device supports.
+    fn adjust<L: Light>(&mut self, light: &L, adjustment: Adjustment) -> Result<(), L::Error> {
+        // Stepping the brightness or temperature by hand takes over from a fade
+        self.fades.cancel(&get_serial_number_with_fallback(light));
+        match adjustment {
+            Adjustment::Brightness { up } => {
+                let minimum = light.minimum_brightness_in_lumen();
+                let maximum = light.maximum_brightness_in_lumen();
+                let step = percentage_within_range(
+                    self.options.brightness_step_percentage,
+                    0,
+                    maximum - minimum,
+                )
+                .max(1);
+                let current = light.brightness_in_lumen()?;
+                let brightness_in_lumen = if up {
+                    current.saturating_add(step).min(maximum)
+                } else {
+                    current.saturating_sub(step).max(minimum)
+                };
+
+                log_info!(
+                    "Changing brightness of {} device (serial number: {}) from {} lm to {} lm",
+                    light.kind(),
+                    get_serial_number_with_fallback(light),
+                    current,
+                    brightness_in_lumen
+                );
+                light.set_brightness_in_lumen(brightness_in_lumen)?;
+                self.verify_write(
+                    light,
+                    ExpectedState {
+                        brightness_in_lumen: Some(brightness_in_lumen),
+                        ..ExpectedState::default()
+                    },
+                )?;
+            }
+            Adjustment::Temperature { up } => {
+                let minimum = light.minimum_temperature_in_kelvin();
+                let maximum = light.maximum_temperature_in_kelvin();
+                let step = self.options.temperature_step_in_kelvin;
+                let current = light.temperature_in_kelvin()?;
+                let temperature_in_kelvin = if up {
+                    current.saturating_add(step).min(maximum)
+                } else {
+                    current.saturating_sub(step).max(minimum)
+                };
+
+                log_info!(
+                    "Changing temperature of {} device (serial number: {}) from {} K to {} K",
+                    light.kind(),
+                    get_serial_number_with_fallback(light),
+                    current,
+                    temperature_in_kelvin
+                );
+                light.set_temperature_in_kelvin(temperature_in_kelvin)?;
+                self.verify_write(
+                    light,
+                    ExpectedState {
+                        temperature_in_kelvin: Some(temperature_in_kelvin),
+                        ..ExpectedState::default()
+                    },
+                )?;
+            }
+        }
+
+        self.record_known_state(light);
+
+        Ok(())
+    }
+}
+
+/// The brightness a fade which was interrupted was heading for if it was brightening the device
+/// or dimming it while leaving it on, since that's the device's usual brightness, or otherwise
+/// `brightness_in_lumen`.
+fn fading_to_or(fading: Option<Fade>, brightness_in_lumen: u16) -> u16 {
+    match fading {
+        Some(Fade {
+            to,
+            end: FadeEnd::StayOn,
+            ..
+        }) => to,
+        _ => brightness_in_lumen,
+    }
+}
+
+/// Takes the next step of each fade started with `--fade` which is due, finishing those which are
+/// over. The device worker runs this between other requests, so fades don't hold it up, and
+/// every device fades at once.
+fn step_fades(device_context: &mut DeviceContext, device_filter: &DeviceFilter) {
+    let steps = device_context.fades.take_due_steps(Instant::now());
+    if steps.is_empty() {
+        return;
+    }
+
+    let persistent = !device_context.persistent_device_handles.is_empty();
+    let device_handles = if persistent {
+        std::mem::take(&mut device_context.persistent_device_handles)
+    } else {
+        get_selected_devices(
+            &mut device_context.context,
+            device_filter,
+            false,
+            device_context.options.retry_policy,
+        )
+        .unwrap_or_else(|error| {
+            log_error!("Failed to open Litra device to fade it: {}", error);
+            Vec::new()
+        })
+    };
+
+    for (serial_number, step) in steps {
+        let Some(device_handle) = device_handles
+            .iter()
+            .find(|device_handle| get_serial_number_with_fallback(*device_handle) == serial_number)
+        else {
+            if let FadeStep::Finished { .. } = step {
+                log_error!(
+                    "Litra device (serial number: {}) disappeared before it finished fading",
+                    serial_number
+                );
+            }
+            continue;
+        };
+
+        let result = match step {
+            FadeStep::Brightness(brightness_in_lumen) => {
+                device_handle.set_brightness_in_lumen(brightness_in_lumen)
+            }
+            FadeStep::Finished { to, end } => device_context.finish_fade(device_handle, to, end),
+        };
+        if let Err(error) = result {
+            log_error!(
+                "Failed to fade {} device (serial number: {}): {}",
+                device_handle.device_type(),
+                serial_number,
+                error
+            );
+        }
+    }
+
+    if persistent {
+        device_context.persistent_device_handles = device_handles;
+    }
+}
+
+/// Steps any fades started with `--fade` until they have finished, for commands which exit
+/// straight afterwards, rather than leaving them to the device worker.
+fn finish_fades(device_context: &mut DeviceContext, device_filter: &DeviceFilter) {
+    while let Some(wait) = device_context.fades.next_step_in(Instant::now()) {
+        std::thread::sleep(wait);
+        step_fades(device_context, device_filter);
+    }
+}
+
+/// Logs that a device is being turned on or off (or dimmed, with `--idle-brightness-percentage`),
+/// with `action` describing which, e.g. `Turning on`.
+fn print_toggle_log(
+    light: &impl Light,
+    action: &str,
+    trigger_source: TriggerSource,
+    camera: Option<&str>,
+) {
+    match camera {
+        Some(camera) => log_info!(
+            "{} {} device (serial number: {}, trigger source: {}, triggered by: {})",
+            action,
+            light.kind(),
+            get_serial_number_with_fallback(light),
+            trigger_source.as_str(),
+            camera
+        ),
+        None => log_info!(
+            "{} {} device (serial number: {}, trigger source: {})",
+            action,
+            light.kind(),
+            get_serial_number_with_fallback(light),
+            trigger_source.as_str()
+        ),
+    }
+}
+
+/// Turns each of `lights` on or off, carrying on with the rest if one fails. Returns the first
+/// error, along with the positions in `lights` of every light which failed. Lights are turned on
+/// in order, and off in reverse order.
+fn set_devices_on_and_log<L: Light>(
+    device_context: &mut DeviceContext,
+    lights: &[&L],
+    trigger_source: TriggerSource,
+    camera: Option<&str>,
+    on: bool,
+) -> Result<(), (L::Error, Vec<usize>)> {
+    let mut order: Vec<usize> = (0..lights.len()).collect();
+    if !on {
+        order.reverse();
+    }
+
+    let mut first_error = None;
+    let mut failed = Vec::new();
+    for index in order {
+        let light = lights[index];
+        // With `--idle-brightness-percentage`, devices are dimmed rather than turned off when
+        // the webcam turns off
+        let idle_brightness_percentage = device_context
+            .options
+            .idle_brightness_percentage
+            .filter(|_| !on && trigger_source.follows_webcam());
+
+        // What's being done, for logs and notifications
+        let (action, in_progress, done) = match idle_brightness_percentage {
+            Some(_) => ("dim", "Dimming", "Dimmed"),
+            None if on => ("turn on", "Turning on", "Turned on"),
+            None => ("turn off", "Turning off", "Turned off"),
+        };
+
+        print_toggle_log(light, in_progress, trigger_source, camera);
+        let toggle_result = match idle_brightness_percentage {
+            Some(percentage) => device_context.set_idle(light, percentage),
+            None => device_context.set_on(light, on),
+        };
+
+        logging::log_structured_only(
+            &Event {
+                kind: "toggle",
+                message: format!("{} {} device", done, light.kind()),
+                serial_number: Some(get_serial_number_with_fallback(light)),
+                camera: camera.map(str::to_string),
+                on: Some(on),
+                trigger_source: Some(trigger_source.as_str()),
+                outcome: Some(if toggle_result.is_ok() {
+                    "succeeded"
+                } else {
+                    "failed"
+                }),
+                error: toggle_result.as_ref().err().map(|error| error.to_string()),
+                ..Event::default()
+            },
+            toggle_result.is_err(),
+        );
+
+        match &toggle_result {
+            Ok(()) => notifications::notify(
+                Severity::Info,
+                &format!("{} {}", done, light.kind()),
+                camera,
+            ),
+            Err(error) => notifications::notify(
+                Severity::Error,
+                &format!("Failed to {} {}: {}", action, light.kind(), error),
+                camera,
+            ),
+        }
+
+        match toggle_result {
+            Ok(()) => device_context
+                .session
+                .record_toggle(describe_device(light), trigger_source.as_str()),
+            Err(error) => {
+                failed.push(index);
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+
+    match first_error {
+        Some(error) => Err((error, failed)),
+        None => Ok(()),
+    }
+}
+
+fn set_selected_devices_on_and_log(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    trigger_source: TriggerSource,
+    camera: Option<&str>,
+    on: bool,
+) -> Result<(), CliError> {
+    if device_context.options.observe {
+        log_info!(
+            "Not turning {} Litra device as observe mode is enabled (trigger source: {}, triggered by: {})",
+            if on { "on" } else { "off" },
+            trigger_source.as_str(),
+            camera.unwrap_or("unknown camera")
+        );
+        return Ok(());
+    }
+    if device_context.maintenance {
+        return Err(CliError::MaintenanceMode);
+    }
+
+    if trigger_source.moves_key_lights() {
+        device_context.key_lights.set_on(on);
+    }
+
+    // The serial numbers of devices already turned on or off with their persistent handle, so
+    // they aren't turned on or off again if the others need to be reopened
+    let mut done_serial_numbers = Vec::new();
+    let device_handles = device_context.take_persistent_device_handles(device_filter);
+    if !device_handles.is_empty() {
+        let lights: Vec<&DeviceHandle> = device_handles.iter().collect();
+        match set_devices_on_and_log(device_context, &lights, trigger_source, camera, on) {
+            Ok(()) => {
+                device_context.persistent_device_handles = device_handles;
+                return Ok(());
+            }
+            Err((error, failed)) => {
+                log_error!(
+                    "Failed to toggle device using persistent handle, reopening device: {}",
+                    error
+                );
+                done_serial_numbers = lights
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| !failed.contains(index))
+                    .filter_map(|(_, light)| light.serial_number().ok().flatten())
+                    .collect();
+            }
+        }
+    }
+
+    let device_handles = get_selected_devices(
+        &mut device_context.context,
+        device_filter,
+        require_device,
+        device_context.options.retry_policy,
+    )?;
+    if device_handles.is_empty() {
+        print_device_not_found_log(device_filter);
+        notifications::notify(
+            Severity::Warning,
+            &describe_device_not_found(device_filter),
+            camera,
+        );
+        return Ok(());
+    }
+
+    // Devices without a serial number can't be told apart, so they are all tried again
+    let lights: Vec<&DeviceHandle> = device_handles
+        .iter()
+        .filter(|device_handle| {
+            device_handle
+                .serial_number()
+                .ok()
+                .flatten()
+                .is_none_or(|serial_number| !done_serial_numbers.contains(&serial_number))
+        })
+        .collect();
+    set_devices_on_and_log(device_context, &lights, trigger_source, camera, on)
+        .map_err(|(error, _)| error)?;
+    device_context.keep_device_handles_if_persistent(device_handles);
+
+    Ok(())
+}
+
+/// Turns the selected devices on or off in response to `trigger`, unless the rules in
+/// [`desired_state`] decide to leave them as they are.
+fn set_selected_devices_on_and_log_if_decided(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    camera: Option<&str>,
+    trigger: Trigger,
+) -> Result<(), CliError> {
+    let conditions = device_context.conditions(trigger);
+    match desired_state::decide(trigger, &conditions) {
+        Decision::Turn { on } => set_selected_devices_on_and_log(
+            device_context,
+            device_filter,
+            require_device,
+            match trigger {
+                Trigger::Camera { .. } => TriggerSource::Camera,
+                Trigger::Hotplug { .. } => TriggerSource::Hotplug,
+                Trigger::ForcedStateExpired { .. } => TriggerSource::ForcedStateExpired,
+                Trigger::MaintenanceEnded { .. } => TriggerSource::MaintenanceEnded,
+                Trigger::SnoozeEnded { .. } => TriggerSource::SnoozeEnded,
+                Trigger::Reconcile { .. } => TriggerSource::Reconcile,
+            },
+            camera,
+            on,
+        ),
+        Decision::Skip { on, reason } => {
+            log_info!(
+                "Not turning {} Litra device as {}",
+                if on { "on" } else { "off" },
+                reason
+            );
+            Ok(())
+        }
+    }
+}
+
+fn turn_on_selected_devices_and_log(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    camera: Option<&str>,
+    app: Option<&str>,
+) -> Result<(), CliError> {
+    if !device_context.webcam_in_use {
+        device_context.camera_hooks.run(true, camera, app);
+    }
+    device_context.webcam_in_use = true;
+    record_explanation(device_context, device_filter, camera, true);
+
+    set_selected_devices_on_and_log_if_decided(
+        device_context,
+        device_filter,
+        require_device,
+        camera,
+        Trigger::Camera { on: true },
+    )
+}
+
+fn turn_off_selected_devices_and_log(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    camera: Option<&str>,
+    app: Option<&str>,
+) -> Result<(), CliError> {
+    if device_context.webcam_in_use {
+        device_context.camera_hooks.run(false, camera, app);
+    }
+    device_context.webcam_in_use = false;
+    record_explanation(device_context, device_filter, camera, false);
+
+    set_selected_devices_on_and_log_if_decided(
+        device_context,
+        device_filter,
+        require_device,
+        camera,
+        Trigger::Camera { on: false },
+    )
+}
+
+/// Steps the brightness or temperature of each selected device up or down. Carries on with the
+/// rest of the devices if one fails, and returns the first error.
+fn adjust_selected_devices(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    adjustment: Adjustment,
+) -> Result<(), CliError> {
+    if device_context.options.observe {
+        log_info!("Not adjusting Litra device as observe mode is enabled");
+        return Ok(());
+    }
+    if device_context.maintenance {
+        return Err(CliError::MaintenanceMode);
+    }
+
+    let device_handles = get_selected_devices(
+        &mut device_context.context,
+        device_filter,
+        require_device,
+        device_context.options.retry_policy,
+    )?;
+    if device_handles.is_empty() {
+        print_device_not_found_log(device_filter);
+        return Ok(());
+    }
+
+    let mut result = Ok(());
+    for device_handle in &device_handles {
+        if let Err(error) = device_context.adjust(device_handle, adjustment) {
+            if result.is_ok() {
+                result = Err(CliError::DeviceError(error));
+            }
+        }
+    }
+
+    result
+}
+
+fn describe_device_not_found(device_filter: &DeviceFilter) -> String {
+    match device_filter.serial_numbers.as_slice() {
+        [] => "No Litra devices found".to_string(),
+        [serial_number] => format!(
+            "Litra device with serial number {} not found",
+            serial_number
+        ),
+        serial_numbers => format!(
+            "Litra devices with serial numbers {} not found",
+            serial_numbers.join(", ")
+        ),
+    }
+}
+
+fn print_device_not_found_log(device_filter: &DeviceFilter) {
+    log_info!("{}", describe_device_not_found(device_filter));
+}
+
+/// Describes a light for the session summary, e.g. `Litra Glow (serial number: ABC)`.
+fn describe_device(light: &impl Light) -> String {
+    format!(
+        "{} (serial number: {})",
+        light.kind(),
+        get_serial_number_with_fallback(light)
+    )
+}
+
+/// Returns the light's serial number, or `-` if it doesn't have one or it can't be read. This is
+/// only a label for logs, so it isn't retried: that would hold up the device worker for a device
+/// which is already failing.
+fn get_serial_number_with_fallback(light: &impl Light) -> String {
+    match light.serial_number() {
+        Ok(Some(serial_number)) => serial_number.to_string(),
+        Ok(None) | Err(_) => "-".to_string(),
+    }
+}
+
+/// Reads the state of each selected device, returning it along with the device's serial number.
+/// Nothing is read while maintenance mode is on.
+fn read_selected_device_states(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+) -> Result<Vec<(String, DeviceState)>, CliError> {
+    if device_context.maintenance {
+        return Ok(Vec::new());
+    }
+
+    let device_handles = device_context.take_persistent_device_handles(device_filter);
+    if !device_handles.is_empty() {
+        if let Ok(states) = read_device_states(&device_handles) {
+            device_context.persistent_device_handles = device_handles;
+            return Ok(states);
+        }
+    }
+
+    let device_handles = get_selected_devices(
+        &mut device_context.context,
+        device_filter,
+        false,
+        device_context.options.retry_policy,
+    )?;
+    let states = read_device_states(&device_handles)?;
+    device_context.keep_device_handles_if_persistent(device_handles);
+    Ok(states)
+}
+
+fn read_device_states<L: Light>(lights: &[L]) -> Result<Vec<(String, DeviceState)>, L::Error> {
+    lights
+        .iter()
+        .map(|light| {
+            Ok((
+                get_serial_number_with_fallback(light),
+                DeviceState::read(light)?,
+            ))
+        })
+        .collect()
+}
+
+/// Periodically reads the state of the Litra device, so that aggressive USB power management
+/// doesn't put it (or the hub it is connected to) to sleep between toggles.
+async fn keep_device_awake(
+    worker: DeviceWorker,
+    device_filter: DeviceFilter,
+    keepalive_interval: Duration,
+    verbose: bool,
+) {
+    // The first tick is a full interval away, since we've only just looked for the device
+    let mut interval = CoalescedInterval::new(keepalive_interval);
+
+    loop {
+        interval.tick().await;
+
+        let device_filter = device_filter.clone();
+        match worker
+            .run(move |context| read_selected_device_states(context, &device_filter))
+            .await
+        {
+            Ok(states) if !states.is_empty() => {
+                if verbose {
+                    log_info!("Sent keepalive to Litra device");
+                }
+            }
+            Ok(_) => {
+                if verbose {
+                    log_info!("Skipped keepalive as no Litra device was found");
+                }
+            }
+            Err(error) => log_error!("Failed to send keepalive to Litra device: {}", error),
+        }
+    }
+}
+
+/// Describes the differences between two states of a device, e.g. `turned on, brightness
+/// changed from 100 lm to 200 lm`.
+fn describe_state_changes(previous: &DeviceState, current: &DeviceState) -> String {
+    let mut changes = Vec::new();
+    if previous.on != current.on {
+        changes.push(format!("turned {}", if current.on { "on" } else { "off" }));
+    }
+    if previous.brightness_in_lumen != current.brightness_in_lumen {
+        changes.push(format!(
+            "brightness changed from {} lm to {} lm",
+            previous.brightness_in_lumen, current.brightness_in_lumen
+        ));
+    }
+    if previous.temperature_in_kelvin != current.temperature_in_kelvin {
+        changes.push(format!(
+            "temperature changed from {} K to {} K",
+            previous.temperature_in_kelvin, current.temperature_in_kelvin
+        ));
+    }
+
+    changes.join(", ")
+}
+
+/// Periodically reads the state of the Litra device, and logs any changes we didn't make
+/// ourselves, so conflicts with other tools (like Logitech's own software) are visible.
+async fn watch_for_external_changes(
+    worker: DeviceWorker,
+    device_filter: DeviceFilter,
+    interval: Duration,
+    #[cfg_attr(target_os = "windows", allow(unused_variables))] notify: bool,
+) {
+    let mut interval = CoalescedInterval::immediate(interval);
+
+    loop {
+        interval.tick().await;
+
+        let device_filter = device_filter.clone();
+        let result = worker
+            .run(move |context| {
+                let states = read_selected_device_states(context, &device_filter)?;
+
+                let changes = states
+                    .into_iter()
+                    .filter_map(|(serial_number, state)| {
+                        let previous_state = context
+                            .last_known_states
+                            .insert(serial_number.clone(), state)?;
+                        (previous_state != state).then_some((serial_number, previous_state, state))
+                    })
+                    .collect::<Vec<_>>();
+
+                // Respect the device being turned on or off by hand, rather than undoing it at
+                // the next webcam event
+                if let Some(grace_period) = context.options.manual_override_grace_period {
+                    if changes
+                        .iter()
+                        .any(|(_, previous_state, state)| previous_state.on != state.on)
+                    {
+                        context.manual_override_until = Some(Instant::now() + grace_period);
+                        log_info!(
+                            "Leaving Litra device as it was set by hand for {}, rather than toggling it for webcam events",
+                            duration::format_duration(grace_period)
+                        );
+                    }
+                }
+
+                Ok::<_, CliError>(changes)
+            })
+            .await;
+
+        match result {
+            Ok(changes) => {
+                for (serial_number, previous_state, state) in changes {
+                    let message = format!(
+                        "Litra device (serial number: {}) was changed outside litra-autotoggle: {}",
+                        serial_number,
+                        describe_state_changes(&previous_state, &state)
+                    );
+                    log_info!("{}", message);
+
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    if notify {
+                        if let Err(error) = notifications::show_desktop_notification(&message) {
+                            log_error!("Failed to show desktop notification: {}", error);
+                        }
+                    }
+                }
+            }
+            Err(error) => log_error!(
+                "Failed to check Litra device for external changes: {}",
+                error
+            ),
+        }
+    }
+}
+
+/// Turns the selected devices which aren't on or off as they should be back on or off, e.g. after
+/// a USB glitch or other software changing them, with `--reconcile-interval`. Devices which are
+/// already right are left alone, so their brightness and temperature aren't touched.
+fn reconcile_selected_devices(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+) -> Result<(), CliError> {
+    if device_context.maintenance || device_context.options.observe {
+        return Ok(());
+    }
+
+    let trigger = Trigger::Reconcile {
+        webcam_in_use: device_context.webcam_in_use,
+    };
+    let conditions = device_context.conditions(trigger);
+    let Decision::Turn { on } = desired_state::decide(trigger, &conditions) else {
+        return Ok(());
+    };
+    // Devices can be on while the webcam is off with these, so we can't tell they've drifted
+    if !on
+        && (device_context.options.idle_brightness_percentage.is_some()
+            || device_context.options.restore_previous_state)
+    {
+        return Ok(());
+    }
+
+    let drifted_device_handles: Vec<DeviceHandle> = get_selected_devices(
+        &mut device_context.context,
+        device_filter,
+        false,
+        device_context.options.retry_policy,
+    )?
+    .into_iter()
+    .filter(|device_handle| device_handle.is_on().is_ok_and(|is_on| is_on != on))
+    .collect();
+    for device_handle in &drifted_device_handles {
+        log_info!(
+            "{} device (serial number: {}) should be {}, but isn't",
+            device_handle.device_type(),
+            get_serial_number_with_fallback(device_handle),
+            if on { "on" } else { "off" }
+        );
+    }
+
+    // We can't tell a device turned on or off by hand from one which drifted, so with a grace
+    // period, respect the change rather than undoing it straight away
+    if let Some(grace_period) = device_context.options.manual_override_grace_period {
+        if !drifted_device_handles.is_empty() {
+            device_context.manual_override_until = Some(Instant::now() + grace_period);
+            log_info!(
+                "Leaving Litra device as it was set by hand for {}, rather than turning it {}",
+                duration::format_duration(grace_period),
+                if on { "on" } else { "off" }
+            );
+            return Ok(());
+        }
+    }
+
+    let lights: Vec<&DeviceHandle> = drifted_device_handles.iter().collect();
+    set_devices_on_and_log(device_context, &lights, TriggerSource::Reconcile, None, on)
+        .map_err(|(error, _)| error)?;
+    Ok(())
+}
+
+/// Periodically brings the selected devices back into line with the webcam if they have
+/// drifted, with `--reconcile-interval`, so the listener heals itself rather than only reacting
+/// to events.
+async fn reconcile_periodically(
+    worker: DeviceWorker,
+    device_filter: DeviceFilter,
+    interval: Duration,
+) {
+    // Don't check straight away, so the webcam has been seen before we decide anything
+    let mut interval = CoalescedInterval::new(interval);
+
+    loop {
+        interval.tick().await;
+
+        let device_filter = device_filter.clone();
+        if let Err(error) = worker
+            .run(move |context| reconcile_selected_devices(context, &device_filter))
+            .await
+        {
+            log_error!("Failed to reconcile Litra device state: {}", error);
+        }
+    }
+}
+
+/// Turns newly attached devices on or off to match the webcam, or the forced state if there is
+/// one, unless automatic toggling is paused.
+fn apply_desired_state_to_attached_devices(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+) {
+    let trigger = Trigger::Hotplug {
+        webcam_in_use: device_context.webcam_in_use,
+    };
+    if let Err(error) = set_selected_devices_on_and_log_if_decided(
+        device_context,
+        device_filter,
+        false,
+        None,
+        trigger,
+    ) {
+        log_error!("Failed to update attached Litra device: {}", error);
+    }
+}
+
+/// Periodically looks for Litra devices being plugged in or unplugged, and brings newly plugged
+/// in devices into line with the webcam, so devices connected after the listener started (e.g.
+/// when docking a laptop) work straight away.
+async fn watch_for_hotplug(worker: DeviceWorker, device_filter: DeviceFilter, interval: Duration) {
+    let mut interval = CoalescedInterval::immediate(interval);
+    // The devices we saw last time, by path, or `None` until we've looked once
+    let mut known_devices: Option<HashMap<String, String>> = None;
+
+    loop {
+        interval.tick().await;
+
+        let device_filter = device_filter.clone();
+        let previous_devices = known_devices.take();
+        let result = worker
+            .run(move |context| {
+                // Devices aren't looked for while maintenance mode is on, and are all brought up
+                // to date when it ends
+                if context.maintenance {
+                    return Ok(None);
+                }
+
+                context.context.refresh_connected_devices()?;
+                let devices: HashMap<String, String> = context
+                    .context
+                    .get_connected_devices()
+                    .filter(|device| device_filter.matches(device))
+                    .map(|device| {
+                        (
+                            device.device_info().path().to_string_lossy().to_string(),
+                            format!(
+                                "{} (serial number: {})",
+                                device.device_type(),
+                                device.device_info().serial_number().unwrap_or("-")
+                            ),
+                        )
+                    })
+                    .collect();
+
+                if let Some(previous_devices) = previous_devices {
+                    let mut attached = false;
+                    for (path, description) in &devices {
+                        if !previous_devices.contains_key(path) {
+                            log_info!("Litra device attached: {}", description);
+                            attached = true;
+                        }
+                    }
+                    let mut detached = false;
+                    for (path, description) in &previous_devices {
+                        if !devices.contains_key(path) {
+                            log_info!("Litra device detached: {}", description);
+                            notifications::notify(
+                                Severity::Warning,
+                                &format!("Litra device detached: {}", description),
+                                None,
+                            );
+                            detached = true;
+                        }
+                    }
+
+                    // Persistent handles are for the devices we had before, so open them again
+                    if attached || detached {
+                        context.persistent_device_handles.clear();
+                    }
+                    if attached && !context.options.observe {
+                        apply_desired_state_to_attached_devices(context, &device_filter);
+                    }
+                }
+
+                Ok::<_, CliError>(Some(devices))
+            })
+            .await;
+
+        match result {
+            Ok(devices) => known_devices = devices,
+            Err(error) => log_error!("Failed to look for attached Litra devices: {}", error),
+        }
+    }
+}
+
+/// Turns the device on (`Some(true)`), off (`Some(false)`) or toggles it (`None`).
+fn handle_power_command(args: &Cli, on: Option<bool>) -> CliResult {
+    let device_filter = &args.device_filter();
+    let mut context = DeviceContext::new(args.device_options(), args.profile.as_deref())?;
+
+    let result = match on {
+        Some(on) => set_selected_devices_on_and_log(
+            &mut context,
+            device_filter,
+            args.require_device.at_startup(),
+            TriggerSource::Manual,
+            None,
+            on,
+        ),
+        None => toggle_selected_devices_and_log(
+            &mut context,
+            device_filter,
+            args.require_device.at_startup(),
+        ),
+    };
+    finish_fades(&mut context, device_filter);
+    result
+}
+
+/// Turns the selected devices off if the first of them is on, and on otherwise, so that when
+/// toggling several devices, they all end up in the opposite state to the first.
+fn toggle_selected_devices_and_log(
+    device_context: &mut DeviceContext,
+    device_filter: &DeviceFilter,
+    require_device: bool,
+) -> CliResult {
+    if device_context.maintenance {
+        return Err(CliError::MaintenanceMode);
+    }
+
+    let on = match get_selected_devices(
+        &mut device_context.context,
+        device_filter,
+        require_device,
+        device_context.options.retry_policy,
+    )?
+    .first()
+    {
+        Some(device_handle) => !device_handle.is_on()?,
+        None => {
+            print_device_not_found_log(device_filter);
+            return Ok(());
+        }
+    };
+
+    set_selected_devices_on_and_log(
+        device_context,
+        device_filter,
+        require_device,
+        TriggerSource::Manual,
+        None,
+        on,
+    )
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceInfo {
+    device_type: String,
+    serial_number: Option<String>,
+    device_path: String,
+    is_on: Option<bool>,
+    brightness_in_lumen: Option<u16>,
+    temperature_in_kelvin: Option<u16>,
+}
+
+fn get_connected_devices_info(context: &Litra, device_filter: &DeviceFilter) -> Vec<DeviceInfo> {
+    context
+        .get_connected_devices()
+        .filter(|device| device_filter.matches(device))
+        .map(|device| {
+            // If we can't open the device (e.g. because of missing permissions), we can still
+            // list it, but we don't know its current state.
+            let device_handle = device.open(context).ok();
+            DeviceInfo {
+                device_type: device.device_type().to_string(),
+                serial_number: device
+                    .device_info()
+                    .serial_number()
+                    .map(|serial_number| serial_number.to_string()),
+                device_path: device.device_info().path().to_string_lossy().to_string(),
+                is_on: device_handle
+                    .as_ref()
+                    .and_then(|device_handle| device_handle.is_on().ok()),
+                brightness_in_lumen: device_handle
+                    .as_ref()
+                    .and_then(|device_handle| device_handle.brightness_in_lumen().ok()),
+                temperature_in_kelvin: device_handle
+                    .as_ref()
+                    .and_then(|device_handle| device_handle.temperature_in_kelvin().ok()),
+            }
+        })
+        .collect()
+}
+
+fn format_optional<T: fmt::Display>(value: Option<T>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{} {}", value, unit),
+        None => "unknown".to_string(),
+    }
+}
+
+fn handle_devices_command(device_filter: &DeviceFilter, json: bool) -> CliResult {
+    let context = Litra::new()?;
+    let devices = get_connected_devices_info(&context, device_filter);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+
+    if devices.is_empty() {
+        print_device_not_found_log(device_filter);
+        return Ok(());
+    }
+
+    for device in devices {
+        println!(
+            "- {} (serial number: {}, path: {})",
+            device.device_type,
+            device.serial_number.as_deref().unwrap_or("-"),
+            device.device_path
+        );
+        println!(
+            "  - On: {}",
+            match device.is_on {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            }
+        );
+        println!(
+            "  - Brightness: {}",
+            format_optional(device.brightness_in_lumen, "lm")
+        );
+        println!(
+            "  - Temperature: {}",
+            format_optional(device.temperature_in_kelvin, "K")
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceStatus {
+    device_type: String,
+    serial_number: Option<String>,
+    is_on: Option<bool>,
+    brightness_in_lumen: Option<u16>,
+    temperature_in_kelvin: Option<u16>,
+}
+
+impl From<DeviceInfo> for DeviceStatus {
+    fn from(device: DeviceInfo) -> Self {
+        DeviceStatus {
+            device_type: device.device_type,
+            serial_number: device.serial_number,
+            is_on: device.is_on,
+            brightness_in_lumen: device.brightness_in_lumen,
+            temperature_in_kelvin: device.temperature_in_kelvin,
+        }
+    }
+}
+
+fn format_device_status(status: &DeviceStatus) -> String {
+    format!(
+        "{} (serial number: {}): {}, brightness {}, temperature {}",
+        status.device_type,
+        status.serial_number.as_deref().unwrap_or("-"),
+        match status.is_on {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        },
+        format_optional(status.brightness_in_lumen, "lm"),
+        format_optional(status.temperature_in_kelvin, "K")
+    )
+}
+
+/// Formats the status of a device as `name: value` lines for `status --plain`. These names and
+/// values are relied on by scripts and assistive tools, so they mustn't change.
+fn format_device_status_plain(status: &DeviceStatus) -> String {
+    format!(
+        "device: {}\nserial number: {}\npower: {}\nbrightness: {}\ntemperature: {}",
+        status.device_type,
+        status.serial_number.as_deref().unwrap_or("unknown"),
+        match status.is_on {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        },
+        status
+            .brightness_in_lumen
+            .map(|brightness| format!("{} lumens", brightness))
+            .unwrap_or_else(|| "unknown".to_string()),
+        status
+            .temperature_in_kelvin
+            .map(|temperature| format!("{} kelvin", temperature))
+            .unwrap_or_else(|| "unknown".to_string())
+    )
+}
+
+fn handle_status_command(
+    device_filter: &DeviceFilter,
+    require_device: bool,
+    json: bool,
+    plain: bool,
+) -> CliResult {
+    let context = Litra::new()?;
+    let statuses: Vec<DeviceStatus> = get_connected_devices_info(&context, device_filter)
+        .into_iter()
+        .map(DeviceStatus::from)
+        .collect();
+
+    if require_device {
+        let found_serial_numbers: Vec<Option<String>> = statuses
+            .iter()
+            .map(|status| status.serial_number.clone())
+            .collect();
+        check_required_devices_found(&device_filter.serial_numbers, &found_serial_numbers)?;
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
+
+    if plain {
+        // Devices are separated by a blank line, and nothing is printed if there are none
+        let statuses: Vec<String> = statuses.iter().map(format_device_status_plain).collect();
+        if !statuses.is_empty() {
+            println!("{}", statuses.join("\n\n"));
+        }
+        return Ok(());
+    }
+
+    if statuses.is_empty() {
+        print_device_not_found_log(device_filter);
+        return Ok(());
+    }
+
+    for status in statuses {
+        println!("{}", format_device_status(&status));
+    }
+
+    Ok(())
+}
+
+/// Prints a profiles file with a single profile, named after `--profile`, holding either the
+/// options in effect or an example of every option.
+fn handle_profile_command(args: &Cli, command: &ProfileCommand) -> CliResult {
+    let options = match command {
+        ProfileCommand::Dump => profiles::effective_options(args)
+            .map_err(|message| CliError::IoError(std::io::Error::other(message)))?,
+        ProfileCommand::Example => profiles::example_options(),
+    };
+
+    let mut profiles = Map::new();
+    profiles.insert(
+        args.profile
+            .clone()
+            .unwrap_or_else(|| profiles::DEFAULT_PROFILE.to_string()),
+        Value::Object(options),
+    );
+    println!("{}", serde_json::to_string_pretty(&profiles)?);
+
+    Ok(())
+}
+
+fn handle_command(command: &Commands, args: &Cli) -> CliResult {
+    match command {
+        Commands::On => handle_power_command(args, Some(true)),
+        Commands::Off => handle_power_command(args, Some(false)),
+        Commands::Toggle => handle_power_command(args, None),
+        Commands::Devices { json } => handle_devices_command(&args.device_filter(), *json),
+        Commands::Init => init::run_init_wizard(args),
+        Commands::Profile { command } => handle_profile_command(args, command),
+        Commands::Status { json, plain } => handle_status_command(
+            &args.device_filter(),
+            args.require_device.at_startup(),
+            *json,
+            *plain,
+        ),
+        #[cfg(unix)]
+        Commands::Ctl {
+            command,
+            arguments,
+            socket,
+        } => control::send_control_command(
+            &socket
+                .clone()
+                .unwrap_or_else(|| control::default_control_socket_path(args.profile.as_deref())),
+            *command,
+            arguments,
+        ),
+        #[cfg(unix)]
+        Commands::Simulate { event, socket } => control::send_control_command(
+            &socket
+                .clone()
+                .unwrap_or_else(|| control::default_control_socket_path(args.profile.as_deref())),
+            event.control_command(),
+            &[],
+        ),
+        #[cfg(target_os = "linux")]
+        Commands::FixPermissions => permissions::fix_permissions(),
+        #[cfg(target_os = "linux")]
+        Commands::Service {
+            command:
+                ServiceCommand::Install {
+                    force,
+                    args: service_args,
+                },
+        } => systemd::install_user_unit(service_args, *force, args.profile.as_deref()),
+        #[cfg(target_os = "linux")]
+        Commands::Service {
+            command: ServiceCommand::Uninstall,
+        } => systemd::uninstall_user_unit(args.profile.as_deref()),
+        #[cfg(target_os = "macos")]
+        Commands::Service {
+            command:
+                ServiceCommand::Install {
+                    force,
+                    args: service_args,
+                },
+        } => launchd::install_launch_agent(service_args, *force, args.profile.as_deref()),
+        #[cfg(target_os = "macos")]
+        Commands::Service {
+            command: ServiceCommand::Uninstall,
+        } => launchd::uninstall_launch_agent(args.profile.as_deref()),
+    }
+}
+
+/// Sets up the device worker used while listening for webcam events, logging which device was
+/// found and starting any background tasks. Errors from toggling the device are sent to the
+/// returned receiver, and what the listener does is recorded in `session`.
+async fn start_listener(
+    args: &Cli,
+    event_source: &EventSourceHealth,
+    session: &Session,
+) -> Result<(DeviceWorker, mpsc::UnboundedReceiver<CliError>), CliError> {
+    let device_filter = &args.device_filter();
+
+    let mut context = DeviceContext::new(args.device_options(), args.profile.as_deref())?;
+    context.session = session.clone();
+    context.camera_hooks = args.camera_hooks();
+    for address in &args.elgato_light {
+        context.key_lights.add(address.clone());
+    }
+    context.profile_reloader = reload::ProfileReloader::new(args);
+    if args.discover_elgato_lights {
+        match elgato::discover_key_lights(elgato::DISCOVERY_TIMEOUT).await {
+            Ok(addresses) => {
+                if addresses.is_empty() {
+                    log_info!("No Elgato Key Lights found on the local network");
+                }
+                for address in addresses {
+                    if !context.key_lights.contains(&address) {
+                        log_info!("Found Elgato Key Light at {}", address);
+                        context.key_lights.add(address);
+                    }
+                }
+            }
+            Err(error) => log_error!("Failed to look for Elgato Key Lights: {}", error),
+        }
+    }
+    let device_handles = get_selected_devices(
+        &mut context.context,
+        device_filter,
+        args.require_device.at_startup(),
+        context.options.retry_policy,
+    )?;
+    if device_handles.is_empty() {
+        print_device_not_found_log(device_filter);
+    }
+    for device_handle in &device_handles {
+        log_info!(
+            "Found {} device (serial number: {})",
+            device_handle.device_type(),
+            get_serial_number_with_fallback(device_handle)
+        );
+        session.record_device(describe_device(device_handle));
+    }
+    context.keep_device_handles_if_persistent(device_handles);
+
+    let (worker, toggle_errors) = DeviceWorker::spawn(
+        context,
+        device_filter.clone(),
+        args.require_device.on_toggle(),
+    );
+
+    #[cfg(unix)]
+    tokio::spawn(handle_control_signals(
+        worker.clone(),
+        device_filter.clone(),
+        args.require_device.on_toggle(),
+        args.profile.is_some(),
+    ));
+
+    if let Some(keepalive_interval) = args.keepalive_interval {
+        tokio::spawn(keep_device_awake(
+            worker.clone(),
+            device_filter.clone(),
+            keepalive_interval,
+            args.verbose,
+        ));
+    }
+
+    if let Some(interval) = args.reconcile_interval {
+        tokio::spawn(reconcile_periodically(
+            worker.clone(),
+            device_filter.clone(),
+            interval,
+        ));
+    }
+
+    if let Some(interval) = args.detect_hotplug {
+        tokio::spawn(watch_for_hotplug(
+            worker.clone(),
+            device_filter.clone(),
+            interval,
+        ));
+    }
+
+    if let Some(interval) = args.detect_external_changes {
+        tokio::spawn(watch_for_external_changes(
+            worker.clone(),
+            device_filter.clone(),
+            interval,
+            args.notify_external_changes(),
+        ));
+    }
+
+    #[cfg(unix)]
+    if let Some(control_socket) = &args.control_socket {
+        let control_socket_path = control_socket
+            .clone()
+            .unwrap_or_else(|| control::default_control_socket_path(args.profile.as_deref()));
+        let listener = control::bind_control_socket(&control_socket_path)?;
+        log_info!(
+            "Listening for commands on control socket {}",
+            control_socket_path.display()
+        );
+
+        tokio::spawn(control::serve_control_socket(
+            listener,
+            worker.clone(),
+            event_source.clone(),
+            device_filter.clone(),
+            args.require_device.on_toggle(),
+        ));
+    }
+
+    if let Some(address) = args.listen_http {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        log_info!("Serving HTTP API on http://{}", listener.local_addr()?);
+
+        tokio::spawn(http::serve_http(
+            listener,
+            worker.clone(),
+            event_source.clone(),
+            device_filter.clone(),
+            args.require_device.on_toggle(),
+        ));
+    }
+
+    Ok((worker, toggle_errors))
+}
+
+/// Turns the Litra on and off as the webcam turns on and off, with the event source picked for
+/// this platform, until the event source stops or toggling the Litra fails. The device worker
+/// is put in `listener_worker` once it has started, so the caller can use it after we stop.
+async fn handle_autotoggle_command(
+    args: &Cli,
+    session: &Session,
+    listener_worker: &mut Option<DeviceWorker>,
+) -> CliResult {
+    let event_source = get_event_source(args)?;
+    let health = EventSourceHealth::new(event_source.kind());
+    let (worker, mut toggle_errors) = start_listener(args, &health, session).await?;
+    *listener_worker = Some(worker.clone());
+    let mut events = event_source.start(health.clone());
+
+    #[cfg(target_os = "linux")]
+    systemd::notify("READY=1");
+    // Keepalives are sent from the event loop itself, and only while the event source is showing
+    // signs of life, so systemd restarts us if either of them hangs
+    #[cfg(target_os = "linux")]
+    let watchdog_interval = systemd::watchdog_interval();
+    #[cfg(target_os = "linux")]
+    let mut watchdog = watchdog_interval.map(CoalescedInterval::immediate);
+    #[cfg(not(target_os = "linux"))]
+    let mut watchdog = None;
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => event?,
+                None => break,
+            },
+            Some(error) = toggle_errors.recv() => return Err(error),
+            _ = tick_watchdog(&mut watchdog) => {
+                #[cfg(target_os = "linux")]
+                if let Some(watchdog_interval) = watchdog_interval {
+                    notify_watchdog_if_event_source_alive(
+                        &health,
+                        watchdog_interval.max(args.poll_interval) * 2,
+                    );
+                }
+                continue;
+            }
+        };
+
+        health.record_event();
+        log_info!(
+            "Detected that a video device has been turned {} ({}), attempting to turn {} Litra device...",
+            if event.on { "on" } else { "off" },
+            event.describe(),
+            if event.on { "on" } else { "off" }
+        );
+        worker.toggle(event.on, event.camera.as_deref(), event.app.as_deref());
+    }
+
+    Err(CliError::IoError(std::io::Error::other(
+        "Stopped receiving video device events unexpectedly",
+    )))
+}
+
+/// Sends systemd a watchdog keepalive, unless the event source hasn't shown signs of life within
+/// `timeout`, in which case it has probably hung, so we let systemd restart us.
+#[cfg(target_os = "linux")]
+fn notify_watchdog_if_event_source_alive(health: &EventSourceHealth, timeout: Duration) {
+    let time_since_heartbeat = health.time_since_heartbeat();
+    if time_since_heartbeat < timeout {
+        systemd::notify("WATCHDOG=1");
+    } else {
+        let message = format!(
+            "Not sending systemd watchdog keepalive, as the event source hasn't responded for {}s",
+            time_since_heartbeat.as_secs()
+        );
+        log_error!("{}", message);
+        notifications::notify(Severity::Warning, &message, None);
+    }
+}
+
+/// Waits for the next systemd watchdog keepalive to be due, or forever if the watchdog is disabled.
+async fn tick_watchdog(watchdog: &mut Option<CoalescedInterval>) {
+    match watchdog {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The level of messages streamed by `log stream`.
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogStreamLevel {
+    Default,
+    Info,
+    Debug,
+}
+
+#[cfg(target_os = "macos")]
+impl LogStreamLevel {
+    fn as_arg(&self) -> &'static str {
+        match self {
+            LogStreamLevel::Default => "default",
+            LogStreamLevel::Info => "info",
+            LogStreamLevel::Debug => "debug",
+        }
+    }
+}
+
+/// How webcam usage is detected on macOS.
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DetectionMethod {
+    /// Stream the system log with `log stream`, and look for messages about video devices
+    /// starting and stopping. This supports `--only-app`, `--ignore-app`, `--delay`,
+    /// `--on-delay`, `--off-delay` and `--min-session-duration`.
+    LogStream,
+    /// Ask CoreMediaIO whether any video device is in use every `--poll-interval`. This doesn't
+    /// depend on the format of log messages, which can change between macOS releases.
+    CoreMediaIo,
+}
+
+/// Options for the `log stream` process used to detect webcam events on macOS.
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+struct LogStreamOptions {
+    level: LogStreamLevel,
+    processes: Vec<String>,
+    cpu_report_interval: Option<Duration>,
+}
+
+/// Which apps' webcam events are used on macOS, set with `--only-app` and `--ignore-app`.
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+struct AppFilter {
+    only_apps: Vec<String>,
+    ignored_apps: Vec<String>,
+}
+
+#[cfg(target_os = "macos")]
+impl AppFilter {
+    /// Whether webcam events from `app` should be used. If we don't know which app an event came
+    /// from, it is only used if `--only-app` wasn't given.
+    fn allows(&self, app: Option<&str>) -> bool {
+        let is_in = |apps: &[String]| {
+            app.is_some_and(|app| {
+                apps.iter()
+                    .any(|expected| expected.eq_ignore_ascii_case(app))
+            })
+        };
+
+        (self.only_apps.is_empty() || is_in(&self.only_apps)) && !is_in(&self.ignored_apps)
+    }
+}
+
+/// Extracts the name of the process which logged `log_line` from `log stream`'s default format,
+/// e.g. `zoom.us` from `2024-05-01 09:00:00.000000+0100 0x1a2b Default 0x0 123 0 zoom.us:
+/// (CoreMediaIO) [com.apple.cmio:] ...`.
+#[cfg(target_os = "macos")]
+fn get_log_line_process(log_line: &str) -> Option<&str> {
+    let mut rest = log_line.trim_start();
+    // Skip the date, time, thread, type, activity, PID and TTL columns
+    for _ in 0..7 {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+
+    rest.split_once(": ").map(|(process, _)| process)
+}
+
+/// Periodically prints the CPU usage of the `log` process, as reported by `ps`, until the
+/// process exits.
+#[cfg(target_os = "macos")]
+async fn report_log_process_cpu_usage(pid: u32, cpu_report_interval: Duration) {
+    // The first tick is a full interval away, so the process has done some work by then
+    let mut interval = CoalescedInterval::new(cpu_report_interval);
+
+    loop {
+        interval.tick().await;
+
+        match Command::new("ps")
+            .arg("-o")
+            .arg("%cpu=")
+            .arg("-p")
+            .arg(pid.to_string())
+            .output()
+            .await
+        {
+            Ok(output) if output.status.success() => log_info!(
+                "`log` process CPU usage: {}%",
+                String::from_utf8_lossy(&output.stdout).trim()
+            ),
+            // `ps` fails if the process no longer exists
+            Ok(_) => return,
+            Err(error) => {
+                log_error!("Failed to measure CPU usage of `log` process: {}", error);
+                return;
+            }
+        }
+    }
+}
+
+/// Picks how to detect webcam events on macOS, with `--detection-method`.
+#[cfg(target_os = "macos")]
+pub fn get_event_source(args: &Cli) -> Result<Box<dyn EventSource>, CliError> {
+    let event_source: Box<dyn EventSource> = match args.detection_method {
+        DetectionMethod::LogStream => {
+            if !args.video_device_name.is_empty() {
+                log_error!("WARNING: `--video-device-name` is only supported with `--detection-method core-media-io` on macOS, so it will be ignored");
+            }
+
+            Box::new(LogStreamEventSource::new(args))
+        }
+        DetectionMethod::CoreMediaIo => Box::new(CoreMediaIoEventSource::new(args)),
+    };
+
+    Ok(event_source)
+}
+
+/// Checks whether any video device is in use with CoreMediaIO every `--poll-interval`, and sends
+/// an event whenever that changes.
+#[cfg(target_os = "macos")]
+pub struct CoreMediaIoEventSource {
+    verbose: bool,
+    video_device_names: Vec<String>,
+    poll_interval: Duration,
+}
+
+#[cfg(target_os = "macos")]
+impl CoreMediaIoEventSource {
+    /// Watches the video devices selected with `--video-device-name` every `--poll-interval`.
+    pub fn new(args: &Cli) -> Self {
+        CoreMediaIoEventSource {
+            verbose: args.verbose,
+            video_device_names: args.video_device_name.clone(),
+            poll_interval: args.poll_interval,
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl EventSource for CoreMediaIoEventSource {
+    fn kind(&self) -> &'static str {
+        "CoreMediaIO polling"
+    }
+
+    fn start(
+        self: Box<Self>,
+        _health: EventSourceHealth,
+    ) -> mpsc::Receiver<Result<CameraEvent, CliError>> {
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(poll_core_media_io(*self, sender));
+        receiver
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn poll_core_media_io(
+    source: CoreMediaIoEventSource,
+    sender: mpsc::Sender<Result<CameraEvent, CliError>>,
+) {
+    log_info!("Polling CoreMediaIO to listen for video device events...");
+
+    let video_device_names = &source.video_device_names;
+    let get_running_cameras = || -> std::io::Result<std::collections::BTreeSet<cmio::Camera>> {
+        Ok(cmio::get_running_cameras()?
+            .into_iter()
+            .filter(|camera| {
+                video_device_names.is_empty()
+                    || video_device_names.iter().any(|name| camera.is_named(name))
+            })
+            .collect())
+    };
+
+    let mut interval = CoalescedInterval::immediate(source.poll_interval);
+    let mut previously_running = match get_running_cameras() {
+        Ok(running) => running,
+        Err(error) => {
+            let _ = sender.send(Err(error.into())).await;
+            return;
+        }
+    };
+
+    loop {
+        interval.tick().await;
+
+        let running = match get_running_cameras() {
+            Ok(running) => running,
+            Err(error) => {
+                let _ = sender.send(Err(error.into())).await;
+                return;
+            }
+        };
+
+        if source.verbose {
+            for camera in running.difference(&previously_running) {
+                log_info!(
+                    "Video device started: {} (unique ID: {})",
+                    camera.name,
+                    camera.unique_id
+                );
+            }
+            for camera in previously_running.difference(&running) {
+                log_info!(
+                    "Video device stopped: {} (unique ID: {})",
+                    camera.name,
+                    camera.unique_id
+                );
+            }
+        }
+
+        if running.is_empty() != previously_running.is_empty() {
+            let on = !running.is_empty();
+            let camera = if on {
+                running.iter().next()
+            } else {
+                previously_running.iter().next()
+            }
+            .map(|camera| camera.name.clone());
+
+            if sender
+                .send(Ok(CameraEvent {
+                    on,
+                    camera,
+                    app: None,
+                }))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        previously_running = running;
+    }
+}
+
+/// How long to wait before restarting the `log` process if it stops. The wait doubles each time
+/// it stops again, up to the maximum, and goes back to the minimum once it has run for at least
+/// the maximum.
+#[cfg(target_os = "macos")]
+const LOG_STREAM_MINIMUM_BACKOFF: Duration = Duration::from_secs(1);
+#[cfg(target_os = "macos")]
+const LOG_STREAM_MAXIMUM_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Starts a `log stream` process which prints messages about video devices starting and stopping.
+#[cfg(target_os = "macos")]
+fn spawn_log_stream(options: &LogStreamOptions) -> std::io::Result<tokio::process::Child> {
+    let mut command = Command::new("log");
+    command
+        .arg("stream")
+        .arg("--level")
+        .arg(options.level.as_arg());
+    for process in &options.processes {
+        command.arg("--process").arg(process);
+    }
+
+    command
+        .arg("--predicate")
+        .arg("subsystem == \"com.apple.cmio\" AND (eventMessage CONTAINS \"AVCaptureSession_Tundra startRunning\" || eventMessage CONTAINS \"AVCaptureSession_Tundra stopRunning\")")
+        .stdout(Stdio::piped())
+        // Stop the process if we stop reading from it
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Streams the system log with `log stream`, and sends an event when video devices start or stop,
+/// after `--on-delay` or `--off-delay`.
+#[cfg(target_os = "macos")]
+pub struct LogStreamEventSource {
+    verbose: bool,
+    on_delay: Duration,
+    off_delay: Duration,
+    min_session_duration: Option<Duration>,
+    app_filter: AppFilter,
+    options: LogStreamOptions,
+}
+
+#[cfg(target_os = "macos")]
+impl LogStreamEventSource {
+    /// Streams the log with the delays, app filters and `log` options from `args`.
+    pub fn new(args: &Cli) -> Self {
+        LogStreamEventSource {
+            verbose: args.verbose,
+            on_delay: args.on_delay(),
+            off_delay: args.off_delay(),
+            min_session_duration: args.min_session_duration,
+            app_filter: args.app_filter(),
+            options: args.log_stream_options(),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl EventSource for LogStreamEventSource {
+    fn kind(&self) -> &'static str {
+        "log stream"
+    }
+
+    fn start(
+        self: Box<Self>,
+        health: EventSourceHealth,
+    ) -> mpsc::Receiver<Result<CameraEvent, CliError>> {
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(watch_log_stream(*self, health, sender));
+        receiver
+    }
+}
+
+/// Runs the `log` process for a [`LogStreamEventSource`]. If it stops (e.g. when macOS restarts
+/// the logging daemon), it is restarted with exponential backoff, and the restart is counted in
+/// `health`.
+#[cfg(target_os = "macos")]
+async fn watch_log_stream(
+    source: LogStreamEventSource,
+    health: EventSourceHealth,
+    sender: mpsc::Sender<Result<CameraEvent, CliError>>,
+) {
+    let LogStreamEventSource {
+        verbose,
+        on_delay,
+        off_delay,
+        min_session_duration,
+        app_filter,
+        options: log_stream_options,
+    } = source;
+
+    // Events are held back by the debouncer, and sent by `pending_action` once they are due
+    let mut pending_action: Option<tokio::task::JoinHandle<()>> = None;
+    let debouncer = std::sync::Arc::new(tokio::sync::Mutex::new(Debouncer::new(
+        on_delay,
+        off_delay,
+        min_session_duration,
+    )));
+
+    let mut backoff = LOG_STREAM_MINIMUM_BACKOFF;
+    let mut restarting = false;
+
+    loop {
+        if restarting {
+            log_info!("Restarting `log` process to listen for video device events...");
+        } else {
+            log_info!("Starting `log` process to listen for video device events...");
+        }
+
+        let mut child = match spawn_log_stream(&log_stream_options) {
+            Ok(child) => child,
+            // If we can't start it at all, there's no point retrying
+            Err(error) if !restarting => {
+                let _ = sender.send(Err(error.into())).await;
+                return;
+            }
+            Err(error) => {
+                log_error!(
+                    "Failed to restart `log` process: {}. Trying again in {}.",
+                    error,
+                    duration::format_duration(backoff)
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(LOG_STREAM_MAXIMUM_BACKOFF);
+                continue;
+            }
+        };
+        let started_at = Instant::now();
+
+        if let (Some(cpu_report_interval), Some(pid)) =
+            (log_stream_options.cpu_report_interval, child.id())
+        {
+            tokio::spawn(report_log_process_cpu_usage(pid, cpu_report_interval));
+        }
+
+        let stdout = child
+            .stdout
+            .take()
+            .expect("Failed to start `log` process to listen for video device events");
+        let mut reader = BufReader::new(stdout).lines();
+
+        log_info!("Listening for video device events...");
+
+        loop {
+            let log_line = match reader.next_line().await {
+                Ok(Some(log_line)) => log_line,
+                Ok(None) => break,
+                Err(error) => {
+                    log_error!(
+                        "Failed to read log line from `log` process when listening for video device events: {}",
+                        error
+                    );
+                    break;
+                }
+            };
+
+            if !log_line.starts_with("Filtering the log data") {
+                if verbose {
+                    log_info!("{}", log_line);
+                }
+
+                let app = get_log_line_process(&log_line);
+                if log_line.contains("AVCaptureSession_Tundra") && !app_filter.allows(app) {
+                    log_info!(
+                        "Ignoring video device event from {}.",
+                        app.unwrap_or("an unknown app")
+                    );
+                    continue;
+                }
+
+                let on = if log_line.contains("AVCaptureSession_Tundra startRunning") {
+                    log_info!(
+                        "Video device started running ({}).",
+                        app.unwrap_or("unknown app")
+                    );
+                    true
+                } else if log_line.contains("AVCaptureSession_Tundra stopRunning") {
+                    log_info!(
+                        "Video device stopped running ({}).",
+                        app.unwrap_or("unknown app")
+                    );
+                    false
+                } else {
+                    continue;
+                };
+
+                let replaced = debouncer.lock().await.push(
+                    CameraEvent {
+                        on,
+                        camera: None,
+                        app: app.map(str::to_string),
+                    },
+                    Instant::now(),
+                );
+                // If we're still waiting to turn on the Litra, the session was too short
+                if let (false, Some(min_session_duration), Some(CameraEvent { on: true, .. })) =
+                    (on, min_session_duration, replaced)
+                {
+                    log_info!(
+                        "Video device was turned off within {}, so not turning on Litra device.",
+                        duration::format_duration(on_delay.max(min_session_duration))
+                    );
+                }
+
+                // Cancel any pending action
+                if let Some(handle) = pending_action.take() {
+                    handle.abort();
+                }
+
+                // Clone variables for the async task
+                let debouncer_clone = debouncer.clone();
+                let sender_clone = sender.clone();
+
+                // Start a new delayed action
+                pending_action = Some(tokio::spawn(async move {
+                    let deadline = debouncer_clone.lock().await.deadline();
+                    if let Some(deadline) = deadline {
+                        tokio::time::sleep_until(deadline.into()).await;
+                    }
+
+                    let event = debouncer_clone.lock().await.take_due(Instant::now());
+                    if let Some(event) = event {
+                        let _ = sender_clone.send(Ok(event)).await;
+                    }
+                }));
+            }
+        }
+
+        // Make sure the process has stopped, in case we stopped reading because of an error
+        let _ = child.start_kill();
+        let status = match child.wait().await {
+            Ok(status) => status.to_string(),
+            Err(error) => error.to_string(),
+        };
+
+        // Nobody is listening for events any more, so there's no need to restart it
+        if sender.is_closed() {
+            return;
+        }
+
+        // A process which ran for a while before stopping isn't failing repeatedly
+        if started_at.elapsed() >= LOG_STREAM_MAXIMUM_BACKOFF {
+            backoff = LOG_STREAM_MINIMUM_BACKOFF;
+        }
+
+        let message = format!(
+            "`log` process exited unexpectedly when listening for video device events ({}). Restarting it in {}, so any video device events in the meantime will be missed.",
+            status,
+            duration::format_duration(backoff)
+        );
+        log_error!("{}", message);
+        notifications::notify(Severity::Warning, &message, None);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(LOG_STREAM_MAXIMUM_BACKOFF);
+        health.record_restart();
+        restarting = true;
+    }
+}
+
+/// A watched video device being opened or closed.
+#[cfg(target_os = "linux")]
+struct VideoDeviceEvent {
+    opened: bool,
+    camera: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct PollFd {
+    fd: std::ffi::c_int,
+    events: std::ffi::c_short,
+    revents: std::ffi::c_short,
+}
+
+#[cfg(target_os = "linux")]
+const POLLIN: std::ffi::c_short = 0x1;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: std::ffi::c_ulong, timeout: std::ffi::c_int)
+        -> std::ffi::c_int;
+}
+
+/// Waits up to `timeout` for inotify events to be ready to read, returning whether they are.
+#[cfg(target_os = "linux")]
+fn wait_for_inotify_events(inotify: &Inotify, timeout: Duration) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let mut poll_fd = PollFd {
+        fd: inotify.as_raw_fd(),
+        events: POLLIN,
+        revents: 0,
+    };
+    let timeout = std::ffi::c_int::try_from(timeout.as_millis()).unwrap_or(std::ffi::c_int::MAX);
+
+    // SAFETY: `poll_fd` is a single valid `pollfd` which outlives the call
+    match unsafe { poll(&mut poll_fd, 1, timeout) } {
+        -1 => {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(error)
+            }
+        }
+        ready => Ok(ready > 0),
+    }
+}
+
+/// Reads inotify events on a dedicated thread, since reading them blocks, and forwards each
+/// batch of open and close events to the returned channel. With a `heartbeat_interval`, an empty
+/// batch is sent whenever there haven't been any events for that long, so the listener can tell
+/// the thread hasn't hung.
+#[cfg(target_os = "linux")]
+fn spawn_video_device_event_reader(
+    mut inotify: Inotify,
+    watched_paths: HashMap<WatchDescriptor, PathBuf>,
+    heartbeat_interval: Option<Duration>,
+) -> mpsc::Receiver<std::io::Result<Vec<VideoDeviceEvent>>> {
+    let (sender, receiver) = mpsc::channel(16);
+
+    std::thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        loop {
+            if let Some(heartbeat_interval) = heartbeat_interval {
+                match wait_for_inotify_events(&inotify, heartbeat_interval) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if sender.blocking_send(Ok(Vec::new())).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Err(error) => {
+                        let _ = sender.blocking_send(Err(error));
+                        return;
+                    }
+                }
+            }
+
+            // Read events that were added with `Watches::add`.
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events
+                    .filter_map(|event| {
+                        let opened = match event.mask {
+                            EventMask::OPEN => true,
+                            EventMask::CLOSE_WRITE | EventMask::CLOSE_NOWRITE => false,
+                            _ => return None,
+                        };
+                        // We watch the device nodes themselves, so the event doesn't carry a
+                        // name. Look up the path we registered the watch for instead.
+                        let camera = watched_paths
+                            .get(&event.wd)
+                            .map(|path| path.display().to_string());
+                        Some(VideoDeviceEvent { opened, camera })
+                    })
+                    .collect(),
+                Err(error) => {
+                    let _ = sender.blocking_send(Err(error));
+                    return;
+                }
+            };
+
+            if sender.blocking_send(Ok(events)).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Watches `paths` for open and close events with inotify, returning an error if inotify isn't
+/// available or none of the devices could be watched (e.g. because the filesystem doesn't support
+/// inotify).
+#[cfg(target_os = "linux")]
+fn watch_video_devices(
+    paths: &[PathBuf],
+    heartbeat_interval: Option<Duration>,
+) -> std::io::Result<mpsc::Receiver<std::io::Result<Vec<VideoDeviceEvent>>>> {
+    let inotify = Inotify::init()?;
+    let mut watched_paths = HashMap::new();
+    let mut last_error = None;
+    for path in paths {
+        match inotify
+            .watches()
+            .add(path, WatchMask::OPEN | WatchMask::CLOSE)
+        {
+            Ok(watch_descriptor) => {
+                log_info!("Watching device {}", path.display());
+                watched_paths.insert(watch_descriptor, path.clone());
+            }
+            Err(error) => {
+                log_error!("Failed to watch device {}", path.display());
+                last_error = Some(error);
+            }
+        }
+    }
+
+    if watched_paths.is_empty() {
+        if let Some(error) = last_error {
+            return Err(error);
+        }
+    }
+
+    Ok(spawn_video_device_event_reader(
+        inotify,
+        watched_paths,
+        heartbeat_interval,
+    ))
+}
+
+/// How webcam usage is detected on Linux.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DetectionMethod {
+    /// Watch video devices for open and close events with inotify, falling back to `proc` if
+    /// inotify isn't available. This reacts straight away and uses the least CPU.
+    Inotify,
+    /// Check which video devices are open in `/proc` every `--poll-interval`. This works out the
+    /// state from scratch each time, so it can't get out of sync if open or close events are
+    /// missed, but it uses more CPU and can't see video devices opened by other users. Like
+    /// `inotify`, it only sees whether a video device is open, not whether it is streaming.
+    Proc,
+}
+
+/// Returns which of `paths` are currently open in any process, by looking at the file descriptors
+/// in `/proc`, along with the apps which have each of them open. Devices are matched by device
+/// number as well as by path, so devices opened inside containers (e.g. by Flatpak apps), where
+/// they may have a different path, are found too. Processes we aren't allowed to inspect (e.g.
+/// those run by other users) are skipped.
+#[cfg(target_os = "linux")]
+fn get_open_video_devices(
+    paths: &[PathBuf],
+) -> HashMap<PathBuf, std::collections::BTreeSet<String>> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let device_numbers: Vec<(u64, &PathBuf)> = paths
+        .iter()
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(path).ok()?;
+            metadata
+                .file_type()
+                .is_char_device()
+                .then(|| (metadata.rdev(), path))
+        })
+        .collect();
+
+    let mut open_video_devices: HashMap<PathBuf, std::collections::BTreeSet<String>> =
+        HashMap::new();
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return open_video_devices;
+    };
+
+    for process in processes.filter_map(|entry| entry.ok()).filter(|entry| {
+        entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.parse::<u32>().is_ok())
+    }) {
+        let Ok(file_descriptors) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+
+        let mut process_video_devices = HashSet::new();
+        for file_descriptor in file_descriptors.filter_map(|entry| entry.ok()) {
+            let file_descriptor = file_descriptor.path();
+            // `metadata` follows the file descriptor to the file it has open, even if that is in
+            // another mount namespace, where the link's target path would mean nothing to us
+            let by_device_number = std::fs::metadata(&file_descriptor)
+                .ok()
+                .filter(|metadata| metadata.file_type().is_char_device())
+                .and_then(|metadata| {
+                    device_numbers
+                        .iter()
+                        .find(|(device_number, _)| *device_number == metadata.rdev())
+                        .map(|(_, path)| (*path).clone())
+                });
+            let video_device = by_device_number.or_else(|| {
+                std::fs::read_link(&file_descriptor)
+                    .ok()
+                    .filter(|target| paths.contains(target))
+            });
+
+            if let Some(video_device) = video_device {
+                process_video_devices.insert(video_device);
+            }
+        }
+
+        if process_video_devices.is_empty() {
+            continue;
+        }
+
+        let app = describe_process(&process.path());
+        for video_device in process_video_devices {
+            let apps = open_video_devices.entry(video_device).or_default();
+            apps.extend(app.clone());
+        }
+    }
+
+    open_video_devices
+}
+
+/// Describes the process whose `/proc` directory is `process`, using its Flatpak app ID if it is
+/// running in a Flatpak sandbox, or its command name otherwise.
+#[cfg(target_os = "linux")]
+fn describe_process(process: &std::path::Path) -> Option<String> {
+    // Flatpak apps have a `.flatpak-info` file at the root of their mount namespace, which we can
+    // read through `/proc/<pid>/root` where we're allowed to
+    let flatpak_app_id = std::fs::read_to_string(process.join("root/.flatpak-info"))
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("name="))
+                .map(|name| name.trim().to_string())
+        });
+    if let Some(flatpak_app_id) = flatpak_app_id {
+        return Some(format!("{} (Flatpak)", flatpak_app_id));
+    }
+
+    std::fs::read_to_string(process.join("comm"))
+        .ok()
+        .map(|command| command.trim().to_string())
+}
+
+/// Starts checking which of `paths` are open in `/proc` every `poll_interval`.
+#[cfg(target_os = "linux")]
+fn poll_video_devices(
+    paths: Vec<PathBuf>,
+    poll_interval: Duration,
+) -> mpsc::Receiver<std::io::Result<Vec<VideoDeviceEvent>>> {
+    for path in &paths {
+        log_info!("Polling device {}", path.display());
+    }
+
+    spawn_video_device_poller(paths, poll_interval)
+}
+
+/// Checks which of `paths` are open every `poll_interval` on a dedicated thread, and forwards the
+/// open and close events since the last check to the returned channel, which are empty if
+/// nothing changed. This is used with
+/// `--detection-method proc`, or instead of inotify when inotify isn't available.
+#[cfg(target_os = "linux")]
+fn spawn_video_device_poller(
+    paths: Vec<PathBuf>,
+    poll_interval: Duration,
+) -> mpsc::Receiver<std::io::Result<Vec<VideoDeviceEvent>>> {
+    let (sender, receiver) = mpsc::channel(16);
+
+    std::thread::spawn(move || {
+        let mut previously_open = HashMap::new();
+        let mut interval = CoalescedInterval::new(poll_interval);
+        loop {
+            let open = get_open_video_devices(&paths);
+            let opened = open
+                .keys()
+                .filter(|path| !previously_open.contains_key(*path));
+            let closed = previously_open
+                .keys()
+                .filter(|path| !open.contains_key(*path));
+
+            for path in opened.clone() {
+                if let Some(apps) = open.get(path).filter(|apps| !apps.is_empty()) {
+                    log_info!(
+                        "Video device {} was opened by {}",
+                        path.display(),
+                        apps.iter().cloned().collect::<Vec<_>>().join(", ")
+                    );
+                }
+            }
+
+            let events: Vec<_> = opened
+                .map(|path| (true, path))
+                .chain(closed.map(|path| (false, path)))
+                .map(|(opened, path)| VideoDeviceEvent {
+                    opened,
+                    camera: Some(path.display().to_string()),
+                })
+                .collect();
+
+            // Send even if nothing changed, so the listener knows we're still polling
+            if sender.blocking_send(Ok(events)).is_err() {
+                return;
+            }
+
+            previously_open = open;
+            interval.tick_blocking();
+        }
+    });
+
+    receiver
+}
+
+/// How long to wait for a close event after a video device is opened before assuming it has been
+/// closed, for drivers that don't reliably send close events.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+struct AssumeClosedAfter {
+    /// The path of the device this applies to, or `None` for all devices
+    device: Option<String>,
+    duration: Duration,
+}
+
+#[cfg(target_os = "linux")]
+fn parse_assume_closed_after(value: &str) -> Result<AssumeClosedAfter, String> {
+    let (device, duration) = match value.rsplit_once('=') {
+        Some((device, duration)) => (Some(device.to_string()), duration),
+        None => (None, value),
+    };
+
+    Ok(AssumeClosedAfter {
+        device,
+        duration: duration::parse_duration(duration)?,
+    })
+}
+
+/// Returns how long to wait for `camera` to be closed, preferring a value for that device over
+/// one for all devices.
+#[cfg(target_os = "linux")]
+fn get_assume_closed_after(
+    assume_closed_after: &[AssumeClosedAfter],
+    camera: Option<&str>,
+) -> Option<Duration> {
+    assume_closed_after
+        .iter()
+        .find(|value| value.device.is_some() && value.device.as_deref() == camera)
+        .or_else(|| {
+            assume_closed_after
+                .iter()
+                .find(|value| value.device.is_none())
+        })
+        .map(|value| value.duration)
+}
+
+/// A watched video device that is currently open.
+#[cfg(target_os = "linux")]
+struct OpenVideoDevice {
+    count: usize,
+    /// When to assume the device has been closed, if it doesn't send a close event before then
+    assume_closed_at: Option<tokio::time::Instant>,
+}
+
+/// Waits until `deadline`, or forever if there isn't one.
+#[cfg(target_os = "linux")]
+async fn sleep_until_if_some(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Picks how to detect webcam events on Linux, with `--detection-method`, for the video devices
+/// selected with `--video-device` and `--video-device-name`.
+#[cfg(target_os = "linux")]
+pub fn get_event_source(args: &Cli) -> Result<Box<dyn EventSource>, CliError> {
+    Ok(Box::new(VideoDeviceEventSource::new(args)?))
+}
+
+/// Sends an event whenever video devices are opened or closed, with the webcam counted as on
+/// while any video device is open.
+#[cfg(target_os = "linux")]
+pub struct VideoDeviceEventSource {
+    kind: &'static str,
+    receiver: mpsc::Receiver<std::io::Result<Vec<VideoDeviceEvent>>>,
+    assume_closed_after: Vec<AssumeClosedAfter>,
+}
+
+#[cfg(target_os = "linux")]
+impl VideoDeviceEventSource {
+    /// Starts watching the video devices selected with `--video-device` and
+    /// `--video-device-name`, with inotify or by polling `/proc` as `--detection-method` says.
+    /// Events are sent once the event source is started.
+    pub fn new(args: &Cli) -> Result<Self, CliError> {
+        let video_devices = &args.video_device;
+        let video_device_names = &args.video_device_name;
+
+        let has_name_in = |path: &PathBuf, names: &[String]| {
+            get_video_device_name(path).is_some_and(|name| {
+                names
+                    .iter()
+                    .any(|expected| expected.eq_ignore_ascii_case(&name))
+            })
+        };
+        let video_device_paths: Vec<PathBuf> = get_video_device_paths(&args.watch_dir)?
+            .into_iter()
+            .filter(|path| {
+                (video_devices.is_empty() && video_device_names.is_empty())
+                    || video_devices
+                        .iter()
+                        .any(|device| path.to_str() == Some(device))
+                    || has_name_in(path, video_device_names)
+            })
+            .collect();
+
+        for video_device in video_devices {
+            if !video_device_paths
+                .iter()
+                .any(|path| path.to_str() == Some(video_device))
+            {
+                log_error!(
+                    "WARNING: Video device {} wasn't found, so it won't be monitored",
+                    video_device
+                );
+            }
+        }
+
+        for video_device_name in video_device_names {
+            if !video_device_paths
+                .iter()
+                .any(|path| has_name_in(path, std::slice::from_ref(video_device_name)))
+            {
+                log_error!(
+                    "WARNING: No video device named {} was found, so it won't be monitored",
+                    video_device_name
+                );
+            }
+        }
+
+        let (kind, receiver) = match args.detection_method {
+            DetectionMethod::Inotify => {
+                match watch_video_devices(&video_device_paths, systemd::watchdog_interval()) {
+                    Ok(receiver) => ("inotify", receiver),
+                    Err(error) => {
+                        log_error!(
+                        "WARNING: Unable to watch video devices with inotify ({}). Falling back to checking which video devices are open every {}. This uses more CPU, and can't see video devices opened by other users.",
+                        error,
+                        duration::format_duration(args.poll_interval)
+                    );
+                        (
+                            "/proc polling",
+                            poll_video_devices(video_device_paths, args.poll_interval),
+                        )
+                    }
+                }
+            }
+            DetectionMethod::Proc => (
+                "/proc polling",
+                poll_video_devices(video_device_paths, args.poll_interval),
+            ),
+        };
+
+        Ok(VideoDeviceEventSource {
+            kind,
+            receiver,
+            assume_closed_after: args.assume_closed_after.clone(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl EventSource for VideoDeviceEventSource {
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn start(
+        self: Box<Self>,
+        health: EventSourceHealth,
+    ) -> mpsc::Receiver<Result<CameraEvent, CliError>> {
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(track_open_video_devices(*self, sender, health));
+        receiver
+    }
+}
+
+/// Keeps track of which video devices are open for a [`VideoDeviceEventSource`], assuming they
+/// have been closed after `--assume-closed-after`.
+#[cfg(target_os = "linux")]
+async fn track_open_video_devices(
+    source: VideoDeviceEventSource,
+    sender: mpsc::Sender<Result<CameraEvent, CliError>>,
+    health: EventSourceHealth,
+) {
+    let VideoDeviceEventSource {
+        mut receiver,
+        assume_closed_after,
+        ..
+    } = source;
+
+    let mut open_devices: HashMap<Option<String>, OpenVideoDevice> = HashMap::new();
+    loop {
+        let next_assume_closed_at = open_devices
+            .values()
+            .filter_map(|device| device.assume_closed_at)
+            .min();
+
+        let events = tokio::select! {
+            events = receiver.recv() => match events {
+                // An empty batch means the reader is still working, but nothing happened
+                Some(Ok(events)) if events.is_empty() => {
+                    health.record_heartbeat();
+                    continue;
+                }
+                Some(Ok(events)) => events,
+                Some(Err(error)) => {
+                    let _ = sender.send(Err(error.into())).await;
+                    return;
+                }
+                None => return,
+            },
+            _ = sleep_until_if_some(next_assume_closed_at) => {
+                // Act as if the devices we've given up on have been closed
+                let now = tokio::time::Instant::now();
+                open_devices
+                    .iter_mut()
+                    .filter(|(_, device)| device.assume_closed_at.is_some_and(|at| at <= now))
+                    .map(|(camera, device)| {
+                        log_info!(
+                            "Assuming video device has been closed as no close event was received: {}",
+                            camera.as_deref().unwrap_or("unknown camera")
+                        );
+                        device.count = 1;
+                        VideoDeviceEvent {
+                            opened: false,
+                            camera: camera.clone(),
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        let mut triggering_camera = None;
+        for event in events {
+            if event.opened {
+                match &event.camera {
+                    Some(camera) => log_info!("Video device opened: {}", camera),
+                    None => log_info!("Video device opened"),
+                }
+                let device = open_devices
+                    .entry(event.camera.clone())
+                    .or_insert(OpenVideoDevice {
+                        count: 0,
+                        assume_closed_at: None,
+                    });
+                device.count = device.count.saturating_add(1);
+                device.assume_closed_at =
+                    get_assume_closed_after(&assume_closed_after, event.camera.as_deref())
+                        .map(|duration| tokio::time::Instant::now() + duration);
+            } else {
+                match &event.camera {
+                    Some(camera) => log_info!("Video device closed: {}", camera),
+                    None => log_info!("Video device closed"),
+                }
+                if let Some(device) = open_devices.get_mut(&event.camera) {
+                    device.count = device.count.saturating_sub(1);
+                    if device.count == 0 {
+                        open_devices.remove(&event.camera);
+                    }
+                }
+            }
+            triggering_camera = event.camera;
+        }
+
+        let event = CameraEvent {
+            on: !open_devices.is_empty(),
+            camera: triggering_camera,
+            app: None,
+        };
+        if sender.send(Ok(event)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The registry key where Windows records which applications have used the webcam, and when.
+#[cfg(target_os = "windows")]
+const WEBCAM_CONSENT_STORE_KEY: &str =
+    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\CapabilityAccessManager\\ConsentStore\\webcam";
+
+/// Returns the applications that are currently using the webcam, based on the output of `reg
+/// query`. While an application is using the webcam, Windows sets the `LastUsedTimeStop` value
+/// under its key in the consent store to zero.
+#[cfg(target_os = "windows")]
+fn parse_applications_using_webcam(reg_query_output: &str) -> HashSet<String> {
+    let mut applications = HashSet::new();
+    let mut current_key: Option<&str> = None;
+
+    for line in reg_query_output.lines() {
+        if line.starts_with("HKEY_") {
+            current_key = Some(line.trim());
+            continue;
+        }
+
+        let mut columns = line.split_whitespace();
+        if columns.next() != Some("LastUsedTimeStop") {
+            continue;
+        }
+
+        if columns.nth(1) == Some("0x0") {
+            if let Some(key) = current_key {
+                // Keys for non-packaged apps are named after the path to the executable, with
+                // `#` in place of `\`.
+                let application = key.rsplit('\\').next().unwrap_or(key);
+                applications.insert(application.replace('#', "\\"));
+            }
+        }
+    }
+
+    applications
+}
+
+#[cfg(target_os = "windows")]
+async fn get_applications_using_webcam() -> std::io::Result<HashSet<String>> {
+    let output = Command::new("reg")
+        .arg("query")
+        .arg(WEBCAM_CONSENT_STORE_KEY)
+        .arg("/s")
+        .arg("/v")
+        .arg("LastUsedTimeStop")
+        .output()
+        .await?;
+
+    // `reg query` exits with an error if no application has ever used the webcam, so the key
+    // doesn't exist yet.
+    Ok(parse_applications_using_webcam(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Detects webcam events on Windows by checking the webcam consent store every
+/// `--poll-interval`, and sends an event whenever whether any application is using the webcam
+/// changes.
+#[cfg(target_os = "windows")]
+pub struct WebcamConsentStoreEventSource {
+    verbose: bool,
+    poll_interval: Duration,
+}
+
+#[cfg(target_os = "windows")]
+impl WebcamConsentStoreEventSource {
+    /// Checks the webcam consent store every `--poll-interval`.
+    pub fn new(args: &Cli) -> Self {
+        WebcamConsentStoreEventSource {
+            verbose: args.verbose,
+            poll_interval: args.poll_interval,
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl EventSource for WebcamConsentStoreEventSource {
+    fn kind(&self) -> &'static str {
+        "webcam consent store"
+    }
+
+    fn start(
+        self: Box<Self>,
+        _health: EventSourceHealth,
+    ) -> mpsc::Receiver<Result<CameraEvent, CliError>> {
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(poll_webcam_consent_store(*self, sender));
+        receiver
+    }
+}
+
+/// Picks how to detect webcam events on Windows, which is always the webcam consent store.
+#[cfg(target_os = "windows")]
+pub fn get_event_source(args: &Cli) -> Result<Box<dyn EventSource>, CliError> {
+    Ok(Box::new(WebcamConsentStoreEventSource::new(args)))
+}
+
+#[cfg(target_os = "windows")]
+async fn poll_webcam_consent_store(
+    source: WebcamConsentStoreEventSource,
+    sender: mpsc::Sender<Result<CameraEvent, CliError>>,
+) {
+    log_info!("Polling the webcam consent store to listen for video device events...");
+
+    let mut interval = CoalescedInterval::immediate(source.poll_interval);
+    let mut previous_applications = match get_applications_using_webcam().await {
+        Ok(applications) => applications,
+        Err(error) => {
+            let _ = sender.send(Err(error.into())).await;
+            return;
+        }
+    };
+
+    loop {
+        interval.tick().await;
+
+        let applications = match get_applications_using_webcam().await {
+            Ok(applications) => applications,
+            Err(error) => {
+                let _ = sender.send(Err(error.into())).await;
+                return;
+            }
+        };
+
+        if source.verbose {
+            for application in applications.difference(&previous_applications) {
+                log_info!("Application started using webcam: {}", application);
+            }
+            for application in previous_applications.difference(&applications) {
+                log_info!("Application stopped using webcam: {}", application);
+            }
+        }
+
+        if applications.is_empty() != previous_applications.is_empty() {
+            let on = !applications.is_empty();
+            let app = if on {
+                applications.iter().next()
+            } else {
+                previous_applications.iter().next()
+            }
+            .cloned();
+
+            if sender
+                .send(Ok(CameraEvent {
+                    on,
+                    camera: None,
+                    app,
+                }))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        previous_applications = applications;
+    }
+}
+
+/// Waits for a request to stop the listener: Ctrl+C, or `SIGTERM` on macOS and Linux, which is
+/// what systemd and launchd send. Waits forever if we can't listen for signals.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(error) => {
+                log_error!("Failed to listen for SIGTERM: {}", error);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(error) = result {
+                log_error!("Failed to listen for Ctrl+C: {}", error);
+                std::future::pending::<()>().await;
+            }
+        }
+        _ = terminate => {}
+    }
+}
+
+/// Toggles the selected devices on `SIGUSR1`, and reloads the profiles file like `ctl reload` on
+/// `SIGHUP`, so a running listener can be controlled with `kill`, even without
+/// `--control-socket`.
+///
+/// `SIGHUP` is only handled if `reload_on_hangup` is set, i.e. with `--profile`. Otherwise, it
+/// stops the listener as usual, e.g. when the terminal it's running in is closed.
+#[cfg(unix)]
+async fn handle_control_signals(
+    worker: DeviceWorker,
+    device_filter: DeviceFilter,
+    require_device: bool,
+    reload_on_hangup: bool,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut toggle = match signal(SignalKind::user_defined1()) {
+        Ok(toggle) => toggle,
+        Err(error) => {
+            log_error!("Failed to listen for SIGUSR1: {}", error);
+            return;
+        }
+    };
+    let mut reload = if reload_on_hangup {
+        match signal(SignalKind::hangup()) {
+            Ok(reload) => Some(reload),
+            Err(error) => {
+                log_error!("Failed to listen for SIGHUP: {}", error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    loop {
+        tokio::select! {
+            Some(()) = toggle.recv() => {
+                log_info!("Received SIGUSR1, toggling Litra device");
+                let device_filter = device_filter.clone();
+                let result = worker
+                    .run(move |context| {
+                        toggle_selected_devices_and_log(context, &device_filter, require_device)
+                    })
+                    .await;
+                if let Err(error) = result {
+                    log_error!("Failed to toggle Litra device: {}", error);
+                }
+            }
+            Some(()) = async { reload.as_mut()?.recv().await }, if reload.is_some() => {
+                log_info!("Received SIGHUP, reloading the profiles file");
+                // What changed is logged as the profile is reloaded
+                if let Err(error) = worker.run(reload::reload_profile).await {
+                    log_error!("{}", error);
+                }
+            }
+            else => return,
+        }
+    }
+}
+
+/// How long to wait for devices to be turned off with `--turn-off-on-exit`, so a device that has
+/// stopped responding can't stop the listener from exiting.
+const TURN_OFF_ON_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Turns the selected devices and any Elgato Key Lights off as the listener stops, with
+/// `--turn-off-on-exit`, or puts them back how they were with `--restore-previous-state`.
+async fn turn_off_on_exit(worker: &DeviceWorker, device_filter: DeviceFilter) {
+    let turn_off = worker.run(move |context| {
+        let result = set_selected_devices_on_and_log(
+            context,
+            &device_filter,
+            false,
+            TriggerSource::Exit,
+            None,
+            false,
+        );
+        (result, context.key_lights.settled())
+    });
+
+    let turn_off = async {
+        let (result, key_lights_settled) = turn_off.await;
+        // With `--fade`, devices are only turned off once they have faded down
+        while let Some(wait) = worker
+            .run(|context| context.fades.next_step_in(Instant::now()))
+            .await
+        {
+            tokio::time::sleep(wait).await;
+        }
+        key_lights_settled.await;
+        result
+    };
+
+    match tokio::time::timeout(TURN_OFF_ON_EXIT_TIMEOUT, turn_off).await {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => log_error!("Failed to turn off Litra device before exiting: {}", error),
+        Err(_) => log_error!("Timed out turning off Litra device before exiting"),
+    }
+}
+
+/// Listens for webcam events until the listener fails or is asked to stop, then prints a summary
+/// of what it did.
+pub async fn run_listener(args: &Cli) -> CliResult {
+    if args.check_integrations || args.strict_integrations {
+        let checks = integrations::check_integrations(args).await;
+        if !integrations::log_integration_checks(&checks) && args.strict_integrations {
+            return Err(CliError::IntegrationChecksFailed);
+        }
+    }
+
+    if args.verbose {
+        integrations::log_capabilities(&integrations::get_capabilities(args));
+    }
+
+    let session = Session::new();
+
+    let mut worker = None;
+    let result = tokio::select! {
+        result = handle_autotoggle_command(args, &session, &mut worker) => result,
+        _ = wait_for_shutdown_signal() => {
+            log_info!("Stopping listener...");
+            Ok(())
+        }
+    };
+
+    if let Some(worker) = worker.filter(|_| args.turn_off_on_exit) {
+        turn_off_on_exit(&worker, args.device_filter()).await;
+    }
+
+    session.summary().log();
+    result
+}
+
+/// Runs the command given in `args`, or the listener if there isn't one, logging any error.
+/// Process-wide settings, like the log format, are set from the first `args` this is called with.
+pub async fn run(args: &Cli) -> ExitCode {
+    logging::set_format(args.log_format);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    logging::set_target(args.log_target);
+    if let Some(timer_slack) = args.timer_slack {
+        timer::set_slack(timer_slack);
+    }
+    notifications::set_routes(args.notification_routes());
+
+    let result = match &args.command {
+        Some(command) => handle_command(command, args),
+        None => run_listener(args).await,
+    };
+    notifications::finish_sending().await;
+
+    if let Err(error) = result {
+        log_error!("{}", error);
+        #[cfg(target_os = "linux")]
+        if permissions::is_permission_error(&error) {
+            permissions::print_permission_advice();
+        }
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
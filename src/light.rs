@@ -0,0 +1,91 @@
+use litra::{DeviceError, DeviceHandle, DeviceType};
+use std::fmt;
+
+/// A light the listener can turn on and off and adjust. The toggling, logging and verification
+/// code is written against this trait, so other kinds of light can be driven the same way as
+/// Litra devices by implementing it.
+pub trait Light {
+    type Error: fmt::Display;
+
+    /// What kind of light this is, e.g. `Litra Glow`, for logs and notifications.
+    fn kind(&self) -> String;
+
+    /// The type of Litra device, if this is one, for options which apply to particular types of
+    /// device, like `--write-delay`.
+    fn device_type(&self) -> Option<DeviceType> {
+        None
+    }
+
+    /// The light's serial number, if it has one, which identifies it in logs and the state file.
+    fn serial_number(&self) -> Result<Option<String>, Self::Error>;
+
+    fn is_on(&self) -> Result<bool, Self::Error>;
+    fn set_on(&self, on: bool) -> Result<(), Self::Error>;
+
+    fn brightness_in_lumen(&self) -> Result<u16, Self::Error>;
+    fn set_brightness_in_lumen(&self, brightness_in_lumen: u16) -> Result<(), Self::Error>;
+    fn minimum_brightness_in_lumen(&self) -> u16;
+    fn maximum_brightness_in_lumen(&self) -> u16;
+
+    fn temperature_in_kelvin(&self) -> Result<u16, Self::Error>;
+    fn set_temperature_in_kelvin(&self, temperature_in_kelvin: u16) -> Result<(), Self::Error>;
+    fn minimum_temperature_in_kelvin(&self) -> u16;
+    fn maximum_temperature_in_kelvin(&self) -> u16;
+}
+
+/// Litra devices, controlled over USB HID.
+impl Light for DeviceHandle {
+    type Error = DeviceError;
+
+    fn kind(&self) -> String {
+        DeviceHandle::device_type(self).to_string()
+    }
+
+    fn device_type(&self) -> Option<DeviceType> {
+        Some(DeviceHandle::device_type(self))
+    }
+
+    fn serial_number(&self) -> Result<Option<String>, DeviceError> {
+        DeviceHandle::serial_number(self)
+    }
+
+    fn is_on(&self) -> Result<bool, DeviceError> {
+        DeviceHandle::is_on(self)
+    }
+
+    fn set_on(&self, on: bool) -> Result<(), DeviceError> {
+        DeviceHandle::set_on(self, on)
+    }
+
+    fn brightness_in_lumen(&self) -> Result<u16, DeviceError> {
+        DeviceHandle::brightness_in_lumen(self)
+    }
+
+    fn set_brightness_in_lumen(&self, brightness_in_lumen: u16) -> Result<(), DeviceError> {
+        DeviceHandle::set_brightness_in_lumen(self, brightness_in_lumen)
+    }
+
+    fn minimum_brightness_in_lumen(&self) -> u16 {
+        DeviceHandle::minimum_brightness_in_lumen(self)
+    }
+
+    fn maximum_brightness_in_lumen(&self) -> u16 {
+        DeviceHandle::maximum_brightness_in_lumen(self)
+    }
+
+    fn temperature_in_kelvin(&self) -> Result<u16, DeviceError> {
+        DeviceHandle::temperature_in_kelvin(self)
+    }
+
+    fn set_temperature_in_kelvin(&self, temperature_in_kelvin: u16) -> Result<(), DeviceError> {
+        DeviceHandle::set_temperature_in_kelvin(self, temperature_in_kelvin)
+    }
+
+    fn minimum_temperature_in_kelvin(&self) -> u16 {
+        DeviceHandle::minimum_temperature_in_kelvin(self)
+    }
+
+    fn maximum_temperature_in_kelvin(&self) -> u16 {
+        DeviceHandle::maximum_temperature_in_kelvin(self)
+    }
+}
@@ -0,0 +1,319 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The format of the listener's log output, set with `--log-format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for shipping to a log aggregator
+    Json,
+}
+
+/// Where the listener's logs are written on Linux, set with `--log-target`.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    /// Standard output, with errors on standard error
+    #[default]
+    Stdout,
+    /// The systemd journal, with the details of each event as journal fields
+    Journald,
+}
+
+/// Where the listener's logs are written on macOS, set with `--log-target`.
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    /// Standard output, with errors on standard error
+    #[default]
+    Stdout,
+    /// The system log, which you can read with Console or `log show`
+    Syslog,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+static TARGET: OnceLock<LogTarget> = OnceLock::new();
+
+/// Sets the log format for the rest of the process. Logs are written as text until this is called.
+pub fn set_format(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+fn format() -> LogFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Sets where logs are written for the rest of the process. Logs are written to stdout until this
+/// is called.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set_target(target: LogTarget) {
+    let _ = TARGET.set(target);
+
+    #[cfg(target_os = "macos")]
+    if target == LogTarget::Syslog {
+        // SAFETY: the identifier is a static, null-terminated string, as `openlog` requires
+        unsafe { openlog(SYSLOG_IDENTIFIER.as_ptr().cast(), LOG_PID, LOG_USER) };
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn target() -> LogTarget {
+    TARGET.get().copied().unwrap_or_default()
+}
+
+/// Something that happened, logged as a line of text, or with `--log-format json`, as a JSON
+/// object with its details.
+#[derive(Debug, Default, Serialize)]
+pub struct Event {
+    /// What kind of event this is, e.g. `camera` or `toggle`
+    #[serde(rename = "event")]
+    pub kind: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    /// The app using the webcam, if the event source can tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger_source: Option<&'static str>,
+    /// Whether what happened worked, e.g. `succeeded` or `failed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Any other details, e.g. the session summary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Logs `event` to stdout, or to stderr if it is an error, unless `--log-target` says otherwise.
+pub fn log(event: &Event, is_error: bool) {
+    #[cfg(target_os = "linux")]
+    if target() == LogTarget::Journald {
+        match crate::systemd::send_to_journal(&journal_fields(event, is_error)) {
+            Ok(()) => return,
+            // Fall back to stderr, so the message isn't lost
+            Err(error) => eprintln!("Failed to write to the systemd journal: {}", error),
+        }
+    }
+
+    let line = match format() {
+        LogFormat::Text => event.message.clone(),
+        LogFormat::Json => {
+            let line = JsonLine {
+                timestamp: format_timestamp(SystemTime::now()),
+                level: if is_error { "error" } else { "info" },
+                event,
+            };
+            serde_json::to_string(&line).unwrap_or_else(|_| event.message.clone())
+        }
+    };
+
+    #[cfg(target_os = "macos")]
+    if target() == LogTarget::Syslog {
+        write_to_syslog(&line, is_error);
+        return;
+    }
+
+    if is_error {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Logs `event` only when logging in a structured way, in JSON format or to the systemd journal.
+/// This is for details which are already covered by text log lines, but which are useful to have
+/// as a single structured event.
+pub fn log_structured_only(event: &Event, is_error: bool) {
+    #[cfg(target_os = "linux")]
+    if target() == LogTarget::Journald {
+        log(event, is_error);
+        return;
+    }
+
+    if format() == LogFormat::Json {
+        log(event, is_error);
+    }
+}
+
+/// Converts `event` to journal fields, with its details prefixed with `LITRA_AUTOTOGGLE_`.
+#[cfg(target_os = "linux")]
+fn journal_fields(event: &Event, is_error: bool) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("MESSAGE", event.message.clone()),
+        // syslog priorities: 3 is `err`, and 6 is `info`
+        ("PRIORITY", if is_error { "3" } else { "6" }.to_string()),
+        ("SYSLOG_IDENTIFIER", "litra-autotoggle".to_string()),
+        ("LITRA_AUTOTOGGLE_EVENT", event.kind.to_string()),
+    ];
+
+    let details = [
+        (
+            "LITRA_AUTOTOGGLE_SERIAL_NUMBER",
+            event.serial_number.clone(),
+        ),
+        ("LITRA_AUTOTOGGLE_CAMERA", event.camera.clone()),
+        ("LITRA_AUTOTOGGLE_APP", event.app.clone()),
+        ("LITRA_AUTOTOGGLE_ON", event.on.map(|on| on.to_string())),
+        (
+            "LITRA_AUTOTOGGLE_TRIGGER_SOURCE",
+            event.trigger_source.map(str::to_string),
+        ),
+        (
+            "LITRA_AUTOTOGGLE_OUTCOME",
+            event.outcome.map(str::to_string),
+        ),
+        ("LITRA_AUTOTOGGLE_ERROR", event.error.clone()),
+        (
+            "LITRA_AUTOTOGGLE_DETAILS",
+            event.details.as_ref().map(|details| details.to_string()),
+        ),
+    ];
+    fields.extend(
+        details
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?))),
+    );
+
+    fields
+}
+
+#[cfg(target_os = "macos")]
+const SYSLOG_IDENTIFIER: &[u8] = b"litra-autotoggle\0";
+#[cfg(target_os = "macos")]
+const LOG_PID: std::ffi::c_int = 0x01;
+#[cfg(target_os = "macos")]
+const LOG_USER: std::ffi::c_int = 1 << 3;
+#[cfg(target_os = "macos")]
+const LOG_ERR: std::ffi::c_int = 3;
+#[cfg(target_os = "macos")]
+const LOG_INFO: std::ffi::c_int = 6;
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn openlog(
+        identifier: *const std::ffi::c_char,
+        option: std::ffi::c_int,
+        facility: std::ffi::c_int,
+    );
+    fn syslog(priority: std::ffi::c_int, format: *const std::ffi::c_char, ...);
+}
+
+/// Writes `line` to the system log, which also makes it part of the unified log.
+#[cfg(target_os = "macos")]
+fn write_to_syslog(line: &str, is_error: bool) {
+    let Ok(message) = std::ffi::CString::new(line.replace('\0', "")) else {
+        return;
+    };
+
+    // SAFETY: `%s` takes a single null-terminated string, which `message` is
+    unsafe {
+        syslog(
+            if is_error { LOG_ERR } else { LOG_INFO },
+            b"%s\0".as_ptr().cast(),
+            message.as_ptr(),
+        )
+    };
+}
+
+pub fn is_text() -> bool {
+    format() == LogFormat::Text
+}
+
+pub fn info(message: String) {
+    log(
+        &Event {
+            kind: "message",
+            message,
+            ..Event::default()
+        },
+        false,
+    );
+}
+
+pub fn error(message: String) {
+    log(
+        &Event {
+            kind: "message",
+            message,
+            ..Event::default()
+        },
+        true,
+    );
+}
+
+/// Logs a message, like `println!`, in the format set with `--log-format`.
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::info(format!($($arg)*))
+    };
+}
+
+/// Logs an error, like `eprintln!`, in the format set with `--log-format`.
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logging::error(format!($($arg)*))
+    };
+}
+
+pub(crate) use log_error;
+pub(crate) use log_info;
+
+/// Formats `time` as an RFC 3339 timestamp in UTC, e.g. `2024-06-21T12:00:00.000Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Converts a number of days since 1 January 1970 to a year, month and day, using Howard
+/// Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
@@ -0,0 +1,939 @@
+use crate::logging::log_error;
+use clap::ValueEnum;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// How long a channel has to accept a notification before giving up on it.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_HTTP_PORT: u16 = 80;
+const DEFAULT_SMTP_PORT: u16 = 25;
+const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Who emails are sent from, if the `smtp://` channel doesn't say.
+const DEFAULT_EMAIL_SENDER: &str = "litra-autotoggle@localhost";
+
+/// How serious a notification is. Each channel only gets notifications at or above the severity
+/// it was given with `--notification-channel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Severity {
+    /// The Litra device was turned on or off
+    Info,
+    /// Something needs attention, but the listener carried on, e.g. the Litra device couldn't be
+    /// found
+    Warning,
+    /// Something failed, e.g. the Litra device couldn't be turned on or off
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Sending a notification, which finishes in the background.
+pub type Sending = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+/// Checking that a channel will work, for `--check-integrations`, which finishes with a
+/// description of what was checked.
+pub type Checking = Pin<Box<dyn Future<Output = Result<String, String>> + Send>>;
+
+/// Somewhere notifications can be sent, like the desktop or a webhook. New kinds of channel
+/// implement this and are added to [`parse_route`].
+pub trait NotificationChannel: Send + Sync {
+    /// Starts sending `message`, about `camera` if a webcam caused it. Anything which must happen
+    /// straight away is done before returning, and the rest is done by the returned future.
+    fn send(&self, severity: Severity, message: &str, camera: Option<&str>) -> Sending;
+
+    /// Checks that notifications can be sent, without sending one.
+    fn check(&self) -> Checking;
+}
+
+/// A channel given with `--notification-channel`, and the least serious notifications it gets.
+#[derive(Clone)]
+pub struct Route {
+    severity: Severity,
+    destination: String,
+    channel: Arc<dyn NotificationChannel>,
+}
+
+impl Route {
+    pub fn new(
+        severity: Severity,
+        destination: impl Into<String>,
+        channel: Arc<dyn NotificationChannel>,
+    ) -> Self {
+        Route {
+            severity,
+            destination: destination.into(),
+            channel,
+        }
+    }
+}
+
+impl Route {
+    /// Checks that this route's channel will work, for `--check-integrations`.
+    pub async fn check(&self) -> Result<String, String> {
+        match tokio::time::timeout(SEND_TIMEOUT, self.channel.check()).await {
+            Ok(result) => result,
+            Err(_) => Err("timed out".to_string()),
+        }
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Route({})", self)
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.severity, self.destination)
+    }
+}
+
+/// Splits a destination like `smtp://mail.example.com:2525/ops@example.com` into its host and
+/// port (adding `default_port` if there isn't one) and the rest, without the leading `/`.
+fn split_destination(rest: &str, default_port: u16) -> Result<(String, String), String> {
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    if authority.is_empty() {
+        return Err("it must include a host name or IP address".to_string());
+    }
+
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:{}", authority, default_port)
+    };
+    Ok((address, path.to_string()))
+}
+
+/// Parses a `--notification-channel`, e.g. `desktop` or `error=mqtt://broker.local/litra`.
+pub fn parse_route(value: &str) -> Result<Route, String> {
+    let (severity, destination) = match value.split_once('=') {
+        Some((severity, destination)) if !severity.contains(':') => (
+            Severity::from_str(severity, true).map_err(|_| {
+                format!(
+                    "Invalid severity `{}`. It must be `info`, `warning` or `error`.",
+                    severity
+                )
+            })?,
+            destination,
+        ),
+        _ => (Severity::Info, value),
+    };
+    let invalid = |reason: String| {
+        format!(
+            "Invalid notification channel `{}`: {}.",
+            destination, reason
+        )
+    };
+
+    let channel: Arc<dyn NotificationChannel> = match destination.split_once("://") {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        None if destination == "desktop" => Arc::new(DesktopChannel),
+        #[cfg(target_os = "windows")]
+        None if destination == "desktop" => {
+            return Err(invalid(
+                "desktop notifications are only supported on macOS and Linux".to_string(),
+            ))
+        }
+        Some(("http", rest)) => {
+            let (address, path) = split_destination(rest, DEFAULT_HTTP_PORT).map_err(invalid)?;
+            Arc::new(WebhookChannel {
+                address,
+                path: format!("/{}", path),
+            })
+        }
+        Some(("smtp", rest)) => {
+            let (address, path) = split_destination(rest, DEFAULT_SMTP_PORT).map_err(invalid)?;
+            let (recipient, query) = path.split_once('?').unwrap_or((&path, ""));
+            if !recipient.contains('@') {
+                return Err(invalid(
+                    "it must include who to email, e.g. `smtp://mail.example.com/ops@example.com`"
+                        .to_string(),
+                ));
+            }
+            let sender = query
+                .split('&')
+                .find_map(|parameter| parameter.strip_prefix("from="))
+                .unwrap_or(DEFAULT_EMAIL_SENDER);
+            Arc::new(EmailChannel {
+                address,
+                sender: sender.to_string(),
+                recipient: recipient.to_string(),
+            })
+        }
+        Some(("mqtt", rest)) => {
+            let (address, topic) = split_destination(rest, DEFAULT_MQTT_PORT).map_err(invalid)?;
+            if topic.is_empty() {
+                return Err(invalid(
+                    "it must include a topic, e.g. `mqtt://broker.local/litra`".to_string(),
+                ));
+            }
+            Arc::new(MqttChannel { address, topic })
+        }
+        _ => {
+            return Err(invalid(
+                "it must be `desktop`, or start with `http://`, `smtp://` or `mqtt://`".to_string(),
+            ))
+        }
+    };
+
+    Ok(Route::new(severity, destination, channel))
+}
+
+static ROUTES: OnceLock<Vec<Route>> = OnceLock::new();
+
+/// Notifications which are still being sent, so they can be waited for before exiting.
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Sets where notifications are sent for the rest of the process. Notifications are dropped
+/// until this is called.
+pub fn set_routes(routes: Vec<Route>) {
+    let _ = ROUTES.set(routes);
+}
+
+/// Sends `message` to each channel which gets notifications as serious as `severity`, with the
+/// webcam which caused it, if any. Failures are logged, since they shouldn't stop the listener.
+pub fn notify(severity: Severity, message: &str, camera: Option<&str>) {
+    for route in ROUTES.get().into_iter().flatten() {
+        if severity < route.severity {
+            continue;
+        }
+
+        let sending = route.channel.send(severity, message, camera);
+        let destination = route.destination.clone();
+        let handle = tokio::spawn(async move {
+            let result = match tokio::time::timeout(SEND_TIMEOUT, sending).await {
+                Ok(result) => result,
+                Err(_) => Err("timed out".to_string()),
+            };
+            if let Err(error) = result {
+                log_error!("Failed to send notification to {}: {}", destination, error);
+            }
+        });
+
+        let mut pending = PENDING.lock().unwrap();
+        pending.retain(|handle| !handle.is_finished());
+        pending.push(handle);
+    }
+}
+
+/// Waits for notifications which are still being sent, so they aren't lost when a one-off command
+/// or the listener exits. Each one gives up after [`SEND_TIMEOUT`], so this can't hang.
+pub async fn finish_sending() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+    for handle in pending {
+        let _ = handle.await;
+    }
+}
+
+/// Shows a desktop notification with `message`, using `notify-send` on Linux and `osascript` on
+/// macOS.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn show_desktop_notification(message: &str) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    let result = tokio::process::Command::new("notify-send")
+        .arg("litra-autotoggle")
+        .arg(message)
+        .spawn();
+
+    #[cfg(target_os = "macos")]
+    let result = tokio::process::Command::new("osascript")
+        .arg("-e")
+        .arg(format!(
+            "display notification \"{}\" with title \"litra-autotoggle\"",
+            message.replace('\\', "\\\\").replace('"', "\\\"")
+        ))
+        .spawn();
+
+    result.map(|_| ())
+}
+
+/// Desktop notifications, with `desktop` or `--notify`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub struct DesktopChannel;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl NotificationChannel for DesktopChannel {
+    fn send(&self, _severity: Severity, message: &str, _camera: Option<&str>) -> Sending {
+        // The notification is shown by a separate process, so it is started straight away
+        let result = show_desktop_notification(message).map_err(|error| error.to_string());
+        Box::pin(std::future::ready(result))
+    }
+
+    fn check(&self) -> Checking {
+        #[cfg(target_os = "linux")]
+        let program = "notify-send";
+        #[cfg(target_os = "macos")]
+        let program = "osascript";
+
+        Box::pin(std::future::ready(crate::integrations::check_program(
+            program,
+        )))
+    }
+}
+
+/// Connects to the server a channel sends notifications through.
+async fn connect(address: &str) -> Result<TcpStream, String> {
+    TcpStream::connect(address)
+        .await
+        .map_err(|error| format!("unable to connect to {}: {}", address, error))
+}
+
+/// A JSON `POST` to a webhook, with `http://`.
+struct WebhookChannel {
+    address: String,
+    path: String,
+}
+
+impl NotificationChannel for WebhookChannel {
+    fn send(&self, severity: Severity, message: &str, camera: Option<&str>) -> Sending {
+        let body = serde_json::json!({
+            "severity": severity.to_string(),
+            "message": message,
+            "camera": camera,
+        })
+        .to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.address,
+            body.len(),
+            body
+        );
+        let address = self.address.clone();
+
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&address)
+                .await
+                .map_err(|error| error.to_string())?;
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(|error| error.to_string())?;
+
+            let mut status_line = String::new();
+            BufReader::new(stream)
+                .read_line(&mut status_line)
+                .await
+                .map_err(|error| error.to_string())?;
+            match status_line.split_whitespace().nth(1) {
+                Some(status) if status.starts_with('2') => Ok(()),
+                _ => Err(format!("unexpected response `{}`", status_line.trim())),
+            }
+        })
+    }
+
+    fn check(&self) -> Checking {
+        // Sending a request would trigger the webhook, so this only checks it can be reached
+        let address = self.address.clone();
+        Box::pin(async move {
+            connect(&address).await?;
+            Ok(format!("able to connect to {}", address))
+        })
+    }
+}
+
+/// An email sent through an SMTP server which accepts mail without authentication (e.g. a local
+/// relay), with `smtp://`.
+struct EmailChannel {
+    address: String,
+    sender: String,
+    recipient: String,
+}
+
+/// Formats `time` for an email's `Date` header, e.g. `Tue, 01 Oct 2024 09:30:00 +0000`. It is
+/// always given in UTC, since the standard library can't convert to local time.
+fn format_email_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let days = seconds / 86_400;
+    let seconds_of_day = seconds % 86_400;
+
+    // Converts days since the Unix epoch to a date, from Howard Hinnant's `civil_from_days`
+    let days_since_0000 = days + 719_468;
+    let era = days_since_0000 / 146_097;
+    let day_of_era = days_since_0000 % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    // `month_index` counts from March, so January and February are in the next year
+    let month = (month_index + 2) % 12;
+    let year = year_of_era + era * 400 + u64::from(month < 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Makes a unique `Message-ID` for an email from `sender`, using the sender's domain.
+fn email_message_id(sender: &str, time: SystemTime) -> String {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+
+    let domain = sender
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "<{}.{:09}.{}.{}@{}>",
+        time.as_secs(),
+        time.subsec_nanos(),
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed),
+        domain
+    )
+}
+
+/// Reads an SMTP reply, which can span several lines, and checks it has the expected code.
+async fn read_smtp_reply(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    expected: &[&str],
+) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        if reader
+            .read_line(&mut line)
+            .await
+            .map_err(|error| error.to_string())?
+            == 0
+        {
+            return Err("the server closed the connection".to_string());
+        }
+
+        // Every line but the last has a `-` after the code
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+
+        return match line.get(..3) {
+            Some(code) if expected.contains(&code) => Ok(()),
+            _ => Err(format!("unexpected reply `{}`", line.trim())),
+        };
+    }
+}
+
+impl NotificationChannel for EmailChannel {
+    fn send(&self, severity: Severity, message: &str, camera: Option<&str>) -> Sending {
+        let message = match camera {
+            Some(camera) => format!("{}\n\nCamera: {}", message, camera),
+            None => message.to_string(),
+        };
+        // Lines starting with `.` are escaped, so they can't end the message early
+        let body = message
+            .lines()
+            .map(|line| match line.strip_prefix('.') {
+                Some(_) => format!(".{}", line),
+                None => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let now = SystemTime::now();
+        let commands: [(String, &[&str]); 6] = [
+            ("HELO localhost\r\n".to_string(), &["250"]),
+            (format!("MAIL FROM:<{}>\r\n", self.sender), &["250"]),
+            (format!("RCPT TO:<{}>\r\n", self.recipient), &["250", "251"]),
+            ("DATA\r\n".to_string(), &["354"]),
+            (
+                format!(
+                    "Date: {}\r\nMessage-ID: {}\r\nFrom: {}\r\nTo: {}\r\nSubject: litra-autotoggle {}\r\n\r\n{}\r\n.\r\n",
+                    format_email_date(now),
+                    email_message_id(&self.sender, now),
+                    self.sender,
+                    self.recipient,
+                    severity,
+                    body
+                ),
+                &["250"],
+            ),
+            ("QUIT\r\n".to_string(), &["221"]),
+        ];
+        let address = self.address.clone();
+
+        Box::pin(async move {
+            let stream = TcpStream::connect(&address)
+                .await
+                .map_err(|error| error.to_string())?;
+            let mut reader = BufReader::new(stream);
+            read_smtp_reply(&mut reader, &["220"]).await?;
+
+            for (command, expected) in commands {
+                reader
+                    .get_mut()
+                    .write_all(command.as_bytes())
+                    .await
+                    .map_err(|error| error.to_string())?;
+                read_smtp_reply(&mut reader, expected).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn check(&self) -> Checking {
+        let address = self.address.clone();
+        Box::pin(async move {
+            let mut reader = BufReader::new(connect(&address).await?);
+            read_smtp_reply(&mut reader, &["220"]).await?;
+            reader
+                .get_mut()
+                .write_all(b"QUIT\r\n")
+                .await
+                .map_err(|error| error.to_string())?;
+            read_smtp_reply(&mut reader, &["221"]).await?;
+            Ok(format!(
+                "the SMTP server at {} is accepting connections",
+                address
+            ))
+        })
+    }
+}
+
+/// A message published to an MQTT broker which accepts anonymous clients, with `mqtt://`.
+struct MqttChannel {
+    address: String,
+    topic: String,
+}
+
+/// Builds an MQTT control packet, with its remaining length encoded as the spec requires.
+fn mqtt_packet(packet_type: u8, contents: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    let mut length = contents.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(contents);
+    packet
+}
+
+/// Encodes a string with the two byte length prefix MQTT uses.
+fn mqtt_string(value: &str) -> Vec<u8> {
+    let mut encoded = (value.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(value.as_bytes());
+    encoded
+}
+
+/// Connects to the MQTT broker at `address`, waiting for it to accept the connection.
+async fn mqtt_connect(address: &str) -> Result<TcpStream, String> {
+    // An MQTT 3.1.1 connection with a clean session, no will and a 60 second keepalive
+    let mut contents = mqtt_string("MQTT");
+    contents.extend_from_slice(&[4, 0x02, 0, 60]);
+    contents.extend(mqtt_string(&format!(
+        "litra-autotoggle-{}",
+        std::process::id()
+    )));
+
+    let mut stream = connect(address).await?;
+    stream
+        .write_all(&mqtt_packet(0x10, &contents))
+        .await
+        .map_err(|error| error.to_string())?;
+
+    let mut connack = [0; 4];
+    stream
+        .read_exact(&mut connack)
+        .await
+        .map_err(|error| error.to_string())?;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        return Err(format!(
+            "the broker refused the connection (return code {})",
+            connack[3]
+        ));
+    }
+
+    Ok(stream)
+}
+
+impl NotificationChannel for MqttChannel {
+    fn send(&self, severity: Severity, message: &str, camera: Option<&str>) -> Sending {
+        // Published with QoS 0, so there's no acknowledgement to wait for
+        let mut publish = mqtt_string(&self.topic);
+        publish.extend_from_slice(
+            serde_json::json!({
+                "severity": severity.to_string(),
+                "message": message,
+                "camera": camera,
+            })
+            .to_string()
+            .as_bytes(),
+        );
+
+        let packets = [
+            mqtt_packet(0x30, &publish),
+            // DISCONNECT
+            mqtt_packet(0xE0, &[]),
+        ];
+        let address = self.address.clone();
+
+        Box::pin(async move {
+            let mut stream = mqtt_connect(&address).await?;
+            for packet in packets {
+                stream
+                    .write_all(&packet)
+                    .await
+                    .map_err(|error| error.to_string())?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn check(&self) -> Checking {
+        let address = self.address.clone();
+        Box::pin(async move {
+            let mut stream = mqtt_connect(&address).await?;
+            // DISCONNECT
+            stream
+                .write_all(&mqtt_packet(0xE0, &[]))
+                .await
+                .map_err(|error| error.to_string())?;
+            Ok(format!(
+                "the MQTT broker at {} accepted a connection",
+                address
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_mqtt_remaining_length() {
+        for (length, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xFF, 0x7F]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+            (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let contents = vec![0xAB; length];
+            let packet = mqtt_packet(0x30, &contents);
+            assert_eq!(packet[0], 0x30);
+            assert_eq!(packet[1..1 + encoded.len()], encoded, "length {}", length);
+            assert_eq!(packet[1 + encoded.len()..], contents);
+        }
+    }
+
+    #[test]
+    fn encodes_mqtt_strings() {
+        assert_eq!(mqtt_string("MQTT"), vec![0, 4, b'M', b'Q', b'T', b'T']);
+        assert_eq!(mqtt_string(""), vec![0, 0]);
+    }
+
+    #[tokio::test]
+    async fn reads_multi_line_smtp_replies() {
+        let mut reader: &[u8] =
+            b"250-mail.example.com\r\n250-SIZE 1000\r\n250 HELP\r\n354 Go ahead\r\n";
+        assert_eq!(read_smtp_reply(&mut reader, &["250"]).await, Ok(()));
+        // Only the first reply is read
+        assert_eq!(read_smtp_reply(&mut reader, &["354"]).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_smtp_replies() {
+        let mut reader: &[u8] = b"250-mail.example.com\r\n550 No such user\r\n";
+        assert_eq!(
+            read_smtp_reply(&mut reader, &["250", "251"]).await,
+            Err("unexpected reply `550 No such user`".to_string())
+        );
+
+        let mut reader: &[u8] = b"250-mail.example.com\r\n";
+        assert_eq!(
+            read_smtp_reply(&mut reader, &["250"]).await,
+            Err("the server closed the connection".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_routes() {
+        for (value, route) in [
+            (
+                "error=mqtt://broker.local/litra",
+                "error=mqtt://broker.local/litra",
+            ),
+            ("WARNING=http://hooks.local", "warning=http://hooks.local"),
+            (
+                "smtp://mail.local:2525/ops@example.com?from=me@example.com",
+                "info=smtp://mail.local:2525/ops@example.com?from=me@example.com",
+            ),
+            // The `=` is part of the destination, not a severity
+            (
+                "http://hooks.local:8080/notify?a=b",
+                "info=http://hooks.local:8080/notify?a=b",
+            ),
+        ] {
+            assert_eq!(parse_route(value).unwrap().to_string(), route);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_routes() {
+        for value in [
+            "critical=http://hooks.local",
+            "http:///notify",
+            "mqtt://broker.local",
+            "mqtt://broker.local/",
+            "smtp://mail.local/ops",
+            "ftp://files.local/litra",
+            "hooks.local",
+        ] {
+            assert!(parse_route(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_camera_to_webhooks() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let route =
+            parse_route(&format!("http://{}/notify", listener.local_addr().unwrap())).unwrap();
+
+        let sending = route.channel.send(
+            Severity::Info,
+            "Turned on Litra Glow",
+            Some("FaceTime HD Camera"),
+        );
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            body
+        });
+
+        assert_eq!(sending.await, Ok(()));
+        let body: serde_json::Value = serde_json::from_slice(&server.await.unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "severity": "info",
+                "message": "Turned on Litra Glow",
+                "camera": "FaceTime HD Camera",
+            })
+        );
+    }
+
+    #[test]
+    fn formats_email_dates() {
+        for (seconds, date) in [
+            (0, "Thu, 01 Jan 1970 00:00:00 +0000"),
+            (951_782_400, "Tue, 29 Feb 2000 00:00:00 +0000"),
+            (1_727_775_000, "Tue, 01 Oct 2024 09:30:00 +0000"),
+            (1_735_689_599, "Tue, 31 Dec 2024 23:59:59 +0000"),
+        ] {
+            assert_eq!(
+                format_email_date(UNIX_EPOCH + Duration::from_secs(seconds)),
+                date
+            );
+        }
+    }
+
+    #[test]
+    fn makes_unique_message_ids_for_the_sender() {
+        let now = SystemTime::now();
+        let first = email_message_id("me@example.com", now);
+        assert!(first.starts_with('<'));
+        assert!(first.ends_with("@example.com>"));
+        assert_ne!(email_message_id("me@example.com", now), first);
+        assert!(email_message_id("me", now).ends_with("@localhost>"));
+    }
+
+    #[tokio::test]
+    async fn sends_emails_through_smtp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let route = parse_route(&format!(
+            "smtp://{}/ops@example.com?from=me@example.com",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let sending = route.channel.send(
+            Severity::Error,
+            "Failed to turn on Litra Glow\n.hidden",
+            Some("FaceTime HD Camera"),
+        );
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut commands = Vec::new();
+            let mut data = String::new();
+            reader
+                .get_mut()
+                .write_all(b"220 mail.example.com\r\n")
+                .await
+                .unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let reply: &[u8] = match line.trim_end() {
+                    "DATA" => b"354 Go ahead\r\n",
+                    "QUIT" => b"221 Bye\r\n",
+                    _ => b"250-mail.example.com\r\n250 OK\r\n",
+                };
+                commands.push(line.trim_end().to_string());
+                reader.get_mut().write_all(reply).await.unwrap();
+
+                if line.trim_end() == "DATA" {
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).await.unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&line);
+                    }
+                    reader.get_mut().write_all(b"250 Queued\r\n").await.unwrap();
+                }
+            }
+            (commands, data)
+        });
+
+        assert_eq!(sending.await, Ok(()));
+        let (commands, data) = server.await.unwrap();
+        assert_eq!(
+            commands,
+            [
+                "HELO localhost",
+                "MAIL FROM:<me@example.com>",
+                "RCPT TO:<ops@example.com>",
+                "DATA",
+                "QUIT"
+            ]
+        );
+
+        let (headers, body) = data.split_once("\r\n\r\n").unwrap();
+        let headers: Vec<(&str, &str)> = headers
+            .lines()
+            .map(|header| header.split_once(": ").unwrap())
+            .collect();
+        let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["Date", "Message-ID", "From", "To", "Subject"]);
+        assert!(headers[0].1.ends_with(" +0000"));
+        assert!(headers[1].1.ends_with("@example.com>"));
+        assert_eq!(
+            headers[2..],
+            [
+                ("From", "me@example.com"),
+                ("To", "ops@example.com"),
+                ("Subject", "litra-autotoggle error"),
+            ]
+        );
+        assert_eq!(
+            body,
+            "Failed to turn on Litra Glow\r\n..hidden\r\n\r\nCamera: FaceTime HD Camera\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_to_mqtt() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let route = parse_route(&format!(
+            "mqtt://{}/home/litra",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let sending = route
+            .channel
+            .send(Severity::Info, "Turned on Litra Glow", None);
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // Reads a packet, whose remaining length is short enough to fit in one byte
+            async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+                let mut header = [0; 2];
+                stream.read_exact(&mut header).await.unwrap();
+                assert!(header[1] < 0x80);
+                let mut contents = vec![0; header[1] as usize];
+                stream.read_exact(&mut contents).await.unwrap();
+                (header[0], contents)
+            }
+
+            let (packet_type, connect) = read_packet(&mut stream).await;
+            assert_eq!(packet_type, 0x10);
+            assert_eq!(
+                connect[..10],
+                [0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60]
+            );
+            // CONNACK, accepting the connection
+            stream.write_all(&[0x20, 0x02, 0, 0]).await.unwrap();
+
+            let publish = read_packet(&mut stream).await;
+            let disconnect = read_packet(&mut stream).await;
+            (publish, disconnect)
+        });
+
+        assert_eq!(sending.await, Ok(()));
+        let ((packet_type, publish), disconnect) = server.await.unwrap();
+        assert_eq!(packet_type, 0x30);
+        let topic = mqtt_string("home/litra");
+        assert_eq!(publish[..topic.len()], topic);
+        let payload: serde_json::Value = serde_json::from_slice(&publish[topic.len()..]).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "severity": "info",
+                "message": "Turned on Litra Glow",
+                "camera": null,
+            })
+        );
+        assert_eq!(disconnect, (0xE0, Vec::new()));
+    }
+}
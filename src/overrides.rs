@@ -0,0 +1,242 @@
+use crate::duration::parse_duration_in_milliseconds;
+use crate::quiet_hours::parse_quiet_hours;
+use crate::{parse_temperature_step, Brightness, DeviceOptions, Temperature};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+
+/// The value which puts a setting back to how the listener was started.
+const DEFAULT_VALUE: &str = "default";
+
+/// The value which unsets an optional setting, e.g. so the brightness isn't changed when turning
+/// the Litra device on.
+const NONE_VALUE: &str = "none";
+
+/// Settings which can be changed in a running listener with `ctl set`, so you can try out values
+/// before changing how you start the listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Setting {
+    /// `--brightness`, in lumens
+    Brightness,
+    /// `--brightness-percentage`
+    BrightnessPercentage,
+    /// `--temperature`, in Kelvin
+    Temperature,
+    /// `--fade`, e.g. `500ms`
+    Fade,
+    /// `--brightness-step`, as a percentage
+    BrightnessStep,
+    /// `--temperature-step`, in Kelvin
+    TemperatureStep,
+    /// `--idle-brightness-percentage`
+    IdleBrightnessPercentage,
+    /// `--quiet-hours`, e.g. `22:00-07:00`
+    QuietHours,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+pub fn parse_setting(value: &str) -> Result<Setting, String> {
+    Setting::from_str(value, true).map_err(|_| {
+        format!(
+            "Unknown setting `{}`. It must be one of {}.",
+            value,
+            Setting::value_variants()
+                .iter()
+                .map(|setting| format!("`{}`", setting))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
+fn parse_number(value: &str) -> Result<u16, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value `{}`. It must be a whole number.", value))
+}
+
+fn parse_percentage(value: &str) -> Result<u8, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|percentage| (1..=100).contains(percentage))
+        .ok_or_else(|| {
+            format!(
+                "Invalid percentage `{}`. It must be between 1 and 100.",
+                value
+            )
+        })
+}
+
+/// Parses `value` with `parse`, or returns `None` if it is `none`.
+fn parse_optional<T>(
+    value: &str,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    if value == NONE_VALUE {
+        Ok(None)
+    } else {
+        parse(value).map(Some)
+    }
+}
+
+impl Setting {
+    /// Whether changing this setting replaces a change to `other`, because they are different
+    /// ways of setting the same thing.
+    fn replaces(self, other: Setting) -> bool {
+        let is_brightness =
+            |setting| matches!(setting, Setting::Brightness | Setting::BrightnessPercentage);
+
+        self == other || (is_brightness(self) && is_brightness(other))
+    }
+
+    fn apply(self, options: &mut DeviceOptions, value: &str) -> Result<(), String> {
+        match self {
+            Setting::Brightness => {
+                options.power_on_settings.brightness =
+                    parse_optional(value, parse_number)?.map(Brightness::Lumen);
+            }
+            Setting::BrightnessPercentage => {
+                options.power_on_settings.brightness =
+                    parse_optional(value, parse_percentage)?.map(Brightness::Percentage);
+            }
+            Setting::Temperature => {
+                options.power_on_settings.temperature =
+                    parse_optional(value, parse_number)?.map(Temperature::Kelvin);
+            }
+            Setting::Fade => options.fade = parse_optional(value, parse_duration_in_milliseconds)?,
+            Setting::BrightnessStep => {
+                options.brightness_step_percentage = parse_percentage(value)?
+            }
+            Setting::TemperatureStep => {
+                options.temperature_step_in_kelvin = parse_temperature_step(value)?;
+            }
+            Setting::IdleBrightnessPercentage => {
+                let percentage = parse_optional(value, parse_percentage)?;
+                if percentage.is_some() && options.restore_previous_state {
+                    return Err(
+                        "`idle-brightness-percentage` can't be combined with `--restore-previous-state`"
+                            .to_string(),
+                    );
+                }
+                options.idle_brightness_percentage = percentage;
+            }
+            Setting::QuietHours => {
+                options.quiet_hours = parse_optional(value, parse_quiet_hours)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(self, options: &mut DeviceOptions, startup_options: &DeviceOptions) {
+        match self {
+            Setting::Brightness | Setting::BrightnessPercentage => {
+                options.power_on_settings.brightness = startup_options.power_on_settings.brightness;
+            }
+            Setting::Temperature => {
+                options.power_on_settings.temperature =
+                    startup_options.power_on_settings.temperature;
+            }
+            Setting::Fade => options.fade = startup_options.fade,
+            Setting::BrightnessStep => {
+                options.brightness_step_percentage = startup_options.brightness_step_percentage;
+            }
+            Setting::TemperatureStep => {
+                options.temperature_step_in_kelvin = startup_options.temperature_step_in_kelvin;
+            }
+            Setting::IdleBrightnessPercentage => {
+                options.idle_brightness_percentage = startup_options.idle_brightness_percentage;
+            }
+            Setting::QuietHours => options.quiet_hours = startup_options.quiet_hours,
+        }
+    }
+}
+
+/// A setting changed with `ctl set`, which lasts until the listener restarts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Override {
+    pub setting: Setting,
+    pub value: String,
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.setting, self.value)
+    }
+}
+
+/// The settings changed with `ctl set`, and the options the listener was started with, so they
+/// can be put back.
+#[derive(Debug)]
+pub struct Overrides {
+    startup_options: DeviceOptions,
+    active: Vec<Override>,
+}
+
+impl Overrides {
+    pub fn new(startup_options: DeviceOptions) -> Self {
+        Overrides {
+            startup_options,
+            active: Vec::new(),
+        }
+    }
+
+    /// Replaces the options the listener was started with, e.g. after the profiles file is
+    /// reloaded, returning them with the settings changed with `ctl set` applied on top. Changes
+    /// which can't be applied to the new options any more are dropped.
+    pub fn rebase(&mut self, startup_options: DeviceOptions) -> DeviceOptions {
+        let mut options = startup_options;
+        self.active
+            .retain(|active| active.setting.apply(&mut options, &active.value).is_ok());
+        self.startup_options = startup_options;
+        options
+    }
+
+    /// The settings which are currently changed from how the listener was started.
+    pub fn active(&self) -> &[Override] {
+        &self.active
+    }
+
+    /// Changes `setting` in `options` to `value` until the listener restarts, or puts it back to
+    /// how the listener was started if `value` is `default`. Returns a message describing the
+    /// change.
+    pub fn set(
+        &mut self,
+        options: &mut DeviceOptions,
+        setting: Setting,
+        value: &str,
+    ) -> Result<String, String> {
+        if value == DEFAULT_VALUE {
+            setting.reset(options, &self.startup_options);
+            self.active
+                .retain(|active| !setting.replaces(active.setting));
+            return Ok(format!(
+                "Set `{}` back to how the listener was started",
+                setting
+            ));
+        }
+
+        setting.apply(options, value)?;
+        self.active
+            .retain(|active| !setting.replaces(active.setting));
+        self.active.push(Override {
+            setting,
+            value: value.to_string(),
+        });
+
+        Ok(format!(
+            "Set `{}` to `{}` until the listener restarts",
+            setting, value
+        ))
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{CliError, CliResult};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// The udev rules from the repository, which give the `video` group access to Litra devices.
+const UDEV_RULES: &str = include_str!("../99-litra.rules");
+
+/// Where `fix-permissions` installs the udev rules.
+const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-litra.rules";
+
+/// The directories udev loads rules from.
+const UDEV_RULES_DIRECTORIES: [&str; 4] = [
+    "/etc/udev/rules.d",
+    "/run/udev/rules.d",
+    "/usr/lib/udev/rules.d",
+    "/lib/udev/rules.d",
+];
+
+const GROUP: &str = "video";
+
+/// Whether `error` is from being refused access to a Litra device.
+pub fn is_permission_error(error: &CliError) -> bool {
+    matches!(error, CliError::DeviceError(error) if error.to_string().contains("Permission denied"))
+}
+
+/// Works out why we weren't allowed to open a Litra device, and prints how to fix it.
+pub fn print_permission_advice() {
+    eprintln!();
+    eprintln!("You don't have permission to access your Litra device.");
+
+    let has_rule = has_udev_rule();
+    let membership = get_group_membership();
+
+    if !has_rule {
+        eprintln!(
+            "- No udev rule for Litra devices was found in {}.",
+            UDEV_RULES_DIRECTORIES.join(", ")
+        );
+    }
+    match membership {
+        GroupMembership::Active => {}
+        GroupMembership::PendingLogin => eprintln!(
+            "- You've been added to the `{}` group, but you need to log out and back in for it to take effect.",
+            GROUP
+        ),
+        GroupMembership::None => eprintln!("- You aren't in the `{}` group.", GROUP),
+    }
+
+    if has_rule && membership == GroupMembership::Active {
+        eprintln!("- The udev rule and your group membership look right, so the rule may not have been applied to your device yet. Unplug it and plug it back in, or run `sudo udevadm control --reload-rules && sudo udevadm trigger`.");
+    } else {
+        eprintln!("Run `sudo litra-autotoggle fix-permissions` to fix this, or see https://github.com/timrogers/litra-autotoggle#configuring-udev-permissions-linux-only.");
+    }
+}
+
+/// Installs the udev rules, applies them, and adds the user who ran `sudo` to the `video` group.
+/// Must be run as `root`.
+pub fn fix_permissions() -> CliResult {
+    if has_udev_rule() {
+        println!("A udev rule for Litra devices is already installed");
+    } else {
+        std::fs::write(UDEV_RULES_PATH, UDEV_RULES).map_err(|error| {
+            CliError::IoError(std::io::Error::new(
+                error.kind(),
+                format!(
+                    "Failed to write {} ({}). Run `sudo litra-autotoggle fix-permissions`.",
+                    UDEV_RULES_PATH, error
+                ),
+            ))
+        })?;
+        println!("Installed udev rule at {}", UDEV_RULES_PATH);
+
+        run("udevadm", &["control", "--reload-rules"])?;
+        run("udevadm", &["trigger"])?;
+        println!("Applied udev rules");
+    }
+
+    match std::env::var("SUDO_USER") {
+        Ok(user) if !group_members().contains(&user) => {
+            run("usermod", &["--append", "--groups", GROUP, &user])?;
+            println!(
+                "Added {} to the `{}` group. Log out and back in for it to take effect.",
+                user, GROUP
+            );
+        }
+        Ok(user) => println!("{} is already in the `{}` group", user, GROUP),
+        Err(_) => println!(
+            "Make sure you're in the `{}` group, e.g. with `sudo usermod --append --groups {} $USER`",
+            GROUP, GROUP
+        ),
+    }
+
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> CliResult {
+    let status = Command::new(program).args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CliError::IoError(std::io::Error::other(format!(
+            "`{} {}` failed - {}",
+            program,
+            args.join(" "),
+            status
+        ))))
+    }
+}
+
+/// Whether any udev rules file mentions Logitech's vendor ID and a Litra product ID.
+fn has_udev_rule() -> bool {
+    UDEV_RULES_DIRECTORIES
+        .iter()
+        .filter_map(|directory| std::fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+        .filter(|entry| Path::new(&entry.file_name()).extension() == Some(OsStr::new("rules")))
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .any(|contents| {
+            contents.contains("046d")
+                && ["c900", "c901", "b901", "c903"]
+                    .iter()
+                    .any(|product_id| contents.contains(product_id))
+        })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum GroupMembership {
+    /// This process is running with the group
+    Active,
+    /// The user is in the group, but this process isn't running with it yet
+    PendingLogin,
+    None,
+}
+
+fn get_group_membership() -> GroupMembership {
+    let Some(group_id) =
+        group_entry().and_then(|entry| entry.split(':').nth(2)?.parse::<u32>().ok())
+    else {
+        return GroupMembership::None;
+    };
+
+    // `/proc/self/status` lists the groups this process is running with
+    let process_groups = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let is_active = process_groups
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .is_some_and(|groups| {
+            groups
+                .split_whitespace()
+                .any(|group| group.parse::<u32>() == Ok(group_id))
+        });
+
+    if is_active {
+        GroupMembership::Active
+    } else if std::env::var("USER").is_ok_and(|user| group_members().contains(&user)) {
+        GroupMembership::PendingLogin
+    } else {
+        GroupMembership::None
+    }
+}
+
+/// Returns the `video` group's line from `/etc/group`, e.g. `video:x:44:alice,bob`.
+fn group_entry() -> Option<String> {
+    std::fs::read_to_string("/etc/group")
+        .ok()?
+        .lines()
+        .find(|line| line.split(':').next() == Some(GROUP))
+        .map(str::to_string)
+}
+
+/// Returns the users listed as members of the `video` group in `/etc/group`.
+fn group_members() -> Vec<String> {
+    group_entry()
+        .and_then(|entry| {
+            entry.split(':').nth(3).map(|members| {
+                members
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+        })
+        .unwrap_or_default()
+}
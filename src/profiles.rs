@@ -0,0 +1,406 @@
+use crate::Cli;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The name used for a profile set up or printed without `--profile`.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Options which choose the profile, so can't be set by one.
+const PROFILE_OPTIONS: &[&str] = &["profile", "profiles-file"];
+
+/// The options for a profile, as they are in the profiles file and as command line arguments.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub options: Map<String, Value>,
+    pub arguments: Vec<String>,
+}
+
+/// An option which was added, removed or changed in a profile, found when reloading the profiles
+/// file with `ctl reload`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionChange {
+    pub name: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl fmt::Display for OptionChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "`{}` changed from {} to {}", self.name, old, new),
+            (None, Some(new)) => write!(f, "`{}` added with {}", self.name, new),
+            (Some(old), None) => write!(f, "`{}` removed (was {})", self.name, old),
+            (None, None) => write!(f, "`{}` unchanged", self.name),
+        }
+    }
+}
+
+/// Compares the options for a profile before and after the profiles file changed, returning the
+/// options which were added, removed or changed, in order of name.
+pub fn diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Vec<OptionChange> {
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter(|name| old.get(*name) != new.get(*name))
+        .map(|name| OptionChange {
+            name: name.clone(),
+            old: old.get(name).cloned(),
+            new: new.get(name).cloned(),
+        })
+        .collect()
+}
+
+/// Returns the default path of the profiles file, in the platform's configuration directory.
+pub fn default_profiles_file_path() -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
+    let config_directory = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    #[cfg(target_os = "macos")]
+    let config_directory = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Library/Application Support"));
+
+    #[cfg(target_os = "windows")]
+    let config_directory = std::env::var_os("APPDATA").map(PathBuf::from);
+
+    config_directory.map(|directory| directory.join("litra-autotoggle").join("profiles.json"))
+}
+
+fn scalar_to_argument(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        _ => Err(format!(
+            "Invalid value for `{}`. It must be a string, a number, `true` or `false`, or a list of strings and numbers.",
+            name
+        )),
+    }
+}
+
+/// Turns a command line argument into a value for a profile, keeping numbers as numbers so the
+/// profiles file is easier to read.
+pub fn argument_to_value(argument: String) -> Value {
+    argument
+        .parse::<u64>()
+        .map(Value::from)
+        .unwrap_or(Value::String(argument))
+}
+
+/// Turns a single option from a profile into command line arguments, e.g. `"brightness": 200`
+/// into `--brightness 200`. `true` adds a flag, `false` and `null` add nothing, and a list repeats
+/// the option for each value.
+fn option_to_arguments(name: &str, value: &Value) -> Result<Vec<String>, String> {
+    let option = format!("--{}", name);
+
+    match value {
+        Value::Bool(true) => Ok(vec![option]),
+        Value::Bool(false) | Value::Null => Ok(Vec::new()),
+        Value::Array(values) => {
+            let mut arguments = Vec::new();
+            for value in values {
+                arguments.push(option.clone());
+                arguments.push(scalar_to_argument(name, value)?);
+            }
+            Ok(arguments)
+        }
+        value => Ok(vec![option, scalar_to_argument(name, value)?]),
+    }
+}
+
+/// Turns the options for a profile into command line arguments.
+pub fn profile_to_arguments(options: &Map<String, Value>) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    for (name, value) in options {
+        // The profile has already been chosen, and can't pull in another one
+        if PROFILE_OPTIONS.contains(&name.as_str()) {
+            return Err(format!("Profiles can't set `{}`", name));
+        }
+        arguments.extend(option_to_arguments(name, value)?);
+    }
+    Ok(arguments)
+}
+
+/// Reads the profiles file at `path`, returning `None` if it doesn't exist.
+fn read_profiles_file(path: &Path) -> Result<Option<Map<String, Value>>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(format!(
+                "Failed to read profiles file {}: {}",
+                path.display(),
+                error
+            ))
+        }
+    };
+
+    serde_json::from_str(&contents).map(Some).map_err(|error| {
+        format!(
+            "Failed to parse profiles file {}: {}",
+            path.display(),
+            error
+        )
+    })
+}
+
+/// Whether the profiles file at `path` has options for `profile`.
+pub fn has_profile(path: &Path, profile: &str) -> Result<bool, String> {
+    Ok(read_profiles_file(path)?.is_some_and(|profiles| profiles.contains_key(profile)))
+}
+
+/// Adds the options for `profile` to the profiles file at `path`, creating it if it doesn't
+/// exist, and replacing any options the profile already had. Other profiles are left as they are.
+pub fn save_profile(path: &Path, profile: &str, options: Map<String, Value>) -> Result<(), String> {
+    let mut profiles = read_profiles_file(path)?.unwrap_or_default();
+    profiles.insert(profile.to_string(), Value::Object(options));
+
+    let contents = serde_json::to_string_pretty(&profiles)
+        .map_err(|error| format!("Failed to serialize profiles: {}", error))?;
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .map_err(|error| format!("Failed to create {}: {}", directory.display(), error))?;
+    }
+    std::fs::write(path, contents + "\n").map_err(|error| {
+        format!(
+            "Failed to write profiles file {}: {}",
+            path.display(),
+            error
+        )
+    })
+}
+
+/// Reads the options for `profile` from the profiles file at `path` (or the default path),
+/// along with the command line arguments they turn into. The file is a JSON object with an
+/// object of options for each profile, named like the command line options without the leading
+/// `--`, e.g. `{"work": {"serial-number": ["ABC123"], "brightness": 200}}`.
+///
+/// Returns `None` if the profile isn't in the file, or there's no file at the default path, since
+/// a profile doesn't need any options of its own.
+pub fn load_profile(path: Option<&Path>, profile: &str) -> Result<Option<Profile>, String> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_profiles_file_path() {
+            Some(path) => (path, false),
+            None => return Ok(None),
+        },
+    };
+
+    let profiles = match read_profiles_file(&path)? {
+        Some(profiles) => profiles,
+        None if required => {
+            return Err(format!(
+                "Failed to read profiles file {}: it doesn't exist",
+                path.display()
+            ))
+        }
+        None => return Ok(None),
+    };
+
+    match profiles.get(profile) {
+        Some(Value::Object(options)) => profile_to_arguments(options)
+            .map(|arguments| {
+                Some(Profile {
+                    options: options.clone(),
+                    arguments,
+                })
+            })
+            .map_err(|error| {
+                format!(
+                    "Invalid profile `{}` in {}: {}",
+                    profile,
+                    path.display(),
+                    error
+                )
+            }),
+        Some(_) => Err(format!(
+            "Invalid profile `{}` in {}: it must be an object of options, e.g. {{\"brightness\": 200}}",
+            profile,
+            path.display()
+        )),
+        None => Ok(None),
+    }
+}
+
+/// The options which can be set by a profile, i.e. the listener's options, apart from the ones
+/// which choose the profile.
+fn profile_arguments(command: &clap::Command) -> impl Iterator<Item = (&str, &clap::Arg)> {
+    command.get_arguments().filter_map(|arg| {
+        let name = arg.get_long()?;
+        let is_option = !arg.is_hide_set()
+            && !PROFILE_OPTIONS.contains(&name)
+            && !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version);
+        is_option.then_some((name, arg))
+    })
+}
+
+/// The options in effect for `args`, from the command line and the `--profile`, as a profile,
+/// for `profile dump`. Options which were left as their defaults aren't included.
+pub fn effective_options(args: &Cli) -> Result<Map<String, Value>, String> {
+    let mut arguments = if args.arguments.is_empty() {
+        std::env::args_os().collect::<Vec<OsString>>()
+    } else {
+        args.arguments.clone()
+    }
+    .into_iter();
+    let program = arguments.next();
+
+    // Parse the arguments like the listener would, so options repeated on the command line and
+    // in the profile are combined the same way
+    let command = Cli::command();
+    let matches = command
+        .clone()
+        .try_get_matches_from(
+            program
+                .into_iter()
+                .chain(
+                    profile_to_arguments(&args.profile_options)?
+                        .into_iter()
+                        .map(Into::into),
+                )
+                .chain(arguments),
+        )
+        .map_err(|error| crate::describe_parse_error(&error))?;
+
+    let mut options = Map::new();
+    for (name, arg) in profile_arguments(&command) {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let values: Vec<Value> = matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|value| argument_to_value(value.to_string_lossy().to_string()))
+            .collect();
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => Value::Bool(true),
+            ArgAction::Append => Value::Array(values),
+            // Options with an optional value, like `--control-socket`, may have been given alone
+            _ => values.into_iter().next().unwrap_or(Value::Bool(true)),
+        };
+        options.insert(name.to_string(), value);
+    }
+
+    Ok(options)
+}
+
+/// An example profile with every option a profile can set, for `profile example`. Options are set
+/// to their defaults, flags to `false` and options without a default to `null`, so the example
+/// can be used as it is, and changed one option at a time.
+pub fn example_options() -> Map<String, Value> {
+    let command = Cli::command();
+    profile_arguments(&command)
+        .map(|(name, arg)| {
+            let mut defaults = arg
+                .get_default_values()
+                .iter()
+                .map(|value| argument_to_value(value.to_string_lossy().to_string()));
+            let value = match arg.get_action() {
+                ArgAction::SetTrue => Value::Bool(false),
+                ArgAction::Append => Value::Array(defaults.collect()),
+                _ => defaults.next().unwrap_or(Value::Null),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn options(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(options) => options,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn diff_finds_added_removed_and_changed_options_in_order() {
+        let old = options(json!({"brightness": 200, "fade": "500ms", "verify-writes": true}));
+        let new = options(json!({"brightness": 250, "verify-writes": true, "temperature": 4000}));
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                OptionChange {
+                    name: "brightness".to_string(),
+                    old: Some(json!(200)),
+                    new: Some(json!(250)),
+                },
+                OptionChange {
+                    name: "fade".to_string(),
+                    old: Some(json!("500ms")),
+                    new: None,
+                },
+                OptionChange {
+                    name: "temperature".to_string(),
+                    old: None,
+                    new: Some(json!(4000)),
+                },
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn options_become_arguments() {
+        assert_eq!(
+            option_to_arguments("brightness", &json!(200)),
+            Ok(vec!["--brightness".to_string(), "200".to_string()])
+        );
+        assert_eq!(
+            option_to_arguments("fade", &json!("500ms")),
+            Ok(vec!["--fade".to_string(), "500ms".to_string()])
+        );
+        assert_eq!(
+            option_to_arguments("verify-writes", &json!(true)),
+            Ok(vec!["--verify-writes".to_string()])
+        );
+        assert_eq!(
+            option_to_arguments("verify-writes", &json!(false)),
+            Ok(vec![])
+        );
+        assert_eq!(option_to_arguments("fade", &Value::Null), Ok(vec![]));
+        assert_eq!(
+            option_to_arguments("serial-number", &json!(["A", 1])),
+            Ok(vec![
+                "--serial-number".to_string(),
+                "A".to_string(),
+                "--serial-number".to_string(),
+                "1".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn options_must_be_scalars_or_lists_of_scalars() {
+        assert!(option_to_arguments("brightness", &json!({"value": 200})).is_err());
+        assert!(option_to_arguments("serial-number", &json!([["A"]])).is_err());
+        assert!(option_to_arguments("serial-number", &json!([true])).is_err());
+    }
+
+    #[test]
+    fn profiles_cant_choose_a_profile() {
+        assert!(profile_to_arguments(&options(json!({"profile": "work"}))).is_err());
+        assert!(profile_to_arguments(&options(json!({"profiles-file": "x.json"}))).is_err());
+    }
+
+    #[test]
+    fn arguments_keep_numbers_as_numbers() {
+        assert_eq!(argument_to_value("200".to_string()), json!(200));
+        assert_eq!(argument_to_value("500ms".to_string()), json!("500ms"));
+        assert_eq!(argument_to_value("-1".to_string()), json!("-1"));
+    }
+}
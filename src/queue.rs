@@ -0,0 +1,148 @@
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+struct QueueCounts {
+    queued: usize,
+    running: usize,
+    completed: u64,
+    dropped: u64,
+}
+
+/// Counts the work waiting in, running from and dropped by a bounded queue, so `ctl status` and
+/// `GET /status` can show when the listener is falling behind. Cloned handles share the same
+/// counts.
+#[derive(Clone)]
+pub struct QueueMetrics {
+    name: &'static str,
+    capacity: usize,
+    counts: Arc<Mutex<QueueCounts>>,
+}
+
+/// A snapshot of the metrics for a queue.
+#[derive(Debug, Serialize)]
+pub struct QueueStatus {
+    name: &'static str,
+    capacity: usize,
+    queued: usize,
+    running: usize,
+    completed: u64,
+    dropped: u64,
+}
+
+impl QueueMetrics {
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        QueueMetrics {
+            name,
+            capacity,
+            counts: Arc::new(Mutex::new(QueueCounts {
+                queued: 0,
+                running: 0,
+                completed: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Records that work has been added to the queue, if there's room for it. Otherwise, records
+    /// that it was dropped, and returns `false`.
+    pub fn try_record_queued(&self) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.queued + counts.running >= self.capacity {
+            counts.dropped += 1;
+            return false;
+        }
+
+        counts.queued += 1;
+        true
+    }
+
+    /// Records that work has been added to the queue, for callers that wait for room rather than
+    /// dropping work.
+    pub fn record_queued(&self) {
+        self.counts.lock().unwrap().queued += 1;
+    }
+
+    pub fn record_started(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.queued = counts.queued.saturating_sub(1);
+        counts.running += 1;
+    }
+
+    pub fn record_completed(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.running = counts.running.saturating_sub(1);
+        counts.completed += 1;
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let counts = self.counts.lock().unwrap();
+
+        QueueStatus {
+            name: self.name,
+            capacity: self.capacity,
+            queued: counts.queued,
+            running: counts.running,
+            completed: counts.completed,
+            dropped: counts.dropped,
+        }
+    }
+}
+
+impl QueueStatus {
+    /// Formats the status for humans, e.g. `hooks (1 queued, 4 running, 20 completed, 0 dropped,
+    /// capacity 16)`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({} queued, {} running, {} completed, {} dropped, capacity {})",
+            self.name, self.queued, self.running, self.completed, self.dropped, self.capacity
+        )
+    }
+}
+
+/// Runs background tasks, at most `concurrency` at a time. At most `capacity` tasks can be
+/// waiting or running at once, and any more are dropped, so a burst of webcam events can't spawn
+/// an unbounded number of tasks. Cloned handles share the same pool.
+#[derive(Clone)]
+pub struct TaskPool {
+    permits: Arc<Semaphore>,
+    metrics: QueueMetrics,
+}
+
+impl TaskPool {
+    pub fn new(name: &'static str, concurrency: usize, capacity: usize) -> Self {
+        TaskPool {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            metrics: QueueMetrics::new(name, capacity),
+        }
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        self.metrics.status()
+    }
+
+    /// Queues `task` to run once fewer than `concurrency` tasks are running. Returns `false` if
+    /// the pool is full, in which case `task` is dropped without running. Must be called from
+    /// within a Tokio runtime.
+    pub fn spawn<F>(&self, task: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if !self.metrics.try_record_queued() {
+            return false;
+        }
+
+        let permits = self.permits.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            // The semaphore is never closed, so acquiring a permit can't fail
+            let _permit = permits.acquire_owned().await;
+            metrics.record_started();
+            task.await;
+            metrics.record_completed();
+        });
+
+        true
+    }
+}
@@ -0,0 +1,184 @@
+use crate::logging::log_error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A time of day, in minutes since midnight.
+type Minutes = u16;
+
+/// A daily period when the Litra isn't turned on, set with `--quiet-hours`, e.g. `22:00-07:00`.
+/// The period can cross midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    start: Minutes,
+    end: Minutes,
+}
+
+fn parse_time(value: &str) -> Option<Minutes> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let hours: Minutes = hours.parse().ok()?;
+    let minutes: Minutes = minutes.parse().ok()?;
+
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+pub fn parse_quiet_hours(value: &str) -> Result<QuietHours, String> {
+    let invalid = || {
+        format!(
+            "Invalid quiet hours `{}`. Use a start and end time in 24-hour format, e.g. `22:00-07:00`.",
+            value
+        )
+    };
+
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let quiet_hours = QuietHours {
+        start: parse_time(start).ok_or_else(invalid)?,
+        end: parse_time(end).ok_or_else(invalid)?,
+    };
+
+    if quiet_hours.start == quiet_hours.end {
+        return Err(format!(
+            "Invalid quiet hours `{}`. The start and end times must be different.",
+            value
+        ));
+    }
+
+    Ok(quiet_hours)
+}
+
+impl QuietHours {
+    fn contains(&self, time: Minutes) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            // The period crosses midnight
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Whether it is currently quiet hours, in local time. If we can't tell the time, we assume
+    /// it isn't, so the Litra still works.
+    pub fn is_now(&self) -> bool {
+        match get_local_time() {
+            Ok(time) => self.contains(time),
+            Err(error) => {
+                log_error!("Failed to check whether it is quiet hours: {}", error);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+// The libc crate doesn't declare `tzset` for Unix, so it is declared here. It takes no arguments
+// and returns nothing on every Unix.
+#[cfg(unix)]
+extern "C" {
+    fn tzset();
+}
+
+/// Gets the local time of day from the C library, since the standard library can't convert to
+/// local time. The time zone is read again each time, so a change to it is picked up without a
+/// restart.
+fn get_local_time() -> std::io::Result<Minutes> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(std::io::Error::other)?
+        .as_secs() as libc::time_t;
+    // SAFETY: all zeroes is a valid value for `struct tm`, which only holds integers and pointers
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    #[cfg(unix)]
+    {
+        // SAFETY: `time` and `tm` are valid for the duration of the call
+        if unsafe {
+            tzset();
+            libc::localtime_r(&time, &mut tm)
+        }
+        .is_null()
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        // SAFETY: `time` and `tm` are valid for the duration of the call
+        let error = unsafe {
+            libc::tzset();
+            libc::localtime_s(&mut tm, &time)
+        };
+        if error != 0 {
+            return Err(std::io::Error::other(format!(
+                "Failed to convert to local time (error {})",
+                error
+            )));
+        }
+    }
+
+    Ok((tm.tm_hour * 60 + tm.tm_min) as Minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_quiet_hours() {
+        let quiet_hours = parse_quiet_hours("22:00-7:30").unwrap();
+        assert_eq!(
+            quiet_hours,
+            QuietHours {
+                start: 22 * 60,
+                end: 7 * 60 + 30
+            }
+        );
+        assert_eq!(quiet_hours.to_string(), "22:00-07:30");
+    }
+
+    #[test]
+    fn rejects_invalid_quiet_hours() {
+        for value in [
+            "",
+            "22:00",
+            "22:00-",
+            "24:00-07:00",
+            "22:60-07:00",
+            "22-07",
+            "09:00-09:00",
+        ] {
+            assert!(parse_quiet_hours(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn contains_times_within_the_same_day() {
+        let quiet_hours = parse_quiet_hours("12:00-13:00").unwrap();
+        assert!(!quiet_hours.contains(11 * 60 + 59));
+        assert!(quiet_hours.contains(12 * 60));
+        assert!(quiet_hours.contains(12 * 60 + 59));
+        assert!(!quiet_hours.contains(13 * 60));
+    }
+
+    #[test]
+    fn contains_times_across_midnight() {
+        let quiet_hours = parse_quiet_hours("22:00-07:00").unwrap();
+        assert!(!quiet_hours.contains(21 * 60 + 59));
+        assert!(quiet_hours.contains(22 * 60));
+        assert!(quiet_hours.contains(0));
+        assert!(quiet_hours.contains(6 * 60 + 59));
+        assert!(!quiet_hours.contains(7 * 60));
+        assert!(!quiet_hours.contains(12 * 60));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::logging::log_info;
+use crate::profiles::{self, OptionChange};
+use crate::{describe_parse_error, Cli, DeviceContext, OpenStrategy};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+
+/// Options from a profile which take effect as soon as the profiles file is reloaded, because
+/// they are only read when devices are toggled or adjusted. Changing anything else, like which
+/// devices are used or how webcam events are detected, needs the listener to be restarted, so
+/// it isn't torn down while you're on a call.
+const LIVE_OPTIONS: &[&str] = &[
+    "brightness",
+    "brightness-percentage",
+    "temperature",
+    "solar-temperature",
+    "brightness-step",
+    "temperature-step",
+    "write-delay",
+    "verify-writes",
+    "fade",
+    "observe",
+    "quiet-hours",
+    "manual-override-grace-period",
+    "explain",
+    "restore-previous-state",
+    "idle-brightness-percentage",
+    "open-strategy",
+    "retry-attempts",
+    "retry-backoff",
+    "on-camera-on",
+    "on-camera-off",
+    "hook-timeout",
+    "hook-min-session-duration",
+    "hook-rate-limit",
+];
+
+/// What the listener needs to reload the profiles file with `ctl reload`.
+#[derive(Debug, Default)]
+pub struct ProfileReloader {
+    /// The command line the listener was started with, without the options from the profile.
+    /// Empty if the listener wasn't started with a `--profile`.
+    arguments: Vec<OsString>,
+    /// The options from the profile which were last loaded
+    options: Map<String, Value>,
+}
+
+impl ProfileReloader {
+    pub fn new(args: &Cli) -> Self {
+        if args.profile.is_none() {
+            return ProfileReloader::default();
+        }
+
+        ProfileReloader {
+            arguments: args.arguments.clone(),
+            options: args.profile_options.clone(),
+        }
+    }
+}
+
+fn describe_changes(changes: &[&OptionChange]) -> String {
+    changes
+        .iter()
+        .map(|change| format!("`{}`", change.name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Puts the options which can change without a restart into effect. Settings changed with
+/// `ctl set` stay changed.
+fn apply_live_options(context: &mut DeviceContext, args: &Cli) {
+    let mut startup_options = args.device_options();
+    // These decide what the listener sets up when it starts, so they only change on a restart
+    startup_options.remember_settings = context.options.remember_settings;
+    startup_options.detect_external_changes = context.options.detect_external_changes;
+
+    context.options = context.overrides.rebase(startup_options);
+    if context.options.open_strategy != OpenStrategy::Persistent {
+        context.persistent_device_handles.clear();
+    }
+
+    context.camera_hooks.on_camera_on = args.on_camera_on.clone();
+    context.camera_hooks.on_camera_off = args.on_camera_off.clone();
+    context.camera_hooks.timeout = args.hook_timeout;
+    context.camera_hooks.min_session_duration = args.hook_min_session_duration;
+    context.camera_hooks.rate_limit = args.hook_rate_limit;
+}
+
+/// Reloads the profiles file for `ctl reload`, logging how the listener's profile changed, and
+/// applies the changes which can take effect straight away, without reopening devices or
+/// restarting the detection of webcam events. Returns a message to send back to the client.
+pub fn reload_profile(context: &mut DeviceContext) -> Result<String, String> {
+    if context.profile_reloader.arguments.is_empty() {
+        return Err(
+            "The listener wasn't started with a `--profile`, so there's no profile to reload"
+                .to_string(),
+        );
+    }
+
+    let args = Cli::try_parse_with_profile_from(context.profile_reloader.arguments.clone())
+        .map_err(|error| {
+            format!(
+                "Failed to reload the profiles file, so nothing was changed: {}",
+                describe_parse_error(&error)
+            )
+        })?;
+
+    let changes = profiles::diff(&context.profile_reloader.options, &args.profile_options);
+    if changes.is_empty() {
+        log_info!("Reloaded the profiles file, and the profile hasn't changed");
+        return Ok("The profile hasn't changed\n".to_string());
+    }
+
+    for change in &changes {
+        log_info!("Profile changed: {}", change);
+    }
+
+    let (live_changes, restart_changes): (Vec<&OptionChange>, Vec<&OptionChange>) = changes
+        .iter()
+        .partition(|change| LIVE_OPTIONS.contains(&change.name.as_str()));
+
+    let mut message = String::new();
+    if !live_changes.is_empty() {
+        apply_live_options(context, &args);
+        log_info!("Applied changes to {}", describe_changes(&live_changes));
+        message.push_str(&format!(
+            "Applied changes to {}\n",
+            describe_changes(&live_changes)
+        ));
+    }
+    if !restart_changes.is_empty() {
+        log_info!(
+            "Changes to {} will be applied when the listener restarts",
+            describe_changes(&restart_changes)
+        );
+        message.push_str(&format!(
+            "Restart the listener to apply changes to {}\n",
+            describe_changes(&restart_changes)
+        ));
+    }
+
+    context.profile_reloader.options = args.profile_options;
+    Ok(message)
+}
@@ -0,0 +1,61 @@
+use crate::duration::format_duration;
+use crate::logging::log_error;
+use std::fmt;
+use std::time::Duration;
+
+/// How many times to try talking to a Litra device, and how long to wait before the first retry,
+/// by default. The wait doubles with each retry.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How to retry operations on Litra devices, set with `--retry-attempts` and `--retry-backoff`.
+/// The listener keeps this with its other device options, so `ctl reload` can change it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        RetryPolicy { attempts, backoff }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(DEFAULT_ATTEMPTS, DEFAULT_BACKOFF)
+    }
+}
+
+/// Runs `operation`, retrying with exponential backoff if it fails, so transient USB errors (e.g.
+/// while a hub wakes up) don't leave the device in the wrong state. `action` describes the
+/// operation for log messages, e.g. `turn on Litra device`, and `policy` says how many times to
+/// try. Blocks the thread while waiting.
+pub fn retry<T, E: fmt::Display>(
+    policy: RetryPolicy,
+    action: &str,
+    mut operation: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut backoff = policy.backoff;
+    let mut attempt = 1;
+
+    loop {
+        match operation() {
+            Err(error) if attempt < policy.attempts => {
+                log_error!(
+                    "Failed to {} (attempt {} of {}), retrying in {}: {}",
+                    action,
+                    attempt,
+                    policy.attempts,
+                    format_duration(backoff),
+                    error
+                );
+                std::thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
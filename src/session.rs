@@ -0,0 +1,143 @@
+use crate::logging::{self, Event};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct SessionState {
+    started_at: Instant,
+    camera_transitions: u64,
+    toggles: u64,
+    toggles_by_trigger_source: BTreeMap<&'static str, u64>,
+    failures: u64,
+    devices: BTreeSet<String>,
+}
+
+/// Counts what the listener has done since it started, so we can print a summary when it stops.
+/// This is useful context to include with logs when reporting a bug. Cloned handles share the
+/// same state.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+/// A summary of what the listener did while it was running.
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    uptime_in_seconds: u64,
+    camera_transitions: u64,
+    toggles: u64,
+    toggles_by_trigger_source: BTreeMap<&'static str, u64>,
+    failures: u64,
+    devices: Vec<String>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                started_at: Instant::now(),
+                camera_transitions: 0,
+                toggles: 0,
+                toggles_by_trigger_source: BTreeMap::new(),
+                failures: 0,
+                devices: BTreeSet::new(),
+            })),
+        }
+    }
+
+    /// Records that the webcam has been turned on or off.
+    pub fn record_camera_transition(&self) {
+        self.state.lock().unwrap().camera_transitions += 1;
+    }
+
+    /// Records that `device` has been found, e.g. `Litra Glow (serial number: ABC)`.
+    pub fn record_device(&self, device: String) {
+        self.state.lock().unwrap().devices.insert(device);
+    }
+
+    /// Records that `device` has been turned on or off, and what caused it, e.g. `camera`.
+    pub fn record_toggle(&self, device: String, trigger_source: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        state.toggles += 1;
+        *state
+            .toggles_by_trigger_source
+            .entry(trigger_source)
+            .or_insert(0) += 1;
+        state.devices.insert(device);
+    }
+
+    /// Records that turning the device on or off failed.
+    pub fn record_failure(&self) {
+        self.state.lock().unwrap().failures += 1;
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        let state = self.state.lock().unwrap();
+
+        SessionSummary {
+            uptime_in_seconds: state.started_at.elapsed().as_secs(),
+            camera_transitions: state.camera_transitions,
+            toggles: state.toggles,
+            toggles_by_trigger_source: state.toggles_by_trigger_source.clone(),
+            failures: state.failures,
+            devices: state.devices.iter().cloned().collect(),
+        }
+    }
+}
+
+impl SessionSummary {
+    /// Formats the summary for humans, e.g. `running for 3600s, 4 camera transitions, 5 toggles
+    /// (camera: 4, manual: 1), 0 failures, devices seen: Litra Glow (serial number: ABC)`.
+    pub fn describe(&self) -> String {
+        let toggles_by_trigger_source = if self.toggles_by_trigger_source.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " ({})",
+                self.toggles_by_trigger_source
+                    .iter()
+                    .map(|(trigger_source, toggles)| format!("{}: {}", trigger_source, toggles))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        };
+
+        format!(
+            "running for {}s, {} camera transitions, {} toggles{}, {} failures, devices seen: {}",
+            self.uptime_in_seconds,
+            self.camera_transitions,
+            self.toggles,
+            toggles_by_trigger_source,
+            self.failures,
+            if self.devices.is_empty() {
+                "none".to_string()
+            } else {
+                self.devices.join(", ")
+            }
+        )
+    }
+
+    /// Prints the summary, followed by the same summary in JSON format so it can be picked out of
+    /// logs by other tools. With `--log-format json`, the summary is logged as a single event.
+    pub fn log(&self) {
+        if !logging::is_text() {
+            logging::log(
+                &Event {
+                    kind: "session_summary",
+                    message: format!("Session summary: {}", self.describe()),
+                    details: serde_json::to_value(self).ok(),
+                    ..Event::default()
+                },
+                false,
+            );
+            return;
+        }
+
+        println!("Session summary: {}", self.describe());
+        match serde_json::to_string(self) {
+            Ok(json) => println!("Session summary (JSON): {}", json),
+            Err(error) => eprintln!("Failed to serialize session summary: {}", error),
+        }
+    }
+}
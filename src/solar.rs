@@ -0,0 +1,218 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where you are, set with `--solar-temperature`, used to work out the position of the sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// The elevation of the sun, in degrees, at which the coolest temperature is used. Below this,
+/// the temperature gets warmer as the sun gets lower, until it reaches the horizon.
+const FULL_DAYLIGHT_ELEVATION: f64 = 45.0;
+
+pub fn parse_location(value: &str) -> Result<Location, String> {
+    let invalid = || {
+        format!(
+            "Invalid location `{}`. Use a latitude and longitude in degrees, e.g. `51.5,-0.13`.",
+            value
+        )
+    };
+
+    let (latitude, longitude) = value.split_once(',').ok_or_else(invalid)?;
+    let latitude: f64 = latitude.trim().parse().map_err(|_| invalid())?;
+    let longitude: f64 = longitude.trim().parse().map_err(|_| invalid())?;
+
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!(
+            "Invalid location `{}`. The latitude must be between -90 and 90, and the longitude between -180 and 180.",
+            value
+        ));
+    }
+
+    Ok(Location {
+        latitude,
+        longitude,
+    })
+}
+
+impl Location {
+    /// Returns the elevation of the sun above the horizon at `time`, in degrees. This uses a
+    /// low-precision formula, which is accurate to within about a degree.
+    fn sun_elevation_at(&self, time: SystemTime) -> f64 {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or_default();
+        // Days since noon UTC on 1 January 2000
+        let days = seconds / 86400.0 - 10957.5;
+
+        let mean_longitude = 280.460 + 0.985_647_4 * days;
+        let mean_anomaly = (357.528 + 0.985_600_3 * days).to_radians();
+        let ecliptic_longitude =
+            (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+                .to_radians();
+        let obliquity = (23.439 - 0.000_000_4 * days).to_radians();
+
+        let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+        let right_ascension = (obliquity.cos() * ecliptic_longitude.sin())
+            .atan2(ecliptic_longitude.cos())
+            .to_degrees();
+
+        let sidereal_time = 280.460_618_37 + 360.985_647_366_29 * days + self.longitude;
+        let hour_angle = (sidereal_time - right_ascension).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+            .asin()
+            .to_degrees()
+    }
+
+    /// Works out the color temperature to use now, between `minimum` and `maximum` in Kelvin:
+    /// warmest while the sun is below the horizon, and coolest once it is high in the sky.
+    /// Returns the temperature, rounded to a multiple of 100 as Litra devices require, and the
+    /// elevation of the sun it was based on.
+    pub fn temperature_in_kelvin(&self, minimum: u16, maximum: u16) -> (u16, f64) {
+        self.temperature_in_kelvin_at(SystemTime::now(), minimum, maximum)
+    }
+
+    fn temperature_in_kelvin_at(&self, time: SystemTime, minimum: u16, maximum: u16) -> (u16, f64) {
+        let elevation = self.sun_elevation_at(time);
+        let daylight = (elevation / FULL_DAYLIGHT_ELEVATION).clamp(0.0, 1.0);
+
+        let temperature = f64::from(minimum) + f64::from(maximum - minimum) * daylight;
+        let temperature = ((temperature / 100.0).round() * 100.0) as u16;
+
+        (temperature.clamp(minimum, maximum), elevation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const GREENWICH: Location = Location {
+        latitude: 51.4769,
+        longitude: 0.0,
+    };
+    const TROMSO: Location = Location {
+        latitude: 69.65,
+        longitude: 18.96,
+    };
+
+    /// The time `seconds` after the Unix epoch.
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    /// The elevation of the sun every 10 minutes over the day starting at `start`.
+    fn elevations_over_day(location: Location, start: u64) -> Vec<f64> {
+        (0..144)
+            .map(|step| location.sun_elevation_at(at(start + step * 600)))
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.5,
+            "expected an elevation of about {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn parses_locations() {
+        assert_eq!(parse_location("51.4769, 0").unwrap(), GREENWICH);
+        for value in ["51.5", "north,south", "91,0", "0,181"] {
+            assert!(parse_location(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn finds_the_sun_at_solar_noon() {
+        // At solar noon, the sun is as high as 90 degrees less the latitude, plus the declination
+        // (23.44 degrees at the June solstice, and -23.44 at the December solstice)
+        for (location, time, elevation) in [
+            // 20 June 2024 12:02 UTC
+            (GREENWICH, 1_718_884_920, 61.96),
+            // 21 December 2024 11:58 UTC
+            (GREENWICH, 1_734_782_280, 15.08),
+            // 21 December 2024 01:54 UTC, in summer in Sydney
+            (
+                Location {
+                    latitude: -33.87,
+                    longitude: 151.21,
+                },
+                1_734_746_040,
+                79.57,
+            ),
+            // 20 March 2024 12:07 UTC, at the equinox the sun is overhead at the equator
+            (
+                Location {
+                    latitude: 0.0,
+                    longitude: 0.0,
+                },
+                1_710_936_420,
+                90.0,
+            ),
+        ] {
+            assert_close(location.sun_elevation_at(at(time)), elevation);
+        }
+    }
+
+    #[test]
+    fn finds_sunrise_and_sunset() {
+        // The sun rose at 03:43 UTC and set at 20:21 UTC in Greenwich on 20 June 2024, when its
+        // center was 0.83 degrees below the horizon, allowing for refraction
+        let sunrise = 1_718_854_980;
+        let sunset = 1_718_914_860;
+        assert_close(GREENWICH.sun_elevation_at(at(sunrise)), -0.83);
+        assert_close(GREENWICH.sun_elevation_at(at(sunset)), -0.83);
+
+        assert!(GREENWICH.sun_elevation_at(at(sunrise - 900)) < -0.83);
+        assert!(GREENWICH.sun_elevation_at(at(sunrise + 900)) > -0.83);
+        assert!(GREENWICH.sun_elevation_at(at(sunset - 900)) > -0.83);
+        assert!(GREENWICH.sun_elevation_at(at(sunset + 900)) < -0.83);
+    }
+
+    #[test]
+    fn handles_polar_day_and_night() {
+        // Tromsø has the midnight sun on 21 June 2024, and the sun doesn't rise on 21 December
+        let polar_day = elevations_over_day(TROMSO, 1_718_928_000);
+        assert!(polar_day.iter().all(|elevation| *elevation > 0.0));
+
+        let polar_night = elevations_over_day(TROMSO, 1_734_739_200);
+        assert!(polar_night.iter().all(|elevation| *elevation < 0.0));
+        assert_eq!(
+            TROMSO
+                .temperature_in_kelvin_at(at(1_734_782_280), 2700, 6500)
+                .0,
+            2700
+        );
+    }
+
+    #[test]
+    fn cools_the_temperature_as_the_sun_rises() {
+        // Noon at the June solstice, high enough for the coolest temperature
+        assert_eq!(
+            GREENWICH
+                .temperature_in_kelvin_at(at(1_718_884_920), 2700, 6500)
+                .0,
+            6500
+        );
+        // Noon at the December solstice, with the sun a third of the way to full daylight
+        let (temperature, elevation) =
+            GREENWICH.temperature_in_kelvin_at(at(1_734_782_280), 2700, 6500);
+        assert_close(elevation, 15.08);
+        assert_eq!(temperature, 4000);
+        // Before sunrise, the warmest temperature is used
+        assert_eq!(
+            GREENWICH
+                .temperature_in_kelvin_at(at(1_718_854_980 - 3600), 2700, 6500)
+                .0,
+            2700
+        );
+    }
+}
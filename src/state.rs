@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// The last brightness and temperature a device had when it was turned off.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DeviceSettings {
+    pub brightness_in_lumen: u16,
+    pub temperature_in_kelvin: u16,
+}
+
+/// The state a device was last confirmed to be in with `--verify-writes`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct VerifiedState {
+    pub on: bool,
+    pub brightness_in_lumen: u16,
+    pub temperature_in_kelvin: u16,
+    /// When the state was read back, in seconds since the Unix epoch
+    pub verified_at: u64,
+}
+
+/// State persisted between runs, keyed by device serial number.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceSettings>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub verified_states: HashMap<String, VerifiedState>,
+}
+
+/// Somewhere [`State`] is persisted between runs. New kinds of store implement this. Its
+/// `Display` says where the state is stored, for log messages.
+///
+/// [`JsonFileStore`] is the only store. The state is a few settings per device, which don't need a
+/// database, and a SQLite store would need `rusqlite`, which builds or links SQLite itself.
+pub trait StateStore: fmt::Display + Send {
+    /// Loads the state, returning empty state if none has been stored yet.
+    fn load(&self) -> std::io::Result<State>;
+
+    /// Stores `state`, replacing whatever was stored before.
+    fn save(&self, state: &State) -> std::io::Result<()>;
+}
+
+/// Stores the state as a JSON file, whose layout is documented in the README so other tools can
+/// read it. This is the default store.
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new(path: PathBuf) -> Self {
+        JsonFileStore { path }
+    }
+}
+
+impl fmt::Display for JsonFileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn load(&self) -> std::io::Result<State> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Saves the state, creating the file's parent directory if needed. The state is written to a
+    /// temporary file first, so the file is never left half-written.
+    fn save(&self, state: &State) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temporary_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary_path, serde_json::to_string_pretty(state)?)?;
+        std::fs::rename(&temporary_path, &self.path)
+    }
+}
+
+/// Returns the default location of the state file for the current platform, or `None` if the
+/// relevant directory can't be determined from the environment. Each `--profile` has its own
+/// state file.
+pub fn default_state_file_path(profile: Option<&str>) -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
+    let state_directory = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+
+    #[cfg(target_os = "macos")]
+    let state_directory = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Library/Application Support"));
+
+    #[cfg(target_os = "windows")]
+    let state_directory = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    let filename = match profile {
+        Some(profile) => format!("state-{}.json", profile),
+        None => "state.json".to_string(),
+    };
+
+    state_directory.map(|directory| directory.join("litra-autotoggle").join(filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_documented_file_layout() {
+        let path = std::env::temp_dir().join(format!(
+            "litra-autotoggle-state-test-{}.json",
+            std::process::id()
+        ));
+        let store = JsonFileStore::new(path.clone());
+
+        let mut state = store.load().unwrap();
+        assert!(state.devices.is_empty());
+
+        state.devices.insert(
+            "ABC123".to_string(),
+            DeviceSettings {
+                brightness_in_lumen: 120,
+                temperature_in_kelvin: 4500,
+            },
+        );
+        store.save(&state).unwrap();
+
+        let contents: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            serde_json::json!({
+                "devices": {
+                    "ABC123": {"brightness_in_lumen": 120, "temperature_in_kelvin": 4500}
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::CliError;
+use std::ffi::OsStr;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// How often systemd expects a watchdog keepalive, in seconds, written to the unit file. A listener
+/// that stops sending keepalives for this long is restarted.
+const WATCHDOG_SECONDS: u64 = 30;
+
+/// Sends `state` (e.g. `READY=1`) to systemd's notification socket. Does nothing if we weren't
+/// started by systemd with `Type=notify`, and logs rather than returns failures, since they
+/// shouldn't stop the listener.
+pub fn notify(state: &str) {
+    let Some(notify_socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(error) = send_notification(&notify_socket, state) {
+        eprintln!("Failed to notify systemd ({}): {}", state, error);
+    }
+}
+
+fn send_notification(notify_socket: &OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    // Sockets starting with `@` are in the abstract namespace
+    let address = match notify_socket.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(notify_socket)?,
+    };
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+/// The socket journald listens on for log entries sent with its native protocol.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Whether journald is running, so logs can be sent to it.
+pub fn is_journal_available() -> bool {
+    Path::new(JOURNAL_SOCKET).exists()
+}
+
+/// Sends a log entry made up of `fields` (e.g. `MESSAGE` and `PRIORITY`) to journald. Field names
+/// must be uppercase letters, digits and underscores.
+pub fn send_to_journal(fields: &[(&str, String)]) -> std::io::Result<()> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // Values containing newlines are sent with their length, rather than on a single line
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    UnixDatagram::unbound()?.send_to(&entry, JOURNAL_SOCKET)?;
+    Ok(())
+}
+
+/// Returns how often to send watchdog keepalives, if systemd's watchdog is enabled for this
+/// process. Keepalives are sent at half the watchdog timeout, as systemd recommends.
+pub fn watchdog_interval() -> Option<Duration> {
+    // `WATCHDOG_PID` is set if the watchdog is meant for a specific process
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let watchdog_usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(watchdog_usec / 2)).filter(|interval| !interval.is_zero())
+}
+
+/// Returns the name of the user unit, with each `--profile` getting its own unit, e.g.
+/// `litra-autotoggle-office`.
+fn unit_name(profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("litra-autotoggle-{}", profile),
+        None => "litra-autotoggle".to_string(),
+    }
+}
+
+/// Returns the path of the user unit file, inside `$XDG_CONFIG_HOME/systemd/user`.
+fn unit_file_path(unit_name: &str) -> Result<PathBuf, CliError> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|directory| directory.join(format!("systemd/user/{}.service", unit_name)))
+        .ok_or_else(|| {
+            CliError::IoError(std::io::Error::other(
+                "Unable to determine where the systemd unit lives, as neither `$XDG_CONFIG_HOME` nor `$HOME` is set",
+            ))
+        })
+}
+
+/// Runs `systemctl --user` with `args`, logging rather than returning failures, since the unit
+/// may already be stopped.
+fn systemctl_user(args: &[&str]) {
+    match Command::new("systemctl").arg("--user").args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("`systemctl --user {}` failed - {}", args.join(" "), status),
+        Err(error) => eprintln!(
+            "Failed to run `systemctl --user {}`: {}",
+            args.join(" "),
+            error
+        ),
+    }
+}
+
+/// Quotes `argument` for an `ExecStart=` line, escaping the characters systemd treats specially.
+fn quote_argument(argument: &str) -> String {
+    let escaped = argument
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+fn unit_file_contents(executable: &Path, args: &[String]) -> String {
+    let exec_start = std::iter::once(executable.to_string_lossy().to_string())
+        .chain(args.iter().cloned())
+        .map(|argument| quote_argument(&argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "[Unit]
+Description=Automatically turn your Logitech Litra device on when your webcam turns on, and off when your webcam turns off
+Documentation=https://github.com/timrogers/litra-autotoggle
+
+[Service]
+Type=notify
+ExecStart={}
+Restart=on-failure
+WatchdogSec={}
+
+[Install]
+WantedBy=default.target
+",
+        exec_start, WATCHDOG_SECONDS
+    )
+}
+
+/// Writes a systemd user unit that runs the listener with `args`, using the path of the current
+/// executable. With a `profile`, the unit is named after it, and runs the listener with
+/// `--profile`. Refuses to replace an existing unit unless `force` is set.
+pub fn install_user_unit(
+    args: &[String],
+    force: bool,
+    profile: Option<&str>,
+) -> Result<(), CliError> {
+    let unit_name = unit_name(profile);
+    let path = unit_file_path(&unit_name)?;
+
+    if path.exists() && !force {
+        return Err(CliError::IoError(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!(
+                "A systemd unit already exists at {}. Use `--force` to replace it.",
+                path.display()
+            ),
+        )));
+    }
+
+    let executable = std::env::current_exe()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let args: Vec<String> = profile
+        .map(|profile| vec!["--profile".to_string(), profile.to_string()])
+        .unwrap_or_default()
+        .into_iter()
+        .chain(args.iter().cloned())
+        .collect();
+    std::fs::write(&path, unit_file_contents(&executable, &args))?;
+
+    println!("Wrote systemd unit to {}", path.display());
+    println!("To start litra-autotoggle now and whenever you log in, run:");
+    println!("  systemctl --user daemon-reload");
+    println!("  systemctl --user enable --now {}", unit_name);
+
+    Ok(())
+}
+
+/// Stops, disables and removes the user unit written by [`install_user_unit`].
+pub fn uninstall_user_unit(profile: Option<&str>) -> Result<(), CliError> {
+    let unit_name = unit_name(profile);
+    let path = unit_file_path(&unit_name)?;
+    if !path.exists() {
+        println!("No systemd unit found at {}", path.display());
+        return Ok(());
+    }
+
+    systemctl_user(&["disable", "--now", &unit_name]);
+    std::fs::remove_file(&path)?;
+    systemctl_user(&["daemon-reload"]);
+    println!("Removed systemd unit from {}", path.display());
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The point every [`CoalescedInterval`] is lined up with, set the first time one is created.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// How late periodic work is allowed to run, set with `--timer-slack`.
+static SLACK: OnceLock<Duration> = OnceLock::new();
+
+/// Sets how late periodic work is allowed to run for the rest of the process, so it can be
+/// batched into fewer wakeups. Work runs on time until this is called.
+pub fn set_slack(slack: Duration) {
+    let _ = SLACK.set(slack);
+}
+
+fn epoch() -> Instant {
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Returns the first multiple of `step` since the epoch which is after `after` (or at it, if
+/// `inclusive` is set).
+fn next_multiple(step: Duration, after: Instant, inclusive: bool) -> Instant {
+    let step = step.as_nanos().max(1);
+    let elapsed = after.saturating_duration_since(epoch()).as_nanos();
+    let steps = if inclusive {
+        elapsed.div_ceil(step)
+    } else {
+        elapsed / step + 1
+    };
+
+    epoch() + Duration::from_nanos(u64::try_from(steps * step).unwrap_or(u64::MAX))
+}
+
+/// Runs periodic work every `period`, lined up with the rest of the listener's periodic work so
+/// an idle listener wakes up as rarely as possible. Ticks fall on multiples of `period` since the
+/// first interval was created, so intervals with the same period, or periods which are multiples
+/// of each other, wake up together. With `--timer-slack`, ticks are also put back to the next
+/// multiple of the slack, so ticks which are close together are handled in a single wakeup.
+///
+/// Ticks missed because the work took too long are skipped.
+pub struct CoalescedInterval {
+    period: Duration,
+    next: Instant,
+}
+
+impl CoalescedInterval {
+    /// Creates an interval whose first tick is about one period from now.
+    pub fn new(period: Duration) -> Self {
+        CoalescedInterval {
+            period,
+            next: next_multiple(period, Instant::now(), false),
+        }
+    }
+
+    /// Creates an interval whose first tick is straight away, like [`tokio::time::interval`].
+    pub fn immediate(period: Duration) -> Self {
+        CoalescedInterval {
+            period,
+            next: Instant::now(),
+        }
+    }
+
+    /// When the next tick will actually happen, after applying the slack.
+    fn deadline(&self) -> Instant {
+        match SLACK.get() {
+            Some(slack) if !slack.is_zero() => next_multiple(*slack, self.next, true),
+            _ => self.next,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.next = next_multiple(self.period, Instant::now(), false);
+    }
+
+    /// Waits until the next tick.
+    pub async fn tick(&mut self) {
+        tokio::time::sleep_until(self.deadline()).await;
+        self.advance();
+    }
+
+    /// Waits until the next tick by blocking the thread, for work done on a dedicated thread.
+    pub fn tick_blocking(&mut self) {
+        let deadline = self.deadline();
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+        self.advance();
+    }
+}
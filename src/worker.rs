@@ -0,0 +1,208 @@
+use crate::logging::{self, Event};
+use crate::queue::{QueueMetrics, QueueStatus};
+use crate::{
+    step_fades, turn_off_selected_devices_and_log, turn_on_selected_devices_and_log, CliError,
+    DeviceContext, DeviceFilter,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+
+/// How many requests can be waiting for the device worker. Once it is full, requests wait for
+/// room.
+const DEVICE_QUEUE_CAPACITY: usize = 32;
+
+/// Turning the device on or off in response to a webcam event.
+struct Toggle {
+    on: bool,
+    camera: Option<String>,
+    app: Option<String>,
+}
+
+/// A request for the device worker to handle.
+enum DeviceRequest {
+    /// Handle the latest webcam event, by turning the device on or off unless automatic toggling
+    /// is paused. The event is kept in [`DeviceWorker::pending_toggle`], so one that arrives
+    /// before this request is handled replaces it, rather than queueing behind it.
+    Toggle,
+    /// Run a function with exclusive access to the device context.
+    Run(Box<dyn FnOnce(&mut DeviceContext) + Send>),
+}
+
+/// A handle for sending requests to the device worker, which owns the [`DeviceContext`] on a
+/// dedicated thread. Talking to a Litra device blocks, sometimes for a long time if the device is
+/// slow to respond, so doing it on the worker means webcam events keep being processed in the
+/// meantime. Requests are handled one at a time, in the order they were sent, so writes to the
+/// device never interleave.
+#[derive(Clone)]
+pub struct DeviceWorker {
+    sender: mpsc::Sender<DeviceRequest>,
+    metrics: QueueMetrics,
+    /// The latest webcam event, if it hasn't been handled yet. While there is one, a
+    /// [`DeviceRequest::Toggle`] is on its way to the worker.
+    pending_toggle: Arc<Mutex<Option<Toggle>>>,
+}
+
+impl DeviceWorker {
+    /// Starts the device worker. Errors from toggling the device in response to webcam events are
+    /// sent to the returned receiver, so the listener can decide whether to exit.
+    pub fn spawn(
+        mut context: DeviceContext,
+        device_filter: DeviceFilter,
+        require_device: bool,
+    ) -> (Self, mpsc::UnboundedReceiver<CliError>) {
+        let (sender, mut receiver) = mpsc::channel(DEVICE_QUEUE_CAPACITY);
+        let metrics = QueueMetrics::new("device", DEVICE_QUEUE_CAPACITY);
+        let worker_metrics = metrics.clone();
+        let (error_sender, error_receiver) = mpsc::unbounded_channel();
+        let pending_toggle: Arc<Mutex<Option<Toggle>>> = Arc::default();
+        let worker_pending_toggle = pending_toggle.clone();
+
+        // Hooks are started from the worker, so it needs to be able to spawn tasks
+        let runtime = tokio::runtime::Handle::current();
+
+        std::thread::spawn(move || {
+            let _runtime = runtime.enter();
+
+            loop {
+                // While devices are fading, only wait for a request until the next step is due
+                step_fades(&mut context, &device_filter);
+                let request = match context.fades.next_step_in(Instant::now()) {
+                    Some(wait) => {
+                        match runtime.block_on(tokio::time::timeout(wait, receiver.recv())) {
+                            Ok(request) => request,
+                            Err(_) => continue,
+                        }
+                    }
+                    None => receiver.blocking_recv(),
+                };
+                // The worker stops once every handle has been dropped.
+                let Some(request) = request else {
+                    break;
+                };
+
+                worker_metrics.record_started();
+                match request {
+                    DeviceRequest::Toggle => {
+                        let Some(Toggle { on, camera, app }) =
+                            worker_pending_toggle.lock().unwrap().take()
+                        else {
+                            worker_metrics.record_completed();
+                            continue;
+                        };
+                        context.session.record_camera_transition();
+
+                        let result = if on {
+                            turn_on_selected_devices_and_log(
+                                &mut context,
+                                &device_filter,
+                                require_device,
+                                camera.as_deref(),
+                                app.as_deref(),
+                            )
+                        } else {
+                            turn_off_selected_devices_and_log(
+                                &mut context,
+                                &device_filter,
+                                require_device,
+                                camera.as_deref(),
+                                app.as_deref(),
+                            )
+                        };
+
+                        logging::log_structured_only(
+                            &Event {
+                                kind: "camera",
+                                message: format!(
+                                    "Handled webcam turning {}",
+                                    if on { "on" } else { "off" }
+                                ),
+                                camera: camera.clone(),
+                                app: app.clone(),
+                                on: Some(on),
+                                outcome: Some(if result.is_ok() {
+                                    "succeeded"
+                                } else {
+                                    "failed"
+                                }),
+                                error: result.as_ref().err().map(|error| error.to_string()),
+                                ..Event::default()
+                            },
+                            result.is_err(),
+                        );
+
+                        if let Err(error) = result {
+                            context.session.record_failure();
+                            let _ = error_sender.send(error);
+                        }
+                    }
+                    DeviceRequest::Run(function) => function(&mut context),
+                }
+                worker_metrics.record_completed();
+            }
+        });
+
+        (
+            DeviceWorker {
+                sender,
+                metrics,
+                pending_toggle,
+            },
+            error_receiver,
+        )
+    }
+
+    /// Asks the worker to turn the device on or off in response to a webcam event, without
+    /// waiting for it to happen. Events are never dropped, but if the worker hasn't got to the
+    /// previous one yet, it is replaced by this one, since only the latest state of the webcam
+    /// matters. Must be called from within the tokio runtime.
+    pub fn toggle(&self, on: bool, camera: Option<&str>, app: Option<&str>) {
+        let previous = self.pending_toggle.lock().unwrap().replace(Toggle {
+            on,
+            camera: camera.map(|camera| camera.to_string()),
+            app: app.map(|app| app.to_string()),
+        });
+        if previous.is_some() {
+            // The request for the previous event will pick up this one instead
+            return;
+        }
+
+        self.metrics.record_queued();
+        match self.sender.try_send(DeviceRequest::Toggle) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(request)) => {
+                // Wait for room in the background, so the event source isn't held up
+                let sender = self.sender.clone();
+                tokio::spawn(async move {
+                    let _ = sender.send(request).await;
+                });
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+
+    pub fn queue_status(&self) -> QueueStatus {
+        self.metrics.status()
+    }
+
+    /// Runs `function` on the worker with exclusive access to the device context, and returns its
+    /// result once it has run.
+    pub async fn run<T, F>(&self, function: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DeviceContext) -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.metrics.record_queued();
+        let _ = self
+            .sender
+            .send(DeviceRequest::Run(Box::new(move |context| {
+                let _ = result_sender.send(function(context));
+            })))
+            .await;
+
+        result_receiver
+            .await
+            .expect("Litra device worker stopped unexpectedly")
+    }
+}